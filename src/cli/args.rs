@@ -0,0 +1,276 @@
+use crate::{cli::password::PasswordSource, logic::output::ConflictPolicy};
+
+pub(crate) static USAGE: &str = "Usage: ShinCrypt <command> [options] <paths>...
+Without arguments the GUI starts.
+
+Commands:
+  encrypt <paths>...   Encrypt files and directories
+  decrypt <paths>...   Decrypt .snc files, directories are searched for them
+  verify <paths>...    Check that .snc files open and are intact, nothing is written
+  info <paths>...      Show the key slots of .snc files, and their header when a key is given
+  rekey <action> <paths>...
+                       Change the keys of .snc files with the current key, only the key slots are rewritten:
+                         add     Add a new password and keyfiles, the current key keeps working
+                         change  Replace the current key with the new one
+                         remove  Remove the current key, another one has to be left
+  benchmark            Encrypt and decrypt 1 GB of random data
+  help                 Show this text
+
+Options:
+  -o, --output <dir>          Write outputs here instead of next to the input
+  -k, --keyfile <path>        Keyfile, can be repeated
+  -r, --recipient <key|path>  Encrypt for a public key or a file containing public keys, can be repeated
+  -i, --identity <path>       Decrypt with an identity file
+  -p, --password-prompt       Ask for the password on the terminal (default for encrypt, decrypt and verify)
+      --password-fd <fd>      Read the password from the first line of a file descriptor, 0 is stdin
+      --password-env <name>   Read the password from an environment variable
+      --password-file <path>  Read the password from the first line of a file
+      --no-password           Use only keyfiles, recipients or an identity
+      --new-keyfile <path>    Keyfile of the new key for rekey, can be repeated
+      --new-password-fd <fd>  Read the new password like --password-fd, it is asked on the terminal otherwise
+      --new-password-env <name>
+                              Read the new password from an environment variable
+      --new-password-file <path>
+                              Read the new password from the first line of a file
+      --new-no-password       The new key uses only its keyfiles
+      --on-conflict <policy>  fail, skip, overwrite or rename an output that exists (default from the settings)
+      --threads <n>           Cipher threads, 0 uses one per CPU core (default from the settings)
+      --verify                Decrypt the encrypted file once more and compare it before it gets its final name
+      --json                  Print the results as JSON on stdout
+  -h, --help                  Show this text
+
+Encryption settings (cipher, key derivation, compression, padding, metadata) come from the GUI settings.
+
+Exit codes:
+  0  Success, skipped outputs included
+  1  Error
+  2  Invalid arguments, settings or no password
+  3  Wrong password, keyfile or identity
+  4  Corrupt file
+  5  Output exists
+";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Command {
+  Encrypt,
+  Decrypt,
+  Verify,
+  Info,
+  Rekey,
+  Benchmark,
+  #[default]
+  Help,
+}
+
+impl Command {
+  pub fn name(&self) -> &'static str {
+    match self {
+      Self::Encrypt => "encrypt",
+      Self::Decrypt => "decrypt",
+      Self::Verify => "verify",
+      Self::Info => "info",
+      Self::Rekey => "rekey",
+      Self::Benchmark => "benchmark",
+      Self::Help => "help",
+    }
+  }
+
+  fn from_name(name: &str) -> Option<Self> { [Self::Encrypt, Self::Decrypt, Self::Verify, Self::Info, Self::Rekey, Self::Benchmark, Self::Help].into_iter().find(|v| v.name() == name) }
+}
+
+/// What rekey does with the key slots
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RekeyAction {
+  Add,
+  Change,
+  Remove,
+}
+
+impl RekeyAction {
+  pub fn name(&self) -> &'static str {
+    match self {
+      Self::Add => "add",
+      Self::Change => "change",
+      Self::Remove => "remove",
+    }
+  }
+
+  fn from_name(name: &str) -> Option<Self> { [Self::Add, Self::Change, Self::Remove].into_iter().find(|v| v.name() == name) }
+}
+
+/// A parsed command line
+#[derive(Debug, Default)]
+pub struct Args {
+  pub command: Command,
+  pub rekey: Option<RekeyAction>,
+  pub paths: Vec<std::path::PathBuf>,
+  pub output: Option<std::path::PathBuf>,
+  pub keyfiles: Vec<std::path::PathBuf>,
+  pub recipients: Vec<std::path::PathBuf>,
+  pub identity: Option<std::path::PathBuf>,
+  pub password: Option<PasswordSource>, // Given explicitly, cli_ui decides when the terminal is asked instead
+  pub no_password: bool,
+  pub new_keyfiles: Vec<std::path::PathBuf>,
+  pub new_password: Option<PasswordSource>, // Of rekey add and change, asked on the terminal when not given
+  pub new_no_password: bool,
+  pub conflict: Option<ConflictPolicy>,
+  pub threads: Option<usize>,
+  pub verify: bool,
+  pub json: bool,
+}
+
+impl Args {
+  /// Parses the arguments after the program name. The command comes first, options and paths can be mixed after it and `--` ends the options
+  pub fn parse(args: impl IntoIterator<Item = std::ffi::OsString>) -> Result<Self, String> {
+    let mut parsed = Self::default();
+    let mut command = None;
+    let mut options_done = false;
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+      // Paths don't have to be UTF-8, options do
+      let option = match arg.to_str() {
+        Some(v) if !options_done && v.starts_with('-') && v != "-" => v,
+        _ if command.is_none() => {
+          command = Some(arg.to_str().and_then(Command::from_name).ok_or_else(|| format!("Unknown command {:?}, see ShinCrypt help", arg))?);
+          continue;
+        }
+        // rekey takes its action before the paths
+        _ if command == Some(Command::Rekey) && parsed.rekey.is_none() => {
+          parsed.rekey = Some(arg.to_str().and_then(RekeyAction::from_name).ok_or_else(|| format!("Unknown rekey action {:?}, use add, change or remove", arg))?);
+          continue;
+        }
+        _ => {
+          parsed.paths.push(arg.into());
+          continue;
+        }
+      };
+
+      if option == "--" {
+        options_done = true;
+        continue;
+      }
+
+      // "--output=dir" and "--output dir" are the same
+      let (name, inline) = match option.split_once('=') {
+        Some((name, value)) if name.starts_with("--") => (name, Some(std::ffi::OsString::from(value))),
+        _ => (option, None),
+      };
+      let mut value = |what: &str| inline.clone().or_else(|| args.next()).ok_or_else(|| format!("{} needs {}", name, what));
+
+      match name {
+        "-o" | "--output" => parsed.output = Some(value("a directory")?.into()),
+        "-k" | "--keyfile" => parsed.keyfiles.push(value("a path")?.into()),
+        "-r" | "--recipient" => parsed.recipients.push(value("a public key or a path")?.into()),
+        "-i" | "--identity" => parsed.identity = Some(value("a path")?.into()),
+        "-p" | "--password-prompt" => set_password(&mut parsed.password, PasswordSource::Prompt)?,
+        "--password-fd" | "--new-password-fd" => {
+          let fd = value("a file descriptor")?;
+          let source = PasswordSource::Fd(fd.to_str().and_then(|v| v.parse().ok()).ok_or_else(|| format!("Invalid file descriptor {:?}", fd))?);
+          set_password(if name == "--password-fd" { &mut parsed.password } else { &mut parsed.new_password }, source)?
+        }
+        "--password-env" | "--new-password-env" => {
+          let var = value("a variable name")?;
+          let source = PasswordSource::Env(var.into_string().map_err(|v| format!("Invalid variable name {:?}", v))?);
+          set_password(if name == "--password-env" { &mut parsed.password } else { &mut parsed.new_password }, source)?
+        }
+        "--password-file" => set_password(&mut parsed.password, PasswordSource::File(value("a path")?.into()))?,
+        "--new-password-file" => set_password(&mut parsed.new_password, PasswordSource::File(value("a path")?.into()))?,
+        "--no-password" => parsed.no_password = true,
+        "--new-keyfile" => parsed.new_keyfiles.push(value("a path")?.into()),
+        "--new-no-password" => parsed.new_no_password = true,
+        "--on-conflict" => {
+          let policy = value("fail, skip, overwrite or rename")?;
+          parsed.conflict = Some(match policy.to_str() {
+            Some("fail") => ConflictPolicy::Ask,
+            Some("skip") => ConflictPolicy::Skip,
+            Some("overwrite") => ConflictPolicy::Overwrite,
+            Some("rename") => ConflictPolicy::Rename,
+            _ => return Err(format!("Invalid conflict policy {:?}, use fail, skip, overwrite or rename", policy)),
+          });
+        }
+        "--threads" => {
+          let threads = value("a number")?;
+          parsed.threads = Some(threads.to_str().and_then(|v| v.parse().ok()).ok_or_else(|| format!("Invalid number of threads {:?}", threads))?);
+        }
+        "--verify" => parsed.verify = true,
+        "--json" => parsed.json = true,
+        "-h" | "--help" => command = Some(Command::Help),
+        _ => return Err(format!("Unknown option {}, see ShinCrypt help", name)),
+      }
+    }
+
+    parsed.command = command.unwrap_or_default();
+    if parsed.command == Command::Rekey && parsed.rekey.is_none() {
+      return Err("rekey needs an action, add, change or remove".to_string());
+    }
+    Ok(parsed)
+  }
+}
+
+// The current and the new password of rekey each take one source
+fn set_password(password: &mut Option<PasswordSource>, source: PasswordSource) -> Result<(), String> {
+  if password.is_some() {
+    return Err("Only one password source can be given".to_string());
+  }
+  *password = Some(source);
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn parse(args: &[&str]) -> Result<Args, String> { Args::parse(args.iter().map(std::ffi::OsString::from)) }
+
+  #[test]
+  fn options_and_paths_mix_after_the_command() {
+    let args = parse(&["encrypt", "a.txt", "-o", "out", "--keyfile=k1", "-k", "k2", "b", "--on-conflict", "rename", "--threads", "4", "--verify", "--json"]).unwrap();
+    assert_eq!(args.command, Command::Encrypt);
+    assert_eq!(args.paths, [std::path::PathBuf::from("a.txt"), "b".into()]);
+    assert_eq!(args.output, Some("out".into()));
+    assert_eq!(args.keyfiles, [std::path::PathBuf::from("k1"), "k2".into()]);
+    assert_eq!((args.conflict, args.threads, args.verify, args.json), (Some(ConflictPolicy::Rename), Some(4), true, true));
+
+    // After "--" everything is a path
+    let args = parse(&["decrypt", "--", "-o", "--json"]).unwrap();
+    assert_eq!(args.paths, [std::path::PathBuf::from("-o"), "--json".into()]);
+    assert!(!args.json);
+  }
+
+  #[test]
+  fn no_arguments_and_help() {
+    assert_eq!(parse(&[]).unwrap().command, Command::Help);
+    assert_eq!(parse(&["encrypt", "--help"]).unwrap().command, Command::Help);
+    assert_eq!(parse(&["-h"]).unwrap().command, Command::Help);
+  }
+
+  #[test]
+  fn password_sources() {
+    assert_eq!(parse(&["verify", "--password-env", "PW"]).unwrap().password, Some(PasswordSource::Env("PW".to_string())));
+    assert_eq!(parse(&["verify", "--password-fd=0"]).unwrap().password, Some(PasswordSource::Fd(0)));
+    assert_eq!(parse(&["verify", "-p"]).unwrap().password, Some(PasswordSource::Prompt));
+    assert!(parse(&["verify", "-p", "--password-file", "pw.txt"]).is_err());
+    assert!(parse(&["verify", "--password-fd", "x"]).is_err());
+  }
+
+  #[test]
+  fn rekey_takes_an_action_and_a_new_key() {
+    let args = parse(&["rekey", "change", "a.snc", "--password-file", "old.txt", "--new-password-env", "NEW", "--new-keyfile", "k"]).unwrap();
+    assert_eq!((args.command, args.rekey), (Command::Rekey, Some(RekeyAction::Change)));
+    assert_eq!(args.paths, [std::path::PathBuf::from("a.snc")]);
+    assert_eq!(args.password, Some(PasswordSource::File("old.txt".into())));
+    assert_eq!(args.new_password, Some(PasswordSource::Env("NEW".to_string())));
+    assert_eq!(args.new_keyfiles, [std::path::PathBuf::from("k")]);
+
+    assert!(parse(&["rekey"]).is_err());
+    assert!(parse(&["rekey", "rotate", "a.snc"]).is_err());
+  }
+
+  #[test]
+  fn invalid_arguments() {
+    for args in [&["shred", "a"][..], &["encrypt", "--bogus"], &["encrypt", "-o"], &["encrypt", "--on-conflict", "maybe"], &["encrypt", "--threads", "-1"]] {
+      assert!(parse(args).is_err(), "{:?}", args);
+    }
+  }
+}
@@ -0,0 +1,411 @@
+use crate::{cli::{args::{Args, Command, RekeyAction, USAGE}, password::PasswordSource}, gtk::settings_win::AppSettings, logic::{encryption::{CompressionAlgorithm, KeySlot, ShinCrypt}, error::ShinError, global::Global, identity::Recipient, progress::ProgressEvent, secret::Secret}};
+use std::{io::IsTerminal, path::PathBuf, process::ExitCode};
+
+static EXIT_ERROR: u8 = 1;
+static EXIT_USAGE: u8 = 2;
+static EXIT_WRONG_KEY: u8 = 3;
+static EXIT_CORRUPT: u8 = 4;
+static EXIT_EXISTS: u8 = 5;
+
+// One input of a command and what came of it
+struct Report {
+  path: PathBuf,
+  output: Option<PathBuf>,
+  fields: Vec<Field>, // What info found out, also when the key didn't open the file
+  result: Result<(), ShinError>,
+}
+
+impl Report {
+  fn new(path: impl Into<PathBuf>, result: Result<(), ShinError>) -> Self { Self { path: path.into(), output: None, fields: Vec::new(), result } }
+
+  fn status(&self) -> (&'static str, u8) {
+    match &self.result {
+      Ok(_) => ("ok", 0),
+      Err(e) => error_status(e),
+    }
+  }
+}
+
+// Status in the JSON output and the exit code it stands for. Nothing was done to a skipped file, which is what the policy asked for
+fn error_status(error: &ShinError) -> (&'static str, u8) {
+  match error {
+    ShinError::Skipped(_) | ShinError::SkippedEntries(_) => ("skipped", 0),
+    ShinError::WrongPassword | ShinError::WrongIdentity | ShinError::KeyRequired(_) => ("wrong_password", EXIT_WRONG_KEY),
+    ShinError::CorruptHeader(_) | ShinError::CorruptData(_) => ("corrupt", EXIT_CORRUPT),
+    ShinError::OutputExists(_) => ("exists", EXIT_EXISTS),
+    ShinError::InvalidParams(_) => ("invalid_settings", EXIT_USAGE),
+    _ => ("failed", EXIT_ERROR),
+  }
+}
+
+// A line of info, `value` goes into the JSON output and `text` is shown otherwise
+struct Field {
+  key: &'static str,
+  value: serde_json::Value,
+  text: String,
+}
+
+impl Field {
+  fn new(key: &'static str, value: impl serde::Serialize, text: impl std::fmt::Display) -> Self { Self { key, value: serde_json::to_value(value).unwrap_or_default(), text: text.to_string() } }
+}
+
+/// Runs the command line, `args` are the arguments after the program name. Results go to stdout, progress and errors to stderr
+pub fn cli(args: Vec<std::ffi::OsString>) -> ExitCode {
+  #[cfg(windows)]
+  attach_console();
+
+  let json = args.iter().any(|v| v == "--json");
+  let args = match Args::parse(args) {
+    Ok(v) => v,
+    Err(e) => return fail(json, &e, EXIT_USAGE),
+  };
+
+  match args.command {
+    Command::Help => {
+      print!("{}", USAGE);
+      ExitCode::SUCCESS
+    }
+    Command::Benchmark => benchmark(&args),
+    _ if args.paths.is_empty() => fail(args.json, &format!("{} needs at least one path, see ShinCrypt help", args.command.name()), EXIT_USAGE),
+    _ => run(&args),
+  }
+}
+
+// Release builds on Windows are GUI programs without a console of their own, the output goes to the one they were started from
+#[cfg(windows)]
+fn attach_console() { unsafe { winapi::um::wincon::AttachConsole(winapi::um::wincon::ATTACH_PARENT_PROCESS) }; }
+
+fn fail(json: bool, error: &str, code: u8) -> ExitCode {
+  if json {
+    println!("{}", serde_json::json!({ "error": error, "exit_code": code }));
+  } else {
+    eprintln!("{}", error);
+  }
+  ExitCode::from(code)
+}
+
+fn run(args: &Args) -> ExitCode {
+  let settings = AppSettings::import().unwrap_or_default();
+
+  let recipients = match Recipient::from_list(&args.recipients) {
+    Ok(v) => v,
+    Err(e) => return fail(args.json, &e, EXIT_USAGE),
+  };
+
+  let password = match password(args) {
+    Ok(v) => v,
+    Err(e) => return fail(args.json, &e, EXIT_USAGE),
+  };
+
+  if args.command == Command::Encrypt && password.is_empty() && args.keyfiles.is_empty() && recipients.is_empty() {
+    return fail(args.json, "A password, a keyfile or a recipient is required", EXIT_USAGE);
+  }
+
+  let new_password = match new_password(args) {
+    Ok(v) => v,
+    Err(e) => return fail(args.json, &e, EXIT_USAGE),
+  };
+
+  if let Some(v) = &args.output
+    && let Err(e) = std::fs::create_dir_all(v)
+  {
+    return fail(args.json, &format!("Failed to create directory {:?}: {}", v, e), EXIT_ERROR);
+  }
+
+  // Only worth drawing when someone watches, it would clutter a log
+  let show_progress = !args.json && std::io::stderr().is_terminal();
+  let with_key = args.password.is_some() || !args.keyfiles.is_empty() || args.identity.is_some();
+
+  let mut reports = Vec::new();
+  let mut finish = |report: Report| {
+    if !args.json {
+      print_report(args, &report);
+    }
+    reports.push(report);
+  };
+
+  for path in &args.paths {
+    // Directories are searched for .snc files, except when they are the ones to encrypt
+    let inputs = match args.command {
+      Command::Encrypt => vec![path.clone()],
+      _ => match ShinCrypt::encrypted_files(path) {
+        Ok(v) if v.is_empty() => {
+          finish(Report::new(path, Err(format!("No .snc files in {:?}", path).into())));
+          continue;
+        }
+        Ok(v) => v,
+        Err(e) => {
+          finish(Report::new(path, Err(e)));
+          continue;
+        }
+      },
+    };
+
+    for input in inputs {
+      finish(with_progress(show_progress, |progress| {
+        let shincrypt = job(args, &settings, &input, &password, &recipients, progress);
+        run_job(args, &shincrypt, with_key, &new_password)
+      }));
+    }
+  }
+
+  // The first failure decides, a script can tell what went wrong without parsing anything
+  let code = reports.iter().map(|v| v.status().1).find(|v| *v != 0).unwrap_or(0);
+  if args.json {
+    print_json(args, &reports, code);
+  }
+  ExitCode::from(code)
+}
+
+// The terminal is asked unless the password comes from elsewhere or another key makes it optional. info only reads the headers when a key is given
+fn password(args: &Args) -> Result<Secret<String>, String> {
+  let other_key = match args.command {
+    Command::Encrypt => !args.recipients.is_empty(),
+    _ => args.identity.is_some(),
+  };
+
+  let source = match &args.password {
+    Some(_) if args.no_password => return Err("--no-password can't be combined with a password source".to_string()),
+    Some(v) => v,
+    None if args.no_password || other_key || args.command == Command::Info => return Ok(Secret::from("")),
+    None => &PasswordSource::Prompt,
+  };
+  let what = if args.command == Command::Rekey { "Current password" } else { "Password" };
+  source.read(what, args.command == Command::Encrypt)
+}
+
+// The new key of rekey add and change, it always gets confirmed on the terminal. remove takes none
+fn new_password(args: &Args) -> Result<Secret<String>, String> {
+  let new_key = args.new_password.is_some() || args.new_no_password || !args.new_keyfiles.is_empty();
+  let password = match (args.rekey, &args.new_password) {
+    (Some(RekeyAction::Remove), _) if new_key => return Err("rekey remove only takes the key to remove, not a new one".to_string()),
+    (Some(RekeyAction::Add | RekeyAction::Change), Some(_)) if args.new_no_password => return Err("--new-no-password can't be combined with a new password source".to_string()),
+    (Some(RekeyAction::Add | RekeyAction::Change), Some(v)) => v.read("New password", true)?,
+    (Some(RekeyAction::Add | RekeyAction::Change), None) if args.new_no_password => Secret::from(""),
+    (Some(RekeyAction::Add | RekeyAction::Change), None) => PasswordSource::Prompt.read("New password", true)?,
+    _ => return Ok(Secret::from("")),
+  };
+
+  if password.is_empty() && args.new_keyfiles.is_empty() {
+    return Err("The new key needs a password or a keyfile".to_string());
+  }
+  Ok(password)
+}
+
+// Settings the GUI would use for this input, the command line options go over them. The output goes next to the input unless --output is given
+fn job(args: &Args, settings: &AppSettings, input: &std::path::Path, password: &Secret<String>, recipients: &[Recipient], progress: Option<crossbeam::channel::Sender<ProgressEvent>>) -> ShinCrypt {
+  let output = match &args.output {
+    Some(v) => v.clone(),
+    None => input.parent().filter(|v| !v.as_os_str().is_empty()).unwrap_or(std::path::Path::new(".")).to_path_buf(),
+  };
+
+  let mut shincrypt = ShinCrypt::new(input, output, password.as_str(), progress);
+  shincrypt.set_keyfiles(args.keyfiles.clone());
+  shincrypt.set_recipients(recipients.to_vec());
+  if let Some(v) = &args.identity {
+    shincrypt.set_identity(v);
+  }
+  shincrypt.set_metadata_policy(settings.metadata);
+  shincrypt.set_kdf_params(settings.kdf);
+  shincrypt.set_encryption(settings.encryption);
+  shincrypt.set_compression(settings.compression);
+  shincrypt.set_padding(settings.padding);
+  shincrypt.set_obfuscate_name(settings.obfuscate_names);
+  shincrypt.set_threads(args.threads.unwrap_or(settings.threads));
+  // There is nobody to ask, Ask fails with OutputExists
+  shincrypt.set_conflict_policy(args.conflict.unwrap_or(settings.conflict));
+  shincrypt.set_verify(args.verify);
+  shincrypt
+}
+
+fn run_job(args: &Args, shincrypt: &ShinCrypt, with_key: bool, new_password: &Secret<String>) -> Report {
+  let path = shincrypt.input_path();
+  match args.command {
+    Command::Encrypt => match shincrypt.encrypt_file() {
+      Ok(v) => Report { output: Some(v), ..Report::new(path, Ok(())) },
+      Err(e) => Report::new(path, Err(e)),
+    },
+    Command::Decrypt => Report::new(path, shincrypt.decrypt_file()),
+    Command::Verify => Report::new(path, shincrypt.verify_file()),
+    Command::Info => {
+      let mut fields = Vec::new();
+      let result = info(shincrypt, with_key, &mut fields);
+      Report { fields, ..Report::new(path, result) }
+    }
+    Command::Rekey => match args.rekey {
+      Some(RekeyAction::Add) => Report::new(path, shincrypt.add_password(new_password.as_str(), &args.new_keyfiles)),
+      Some(RekeyAction::Change) => Report::new(path, shincrypt.change_password(new_password.as_str(), &args.new_keyfiles)),
+      Some(RekeyAction::Remove) => Report::new(path, shincrypt.remove_key()),
+      None => Report::new(path, Err("rekey needs an action".into())),
+    },
+    Command::Benchmark | Command::Help => Report::new(path, Err("Not a command for files".into())),
+  }
+}
+
+// The key header can be read by anyone, the file header only with a key
+fn info(shincrypt: &ShinCrypt, with_key: bool, fields: &mut Vec<Field>) -> Result<(), ShinError> {
+  let path = shincrypt.input_path();
+  let size = match std::fs::metadata(path) {
+    Ok(v) => v.len(),
+    Err(e) => return Err(ShinError::io("Failed to read", path, e)),
+  };
+  fields.push(Field::new("size", size, Global::format_size(size)));
+
+  match shincrypt.key_header()? {
+    Some(key_h) => {
+      let used = key_h.slots.iter().filter(|v| !matches!(v, KeySlot::Empty)).collect::<Vec<_>>();
+      let slots = used
+        .iter()
+        .map(|v| match v {
+          KeySlot::Password { kdf, .. } => serde_json::json!({ "type": v.label(), "kdf": kdf }),
+          _ => serde_json::json!({ "type": v.label() }),
+        })
+        .collect::<Vec<_>>();
+      fields.push(Field::new("version", key_h.version, key_h.version));
+      fields.push(Field::new("key_slots", slots, format!("{} of {} used: {}", used.len(), key_h.slots.len(), used.iter().map(|v| v.label()).collect::<Vec<_>>().join(", "))));
+      fields.push(Field::new("free_slots", key_h.slots.len() - used.len(), key_h.slots.len() - used.len()));
+    }
+    // v1 files start with the salt, without a key anything else looks like one too
+    None => fields.push(Field::new("version", 1, "1 (no key slots) or not a ShinCrypt file")),
+  }
+
+  if !with_key {
+    return Ok(());
+  }
+
+  let (file_h, _) = shincrypt.open_reader()?;
+  fields.push(Field::new("type", if file_h.packed { "directory" } else { "file" }, if file_h.packed { "directory" } else { "file" }));
+  fields.push(Field::new("encryption", file_h.encryption, format!("{:?}", file_h.encryption)));
+  let compression = match file_h.compression.algorithm {
+    CompressionAlgorithm::None => format!("{:?}", file_h.compression.algorithm),
+    _ => format!("{:?} (level {})", file_h.compression.algorithm, file_h.compression.level),
+  };
+  fields.push(Field::new("compression", file_h.compression, compression));
+  fields.push(Field::new("padding", file_h.padding, format!("{:?}", file_h.padding)));
+  fields.push(Field::new("metadata", file_h.metadata, format!("{:?}", file_h.metadata)));
+  if !file_h.name.is_empty() {
+    fields.push(Field::new("original_name", &file_h.name, &file_h.name));
+  }
+  if !file_h.path.as_os_str().is_empty() {
+    fields.push(Field::new("original_path", file_h.path.to_string_lossy(), file_h.path.display()));
+  }
+  if let Some(v) = file_h.data_len {
+    fields.push(Field::new("data_size", v, Global::format_size(v)));
+  }
+
+  Ok(())
+}
+
+// Runs `job` on a worker thread and keeps redrawing its progress on one line of stderr meanwhile
+fn with_progress<T: Send>(show: bool, job: impl FnOnce(Option<crossbeam::channel::Sender<ProgressEvent>>) -> T + Send) -> T {
+  if !show {
+    return job(None);
+  }
+
+  let (sender, receiver) = crossbeam::channel::unbounded::<ProgressEvent>();
+  std::thread::scope(|s| {
+    // The channel closes when the job and its sender are gone
+    let worker = s.spawn(move || job(Some(sender)));
+    let mut width = 0;
+    for event in receiver.iter() {
+      let text = event.to_string();
+      eprint!("\r{:<1$}", text, width);
+      width = text.chars().count();
+    }
+    eprint!("\r{:1$}\r", "", width);
+
+    worker.join().unwrap_or_else(|e| std::panic::resume_unwind(e))
+  })
+}
+
+fn print_report(args: &Args, report: &Report) {
+  let path = report.path.display();
+  if !report.fields.is_empty() {
+    println!("{}", path);
+    for field in &report.fields {
+      println!("  {}: {}", field.key.replace('_', " "), field.text);
+    }
+  }
+
+  match &report.result {
+    Ok(_) => match args.command {
+      Command::Encrypt => println!("{}: encrypted as {}", path, report.output.as_deref().unwrap_or(&report.path).display()),
+      Command::Decrypt => println!("{}: decrypted", path),
+      Command::Verify => println!("{}: OK", path),
+      Command::Rekey => match args.rekey {
+        Some(RekeyAction::Add) => println!("{}: key added", path),
+        Some(RekeyAction::Change) => println!("{}: key changed", path),
+        Some(RekeyAction::Remove) => println!("{}: key removed", path),
+        None => {}
+      },
+      _ => {}
+    },
+    Err(e @ (ShinError::Skipped(_) | ShinError::SkippedEntries(_))) => println!("{}: {}", path, e),
+    Err(e) => {
+      eprintln!("{}: {}", path, e);
+      if let Some(v) = e.remediation() {
+        eprintln!("  {}", v);
+      }
+    }
+  }
+}
+
+fn print_json(args: &Args, reports: &[Report], code: u8) {
+  let results = reports
+    .iter()
+    .map(|report| {
+      let mut result = serde_json::json!({ "path": report.path.to_string_lossy(), "status": report.status().0 });
+      if let Some(v) = &report.output {
+        result["output"] = v.to_string_lossy().into();
+      }
+      if let Err(e) = &report.result {
+        result["error"] = e.to_string().into();
+      }
+      for field in &report.fields {
+        result[field.key] = field.value.clone();
+      }
+      result
+    })
+    .collect::<Vec<_>>();
+
+  let mut output = serde_json::json!({ "command": args.command.name(), "results": results, "exit_code": code });
+  if let Some(v) = args.rekey {
+    output["action"] = v.name().into();
+  }
+  println!("{}", output);
+}
+
+fn benchmark(args: &Args) -> ExitCode {
+  let settings = AppSettings::import().unwrap_or_default();
+  let threads = ShinCrypt::resolve_threads(args.threads.unwrap_or(settings.threads));
+  if !args.json {
+    eprintln!("Encrypting and decrypting 1 GB with {} threads", threads);
+  }
+
+  let (encrypt_time, decrypt_time) = match ShinCrypt::benchmark(threads) {
+    Ok(v) => v,
+    Err(e) => {
+      // Exit code and status like a failed file, a benchmark that failed has no times to show
+      let (status, code) = error_status(&e);
+      let code = if code == 0 { EXIT_ERROR } else { code };
+      if args.json {
+        println!("{}", serde_json::json!({ "command": Command::Benchmark.name(), "status": status, "error": e.to_string(), "exit_code": code }));
+      } else {
+        eprintln!("{}", e);
+        if let Some(v) = e.remediation() {
+          eprintln!("  {}", v);
+        }
+      }
+      return ExitCode::from(code);
+    }
+  };
+  let (encrypt_speed, decrypt_speed) = (Global::calculate_speed(1.0, encrypt_time), Global::calculate_speed(1.0, decrypt_time));
+
+  if args.json {
+    println!("{}", serde_json::json!({ "command": Command::Benchmark.name(), "threads": threads, "encrypt_seconds": encrypt_time.as_secs_f64(), "decrypt_seconds": decrypt_time.as_secs_f64(), "encrypt_mb_s": encrypt_speed, "decrypt_mb_s": decrypt_speed, "exit_code": 0 }));
+  } else {
+    println!("Encrypt: {} ({:.2} MB/s)", Global::format_duration(encrypt_time), encrypt_speed);
+    println!("Decrypt: {} ({:.2} MB/s)", Global::format_duration(decrypt_time), decrypt_speed);
+  }
+  ExitCode::SUCCESS
+}
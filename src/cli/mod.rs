@@ -0,0 +1,3 @@
+pub(crate) mod args;
+pub(crate) mod cli_ui;
+pub(crate) mod password;
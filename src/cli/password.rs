@@ -0,0 +1,182 @@
+use crate::logic::secret::Secret;
+use std::io::{Read, Write};
+use zeroize::Zeroizing;
+
+static MAX_PASSWORD: usize = 1024; // Bytes, read into a fixed buffer so no copy of the password is left behind by a growing one
+
+/// Where the command line reads the password from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PasswordSource {
+  /// Asked on the terminal without echo, even when stdin is redirected
+  Prompt,
+  /// First line of an inherited file descriptor, 0 is stdin. Unix only
+  Fd(i32),
+  /// An environment variable, visible to other processes of the same user on some systems
+  Env(String),
+  /// First line of a file
+  File(std::path::PathBuf),
+}
+
+impl PasswordSource {
+  /// Reads the password, `what` names it on the prompt and `confirm` asks twice when prompting so a typo doesn't lock the file
+  pub fn read(&self, what: &str, confirm: bool) -> Result<Secret<String>, String> {
+    match self {
+      Self::Prompt => {
+        let password = prompt(&format!("{}: ", what))?;
+        if confirm && !password.is_empty() && *prompt(&format!("Confirm {}: ", what.to_lowercase()))? != *password {
+          return Err("The passwords don't match".to_string());
+        }
+        Ok(password)
+      }
+      Self::Fd(fd) => read_fd(*fd),
+      Self::Env(name) => {
+        let value = Zeroizing::new(std::env::var(name).map_err(|e| format!("Failed to read the password from ${}: {}", name, e))?);
+        read_line(&mut value.as_bytes()).map_err(|e| format!("Failed to read the password from ${}: {}", name, e))
+      }
+      Self::File(path) => {
+        let mut file = std::fs::File::open(path).map_err(|e| format!("Failed to open password file {:?}: {}", path, e))?;
+        read_line(&mut file).map_err(|e| format!("Failed to read password file {:?}: {}", path, e))
+      }
+    }
+  }
+}
+
+// Up to the first line break, a trailing "\r" is dropped too
+fn read_line(reader: &mut impl Read) -> Result<Secret<String>, String> {
+  let mut buf = Zeroizing::new([0u8; MAX_PASSWORD + 2]);
+  let mut len = 0;
+  while len < buf.len() && !buf[..len].contains(&b'\n') {
+    match reader.read(&mut buf[len..]) {
+      Ok(0) => break,
+      Ok(v) => len += v,
+      Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+      Err(e) => return Err(e.to_string()),
+    }
+  }
+
+  let line = buf[..len].split(|v| *v == b'\n').next().unwrap_or_default();
+  let line = line.strip_suffix(b"\r").unwrap_or(line);
+  if line.len() > MAX_PASSWORD {
+    return Err(format!("The password is longer than {} bytes", MAX_PASSWORD));
+  }
+  let line = std::str::from_utf8(line).map_err(|_| "The password is not valid UTF-8".to_string())?;
+
+  Ok(Secret::from(line))
+}
+
+#[cfg(unix)]
+fn read_fd(fd: i32) -> Result<Secret<String>, String> {
+  use std::os::fd::FromRawFd;
+
+  // Borrowed, the caller opened it and closes it
+  let mut file = std::mem::ManuallyDrop::new(unsafe { std::fs::File::from_raw_fd(fd) });
+  read_line(&mut *file).map_err(|e| format!("Failed to read the password from file descriptor {}: {}", fd, e))
+}
+
+#[cfg(not(unix))]
+fn read_fd(_fd: i32) -> Result<Secret<String>, String> { Err("--password-fd is only supported on Unix, use --password-file or --password-env".to_string()) }
+
+static NO_TERMINAL: &str = "No terminal to ask for the password, use --password-fd, --password-env or --password-file";
+
+// The terminal itself and not stdin, so the password can be asked for while data is piped in
+#[cfg(unix)]
+fn prompt(text: &str) -> Result<Secret<String>, String> {
+  use std::os::fd::AsRawFd;
+
+  let mut tty = std::fs::OpenOptions::new().read(true).write(true).open("/dev/tty").map_err(|_| NO_TERMINAL.to_string())?;
+  let fd = tty.as_raw_fd();
+  let mut saved = std::mem::MaybeUninit::<libc::termios>::uninit();
+  if unsafe { libc::tcgetattr(fd, saved.as_mut_ptr()) } != 0 {
+    return Err(NO_TERMINAL.to_string());
+  }
+  let saved = unsafe { saved.assume_init() };
+
+  // No echo, only the Enter at the end shows
+  let mut quiet = saved;
+  quiet.c_lflag &= !libc::ECHO;
+  quiet.c_lflag |= libc::ECHONL;
+  tty.write_all(text.as_bytes()).map_err(|e| format!("Failed to ask for the password: {}", e))?;
+  if unsafe { libc::tcsetattr(fd, libc::TCSAFLUSH, &quiet) } != 0 {
+    return Err("Failed to turn off the terminal echo".to_string());
+  }
+
+  let _restore = EchoGuard { fd, saved };
+  read_line(&mut tty).map_err(|e| format!("Failed to read the password: {}", e))
+}
+
+// Turns the echo back on however reading ends
+#[cfg(unix)]
+struct EchoGuard {
+  fd: i32,
+  saved: libc::termios,
+}
+
+#[cfg(unix)]
+impl Drop for EchoGuard {
+  fn drop(&mut self) { unsafe { libc::tcsetattr(self.fd, libc::TCSAFLUSH, &self.saved) }; }
+}
+
+// The console API reads UTF-16, the bytes of a plain read would be in the ANSI code page
+#[cfg(windows)]
+fn prompt(text: &str) -> Result<Secret<String>, String> {
+  use std::os::windows::io::AsRawHandle;
+  use winapi::um::{consoleapi::{GetConsoleMode, ReadConsoleW, SetConsoleMode}, wincon::ENABLE_ECHO_INPUT};
+
+  let input = std::fs::OpenOptions::new().read(true).write(true).open("CONIN$").map_err(|_| NO_TERMINAL.to_string())?;
+  let mut output = std::fs::OpenOptions::new().write(true).open("CONOUT$").map_err(|_| NO_TERMINAL.to_string())?;
+  let handle = input.as_raw_handle() as winapi::um::winnt::HANDLE;
+  let mut saved = 0;
+  if unsafe { GetConsoleMode(handle, &mut saved) } == 0 {
+    return Err(NO_TERMINAL.to_string());
+  }
+
+  output.write_all(text.as_bytes()).map_err(|e| format!("Failed to ask for the password: {}", e))?;
+  if unsafe { SetConsoleMode(handle, saved & !ENABLE_ECHO_INPUT) } == 0 {
+    return Err("Failed to turn off the console echo".to_string());
+  }
+
+  let mut wide = Zeroizing::new([0u16; MAX_PASSWORD + 2]);
+  let mut len = 0;
+  let read = unsafe { ReadConsoleW(handle, wide.as_mut_ptr() as *mut winapi::ctypes::c_void, wide.len() as u32, &mut len, std::ptr::null_mut()) };
+  unsafe { SetConsoleMode(handle, saved) };
+  let _ = output.write_all(b"\n");
+  if read == 0 {
+    return Err(format!("Failed to read the password: {}", std::io::Error::last_os_error()));
+  }
+
+  let line = wide[..len as usize].split(|v| *v == b'\r' as u16 || *v == b'\n' as u16).next().unwrap_or_default();
+  let line = Zeroizing::new(String::from_utf16(line).map_err(|_| "The password is not valid UTF-16".to_string())?);
+  if line.len() > MAX_PASSWORD {
+    return Err(format!("The password is longer than {} bytes", MAX_PASSWORD));
+  }
+
+  Ok(Secret::from(line.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn first_line_only() {
+    assert_eq!(read_line(&mut &b"secret\nmore"[..]).unwrap().as_str(), "secret");
+    assert_eq!(read_line(&mut &b"secret\r\n"[..]).unwrap().as_str(), "secret");
+    assert_eq!(read_line(&mut &b"no line break"[..]).unwrap().as_str(), "no line break");
+    assert_eq!(read_line(&mut &b""[..]).unwrap().as_str(), "");
+  }
+
+  #[test]
+  fn long_and_invalid_passwords_are_rejected() {
+    assert_eq!(read_line(&mut vec![b'a'; MAX_PASSWORD].as_slice()).unwrap().len(), MAX_PASSWORD);
+    assert!(read_line(&mut vec![b'a'; MAX_PASSWORD + 1].as_slice()).is_err());
+    assert!(read_line(&mut &b"\xff\xfe\n"[..]).is_err());
+  }
+
+  #[test]
+  fn password_from_a_file() {
+    let dir = crate::logic::test_util::TestDir::new();
+    let path = dir.write("password.txt", b"from file\n");
+    assert_eq!(PasswordSource::File(path).read("Password", true).unwrap().as_str(), "from file");
+    assert!(PasswordSource::File(dir.join("missing")).read("Password", false).is_err());
+  }
+}
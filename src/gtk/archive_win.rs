@@ -0,0 +1,67 @@
+use crate::{AppState, gtk::gtk_ui::MarginAll, logic::{encryption::{ArchiveEntry, ShinCrypt}, error::ShinError, global::{GTKhelper, Global}}};
+use gtk::prelude::*;
+use gtk4 as gtk;
+use parking_lot::RwLock;
+use std::{rc::Rc, sync::Arc};
+
+/// Lists the contents of an encrypted directory, single files can be extracted without decrypting the rest
+pub fn archive_win(window: &gtk::ApplicationWindow, aps: Arc<RwLock<AppState>>, shincrypt: ShinCrypt, entries: Vec<ArchiveEntry>) {
+  let consts = aps.read().consts.clone();
+  let shincrypt = Rc::new(shincrypt);
+
+  let archive_win = gtk::ApplicationWindow::builder().transient_for(window).modal(true).resizable(true).title("Archive contents").default_width(450).default_height(400).build();
+
+  let grid = gtk::Grid::new();
+  grid.set_row_spacing(consts.upad);
+  grid.set_column_spacing(consts.upad);
+  grid.set_margin_all(consts.margin);
+  archive_win.set_child(Some(&grid));
+
+  let list = gtk::ListBox::new();
+  list.set_selection_mode(gtk::SelectionMode::None);
+
+  for entry in entries {
+    let row = gtk::Box::new(gtk::Orientation::Horizontal, consts.upad as i32);
+
+    let text = if entry.file { format!("{} ({})", entry.path.display(), Global::format_size(entry.size)) } else { entry.path.display().to_string() };
+    let name_lbl = gtk::Label::new(Some(&text));
+    name_lbl.set_halign(gtk::Align::Start);
+    name_lbl.set_hexpand(true);
+    name_lbl.set_ellipsize(gtk::pango::EllipsizeMode::Middle);
+    row.append(&name_lbl);
+
+    if entry.file {
+      let window_c = window.clone();
+      let shincrypt_c = shincrypt.clone();
+
+      let extract_btn = gtk::Button::with_label("Extract 📤");
+      extract_btn.connect_clicked(move |_| extract(&window_c, &shincrypt_c, &entry));
+      row.append(&extract_btn);
+    }
+
+    list.append(&row);
+  }
+
+  let scroll = gtk::ScrolledWindow::builder().child(&list).hexpand(true).vexpand(true).build();
+  grid.attach(&scroll, 0, 0, 1, 1);
+
+  archive_win.present();
+}
+
+// Asks about an existing output and extracts again with the chosen policy
+fn extract(window: &gtk::ApplicationWindow, shincrypt: &Rc<ShinCrypt>, entry: &ArchiveEntry) {
+  match shincrypt.extract_entry(entry) {
+    Ok(_) => GTKhelper::message_box(window, "Success", format!("Extracted {}", entry.path.display()), None),
+    Err(ShinError::OutputExists(path)) => {
+      let window_c = window.clone();
+      let shincrypt_c = shincrypt.clone();
+      let entry_c = entry.clone();
+      GTKhelper::ask_conflict(window, &path, move |policy| {
+        let mut shincrypt = (*shincrypt_c).clone();
+        shincrypt.set_conflict_policy(policy);
+        extract(&window_c, &Rc::new(shincrypt), &entry_c);
+      });
+    }
+    Err(e) => GTKhelper::error_box(window, &e),
+  }
+}
@@ -1,8 +1,8 @@
-use crate::{AppState, gtk::settings_win::{AppSettings, settings_ui}, logic::{encryption::ShinCrypt, global::{GTKhelper, Global}}};
+use crate::{AppState, gtk::{archive_win::archive_win, key_slots_win::key_slots_win, settings_win::{AppSettings, settings_ui}, verify_win::verify_win}, logic::{encryption::{CancelToken, ShinCrypt, VerifyReport, VerifyStatus}, error::ShinError, global::{GTKhelper, Global}, identity::Recipient, progress::ProgressEvent, shred::{ShredParams, shred_path, shred_warning}}};
 use gtk::prelude::*;
 use gtk4 as gtk;
 use parking_lot::RwLock;
-use std::{path::PathBuf, sync::Arc};
+use std::{cell::RefCell, path::PathBuf, rc::Rc, sync::Arc};
 
 // 1) Define the trait
 pub trait MarginAll {
@@ -55,13 +55,35 @@ pub fn gtk_ui() -> gtk::glib::ExitCode {
     let grid = gtk::Grid::new();
     grid.set_row_spacing(10);
     grid.set_column_spacing(10);
-    grid.set_margin_all(consts.margin);
-    window.set_child(Some(&grid));
+
+    // The progress row sits outside the grid, the grid is greyed out while a job runs but Cancel has to stay usable
+    let main_box = gtk::Box::new(gtk::Orientation::Vertical, 10);
+    main_box.set_margin_all(consts.margin);
+    window.set_child(Some(&main_box));
 
     let progress = gtk::ProgressBar::new();
     progress.set_fraction(0.0);
+    progress.set_show_text(true);
+    progress.set_text(Some(""));
+    progress.set_hexpand(true);
+    progress.set_valign(gtk::Align::Center);
+
+    let cancel = CancelToken::default();
+    let cancel_c = cancel.clone();
+
+    let cancel_btn = gtk::Button::with_label("Cancel");
+    cancel_btn.set_tooltip_text(Some("Stop the running job and remove its partial output"));
+    cancel_btn.set_visible(false);
+    cancel_btn.connect_clicked(move |btn| {
+      cancel_c.cancel();
+      btn.set_sensitive(false);
+    });
 
-    grid.attach(&progress, 0, 0, 3, 1);
+    let progress_box = gtk::Box::new(gtk::Orientation::Horizontal, 10);
+    progress_box.append(&progress);
+    progress_box.append(&cancel_btn);
+    main_box.append(&progress_box);
+    main_box.append(&grid);
 
     // Row 0: Input + Browse
     let input = gtk::Entry::new();
@@ -120,24 +142,79 @@ pub fn gtk_ui() -> gtk::glib::ExitCode {
     grid.attach(&output, 0, 2, 2, 1);
     grid.attach(&browse_o_btn, 2, 2, 1, 1);
 
-    // Row 1: Password + Show/Hide
-    let password = gtk::Entry::new();
-    password.set_placeholder_text(Some("Password"));
-    password.set_visibility(false);
-    password.set_hexpand(true);
+    // Row 1: Password, the peek icon shows it. Read with GTKhelper::password_text, which copies it into locked memory
+    let password = gtk::PasswordEntry::builder().placeholder_text("Password").show_peek_icon(true).hexpand(true).build();
 
     GTKhelper::drag_n_drop(&password);
 
-    let password_c = password.clone();
-    let pw_toggle = gtk::Button::with_label("👁️");
-    pw_toggle.set_tooltip_text(Some("Toggle password visbility"));
-    pw_toggle.connect_clicked(move |_| {
-      let visible = !gtk::prelude::EntryExt::is_visible(&password_c);
-      gtk::prelude::EntryExt::set_visibility(&password_c, visible);
+    grid.attach(&password, 0, 3, 3, 1);
+
+    // Row 4: Keyfiles + Browse
+    let keyfile = gtk::Entry::new();
+    keyfile.set_placeholder_text(Some("Keyfiles (optional)"));
+    keyfile.set_hexpand(true);
+
+    GTKhelper::drag_n_drop(&keyfile);
+
+    let window_c = window.clone();
+    let keyfile_c = keyfile.clone();
+
+    let browse_k_btn = gtk::Button::with_label("🔑");
+    browse_k_btn.set_tooltip_text(Some("Select keyfiles"));
+    browse_k_btn.connect_clicked(move |_| {
+      let file_dialog = gtk::FileDialog::new();
+      file_dialog.set_title("Select keyfiles");
+      let keyfile_c = keyfile_c.clone();
+      let window_c_c = window_c.clone();
+
+      file_dialog.open_multiple(Some(&window_c), gtk::gio::Cancellable::NONE, move |result| match result {
+        Ok(v) => {
+          let paths: Vec<PathBuf> = (0..v.n_items()).filter_map(|i| v.item(i).and_downcast::<gtk::gio::File>()).filter_map(|f| f.path()).collect();
+          match std::env::join_paths(paths) {
+            Ok(v) => keyfile_c.set_text(v.to_str().unwrap_or_default()),
+            Err(err) => GTKhelper::message_box(&window_c_c, "Error", err.to_string(), None),
+          }
+        }
+        Err(err) => GTKhelper::dialog_error(&window_c_c, err),
+      });
     });
 
-    grid.attach(&password, 0, 3, 2, 1);
-    grid.attach(&pw_toggle, 2, 3, 1, 1);
+    grid.attach(&keyfile, 0, 4, 2, 1);
+    grid.attach(&browse_k_btn, 2, 4, 1, 1);
+
+    // Row 5: Recipients (public keys) when encrypting, identity file when decrypting
+    let recipient = gtk::Entry::new();
+    recipient.set_placeholder_text(Some("Public keys to encrypt for / identity to decrypt with (optional)"));
+    recipient.set_tooltip_text(Some("Encrypt: public keys (snc-pk-...) or files containing them, no password needed\nDecrypt: your identity file"));
+    recipient.set_hexpand(true);
+
+    GTKhelper::drag_n_drop(&recipient);
+
+    let window_c = window.clone();
+    let recipient_c = recipient.clone();
+
+    let browse_r_btn = gtk::Button::with_label("👥");
+    browse_r_btn.set_tooltip_text(Some("Select public key or identity files"));
+    browse_r_btn.connect_clicked(move |_| {
+      let file_dialog = gtk::FileDialog::new();
+      file_dialog.set_title("Select public key or identity files");
+      let recipient_c = recipient_c.clone();
+      let window_c_c = window_c.clone();
+
+      file_dialog.open_multiple(Some(&window_c), gtk::gio::Cancellable::NONE, move |result| match result {
+        Ok(v) => {
+          let paths: Vec<PathBuf> = (0..v.n_items()).filter_map(|i| v.item(i).and_downcast::<gtk::gio::File>()).filter_map(|f| f.path()).collect();
+          match std::env::join_paths(paths) {
+            Ok(v) => recipient_c.set_text(v.to_str().unwrap_or_default()),
+            Err(err) => GTKhelper::message_box(&window_c_c, "Error", err.to_string(), None),
+          }
+        }
+        Err(err) => GTKhelper::dialog_error(&window_c_c, err),
+      });
+    });
+
+    grid.attach(&recipient, 0, 5, 2, 1);
+    grid.attach(&browse_r_btn, 2, 5, 1, 1);
 
     let aps_c = aps.clone();
     let window_c = window.clone();
@@ -149,25 +226,142 @@ pub fn gtk_ui() -> gtk::glib::ExitCode {
       settings_ui(&window_c, aps_c.clone());
     });
 
+    let aps_c = aps.clone();
+    let window_c = window.clone();
+    let input_c = input.clone();
+    let password_c = password.clone();
+    let keyfile_c = keyfile.clone();
+    let recipient_c = recipient.clone();
+
+    // Add or remove passwords of the encrypted input file, unlocked with the keys filled in above
+    let key_slots_btn = gtk::Button::with_label("🗝️");
+    key_slots_btn.set_tooltip_text(Some("Key slots"));
+    key_slots_btn.connect_clicked(move |_| {
+      let mut input_v = input_c.text().to_string();
+      input_v.retain(|c| c != '"' && c != '\'');
+
+      let input_path = PathBuf::from(input_v.clone());
+      let password_v = GTKhelper::password_text(&password_c);
+      let keyfiles: Vec<PathBuf> = std::env::split_paths(keyfile_c.text().as_str()).filter(|v| !v.as_os_str().is_empty()).collect();
+      let identity = std::env::split_paths(recipient_c.text().as_str()).find(|v| !v.as_os_str().is_empty());
+
+      if input_v.is_empty() || (password_v.is_empty() && keyfiles.is_empty() && identity.is_none()) {
+        GTKhelper::message_box(&window_c, "Error", "Select an encrypted file and fill in its current password, keyfiles or identity", None);
+        return;
+      }
+
+      if !input_path.is_file() {
+        GTKhelper::message_box(&window_c, "Error", "Invalid input path", None);
+        return;
+      }
+
+      let mut shincrypt = ShinCrypt::new(input_path, PathBuf::new(), password_v.as_str(), None);
+      shincrypt.set_keyfiles(keyfiles);
+      shincrypt.set_kdf_params(aps_c.read().settings.kdf);
+      if let Some(v) = identity {
+        shincrypt.set_identity(v);
+      }
+
+      key_slots_win(&window_c, aps_c.clone(), shincrypt);
+      password_c.set_text("");
+    });
+
+    let aps_c = aps.clone();
+    let window_c = window.clone();
+    let input_c = input.clone();
+    let output_c = output.clone();
+    let password_c = password.clone();
+    let keyfile_c = keyfile.clone();
+    let recipient_c = recipient.clone();
+
+    // Browse an encrypted directory and extract single files from it
+    let archive_btn = gtk::Button::with_label("📜");
+    archive_btn.set_tooltip_text(Some("Archive contents"));
+    archive_btn.connect_clicked(move |_| {
+      let mut input_v = input_c.text().to_string();
+      input_v.retain(|c| c != '"' && c != '\'');
+
+      let mut output_v = output_c.text().to_string();
+      output_v.retain(|c| c != '"' && c != '\'');
+
+      let input_path = PathBuf::from(input_v.clone());
+      let mut output_path = PathBuf::from(output_v.clone());
+      let password_v = GTKhelper::password_text(&password_c);
+      let keyfiles: Vec<PathBuf> = std::env::split_paths(keyfile_c.text().as_str()).filter(|v| !v.as_os_str().is_empty()).collect();
+      let identity = std::env::split_paths(recipient_c.text().as_str()).find(|v| !v.as_os_str().is_empty());
+
+      if input_v.is_empty() || (password_v.is_empty() && keyfiles.is_empty() && identity.is_none()) {
+        GTKhelper::message_box(&window_c, "Error", "Fill in the required fields", None);
+        return;
+      }
+
+      if !input_path.is_file() {
+        GTKhelper::message_box(&window_c, "Error", "Invalid input path", None);
+        return;
+      }
+
+      if aps_c.read().settings.same_dir {
+        output_path = input_path.parent().unwrap().to_path_buf()
+      } else {
+        if !output_path.exists() {
+          if let Err(e) = std::fs::create_dir_all(&output_path) {
+            GTKhelper::message_box(&window_c, "Error", format!("Failed to create directory:\n{}", e), None);
+            return;
+          }
+        }
+      }
+
+      let mut shincrypt = ShinCrypt::new(input_path, output_path, password_v.as_str(), None);
+      shincrypt.set_keyfiles(keyfiles);
+      if let Some(v) = identity {
+        shincrypt.set_identity(v);
+      }
+      shincrypt.set_conflict_policy(aps_c.read().settings.conflict);
+
+      match shincrypt.list_entries() {
+        Ok(v) => archive_win(&window_c, aps_c.clone(), shincrypt, v),
+        Err(e) => GTKhelper::error_box(&window_c, &e),
+      }
+    });
+
     let encrypt_btn = gtk::Button::with_label("Encrypt 🔒");
     let decrypt_btn = gtk::Button::with_label("Decrypt 🔓");
+    let verify_btn = gtk::Button::with_label("🩺");
+    verify_btn.set_tooltip_text(Some("Verify that encrypted files open and aren't damaged, nothing is written. A directory verifies every .snc file in it"));
 
-    grid.attach(&encrypt_btn, 0, 4, 1, 1);
-    grid.attach(&decrypt_btn, 1, 4, 1, 1);
-    grid.attach(&settings_btn, 2, 4, 1, 1);
+    let tools_box = gtk::Box::new(gtk::Orientation::Horizontal, 10);
+    tools_box.append(&verify_btn);
+    tools_box.append(&archive_btn);
+    tools_box.append(&key_slots_btn);
+    tools_box.append(&settings_btn);
 
-    let (e_res_s, e_res_r) = crossbeam::channel::unbounded::<String>();
-    let (d_res_s, d_res_r) = crossbeam::channel::unbounded::<String>();
-    let (progress_s, progress_r) = crossbeam::channel::unbounded::<f64>();
+    grid.attach(&encrypt_btn, 0, 6, 1, 1);
+    grid.attach(&decrypt_btn, 1, 6, 1, 1);
+    grid.attach(&tools_box, 2, 6, 1, 1);
+
+    let (e_res_s, e_res_r) = crossbeam::channel::unbounded::<Result<PathBuf, ShinError>>();
+    let (d_res_s, d_res_r) = crossbeam::channel::unbounded::<Result<(), ShinError>>();
+    let (progress_s, progress_r) = crossbeam::channel::unbounded::<ProgressEvent>();
+    let (rm_res_s, rm_res_r) = crossbeam::channel::unbounded::<Result<(), String>>();
+    let (v_res_s, v_res_r) = crossbeam::channel::unbounded::<Result<Vec<VerifyReport>, ShinError>>();
+
+    // The running jobs, kept to run them again when an output exists and the user picks what happens to it
+    let e_job = Rc::new(RefCell::new(None::<ShinCrypt>));
+    let d_job = Rc::new(RefCell::new(None::<ShinCrypt>));
 
     let window_c = window.clone();
     let grid_c = grid.clone();
     let input_c = input.clone();
     let output_c = output.clone();
     let password_c = password.clone();
+    let keyfile_c = keyfile.clone();
+    let recipient_c = recipient.clone();
     let aps_c = aps.clone();
     let e_res_s_c = e_res_s.clone();
     let progress_s_c = progress_s.clone();
+    let cancel_c = cancel.clone();
+    let cancel_btn_c = cancel_btn.clone();
+    let e_job_c = e_job.clone();
 
     encrypt_btn.connect_clicked(move |_| {
       let mut input_v = input_c.text().to_string();
@@ -178,9 +372,17 @@ pub fn gtk_ui() -> gtk::glib::ExitCode {
 
       let input_path = PathBuf::from(input_v.clone());
       let mut output_path = PathBuf::from(output_v.clone());
-      let password_v = password_c.text().to_string();
+      let password_v = GTKhelper::password_text(&password_c);
+      let keyfiles: Vec<PathBuf> = std::env::split_paths(keyfile_c.text().as_str()).filter(|v| !v.as_os_str().is_empty()).collect();
+      let recipients = match Recipient::from_list(std::env::split_paths(recipient_c.text().as_str()).filter(|v| !v.as_os_str().is_empty())) {
+        Ok(v) => v,
+        Err(e) => {
+          GTKhelper::message_box(&window_c, "Error", e, None);
+          return;
+        }
+      };
 
-      if input_v.is_empty() || password_v.is_empty() {
+      if input_v.is_empty() || (password_v.is_empty() && keyfiles.is_empty() && recipients.is_empty()) {
         GTKhelper::message_box(&window_c, "Error", "Fill in the required fields", None);
         return;
       }
@@ -190,6 +392,11 @@ pub fn gtk_ui() -> gtk::glib::ExitCode {
         return;
       }
 
+      if let Some(v) = keyfiles.iter().find(|v| !v.is_file()) {
+        GTKhelper::message_box(&window_c, "Error", format!("Invalid keyfile path:\n{}", v.display()), None);
+        return;
+      }
+
       if aps_c.read().settings.same_dir {
         output_path = input_path.parent().unwrap().to_path_buf()
       } else {
@@ -201,19 +408,27 @@ pub fn gtk_ui() -> gtk::glib::ExitCode {
         }
       }
 
-      grid_c.set_sensitive(false);
-
       let input_path_c = input_path.clone();
       let output_path_c = output_path.clone();
       let e_res_s_c_c = e_res_s_c.clone();
       let progress_s_c_c = progress_s_c.clone();
 
-      let shincrypt = ShinCrypt::new(input_path_c.clone(), output_path_c.clone(), password_v.clone(), Some(progress_s_c_c.clone()));
-
-      std::thread::spawn(move || match shincrypt.encrypt_file() {
-        Ok(_) => e_res_s_c_c.send("Success".to_string()),
-        Err(e) => e_res_s_c_c.send(e),
-      });
+      let mut shincrypt = ShinCrypt::new(input_path_c.clone(), output_path_c.clone(), password_v.as_str(), Some(progress_s_c_c.clone()));
+      shincrypt.set_keyfiles(keyfiles);
+      shincrypt.set_recipients(recipients);
+      shincrypt.set_metadata_policy(aps_c.read().settings.metadata);
+      shincrypt.set_kdf_params(aps_c.read().settings.kdf);
+      shincrypt.set_encryption(aps_c.read().settings.encryption);
+      shincrypt.set_compression(aps_c.read().settings.compression);
+      shincrypt.set_padding(aps_c.read().settings.padding);
+      shincrypt.set_obfuscate_name(aps_c.read().settings.obfuscate_names);
+      shincrypt.set_threads(aps_c.read().settings.threads);
+      shincrypt.set_conflict_policy(aps_c.read().settings.conflict);
+      shincrypt.set_verify(aps_c.read().settings.remove_org);
+      shincrypt.set_cancel_token(cancel_c.clone());
+
+      *e_job_c.borrow_mut() = Some(shincrypt.clone());
+      start_job(&grid_c, &cancel_c, &cancel_btn_c, e_res_s_c_c, shincrypt, ShinCrypt::encrypt_file);
 
       password_c.set_text("");
     });
@@ -223,9 +438,14 @@ pub fn gtk_ui() -> gtk::glib::ExitCode {
     let input_c = input.clone();
     let output_c = output.clone();
     let password_c = password.clone();
+    let keyfile_c = keyfile.clone();
+    let recipient_c = recipient.clone();
     let aps_c = aps.clone();
     let d_res_s_c = d_res_s.clone();
     let progress_s_c = progress_s.clone();
+    let cancel_c = cancel.clone();
+    let cancel_btn_c = cancel_btn.clone();
+    let d_job_c = d_job.clone();
 
     decrypt_btn.connect_clicked(move |_| {
       let mut input_v = input_c.text().to_string();
@@ -236,9 +456,11 @@ pub fn gtk_ui() -> gtk::glib::ExitCode {
 
       let input_path = PathBuf::from(input_v.clone());
       let mut output_path = PathBuf::from(output_v.clone());
-      let password_v = password_c.text().to_string();
+      let password_v = GTKhelper::password_text(&password_c);
+      let keyfiles: Vec<PathBuf> = std::env::split_paths(keyfile_c.text().as_str()).filter(|v| !v.as_os_str().is_empty()).collect();
+      let identity = std::env::split_paths(recipient_c.text().as_str()).find(|v| !v.as_os_str().is_empty());
 
-      if input_v.is_empty() || password_v.is_empty() {
+      if input_v.is_empty() || (password_v.is_empty() && keyfiles.is_empty() && identity.is_none()) {
         GTKhelper::message_box(&window_c, "Error", "Fill in the required fields", None);
         return;
       }
@@ -248,6 +470,16 @@ pub fn gtk_ui() -> gtk::glib::ExitCode {
         return;
       }
 
+      if let Some(v) = keyfiles.iter().find(|v| !v.is_file()) {
+        GTKhelper::message_box(&window_c, "Error", format!("Invalid keyfile path:\n{}", v.display()), None);
+        return;
+      }
+
+      if let Some(v) = identity.as_ref().filter(|v| !v.is_file()) {
+        GTKhelper::message_box(&window_c, "Error", format!("Invalid identity file path:\n{}", v.display()), None);
+        return;
+      }
+
       if aps_c.read().settings.same_dir {
         output_path = input_path.parent().unwrap().to_path_buf()
       } else {
@@ -259,19 +491,75 @@ pub fn gtk_ui() -> gtk::glib::ExitCode {
         }
       }
 
-      grid_c.set_sensitive(false);
-
       let input_path_c = input_path.clone();
       let output_path_c = output_path.clone();
       let d_res_s_c_c = d_res_s_c.clone();
       let progress_s_c_c = progress_s_c.clone();
 
-      let shincrypt = ShinCrypt::new(input_path_c.clone(), output_path_c.clone(), password_v.clone(), Some(progress_s_c_c.clone()));
+      let mut shincrypt = ShinCrypt::new(input_path_c.clone(), output_path_c.clone(), password_v.as_str(), Some(progress_s_c_c.clone()));
+      shincrypt.set_keyfiles(keyfiles);
+      if let Some(v) = identity {
+        shincrypt.set_identity(v);
+      }
+      shincrypt.set_threads(aps_c.read().settings.threads);
+      shincrypt.set_conflict_policy(aps_c.read().settings.conflict);
+      shincrypt.set_cancel_token(cancel_c.clone());
 
-      std::thread::spawn(move || match shincrypt.decrypt_file() {
-        Ok(_) => d_res_s_c_c.send("Success".to_string()),
-        Err(e) => d_res_s_c_c.send(e),
-      });
+      *d_job_c.borrow_mut() = Some(shincrypt.clone());
+      start_job(&grid_c, &cancel_c, &cancel_btn_c, d_res_s_c_c, shincrypt, ShinCrypt::decrypt_file);
+
+      password_c.set_text("");
+    });
+
+    let window_c = window.clone();
+    let grid_c = grid.clone();
+    let input_c = input.clone();
+    let password_c = password.clone();
+    let keyfile_c = keyfile.clone();
+    let recipient_c = recipient.clone();
+    let aps_c = aps.clone();
+    let progress_s_c = progress_s.clone();
+    let cancel_c = cancel.clone();
+    let cancel_btn_c = cancel_btn.clone();
+
+    verify_btn.connect_clicked(move |_| {
+      let mut input_v = input_c.text().to_string();
+      input_v.retain(|c| c != '"' && c != '\'');
+
+      let input_path = PathBuf::from(input_v.clone());
+      let password_v = GTKhelper::password_text(&password_c);
+      let keyfiles: Vec<PathBuf> = std::env::split_paths(keyfile_c.text().as_str()).filter(|v| !v.as_os_str().is_empty()).collect();
+      let identity = std::env::split_paths(recipient_c.text().as_str()).find(|v| !v.as_os_str().is_empty());
+
+      if input_v.is_empty() || (password_v.is_empty() && keyfiles.is_empty() && identity.is_none()) {
+        GTKhelper::message_box(&window_c, "Error", "Select encrypted files and fill in their password, keyfiles or identity", None);
+        return;
+      }
+
+      if !input_path.exists() {
+        GTKhelper::message_box(&window_c, "Error", "Invalid input path", None);
+        return;
+      }
+
+      if let Some(v) = keyfiles.iter().find(|v| !v.is_file()) {
+        GTKhelper::message_box(&window_c, "Error", format!("Invalid keyfile path:\n{}", v.display()), None);
+        return;
+      }
+
+      if let Some(v) = identity.as_ref().filter(|v| !v.is_file()) {
+        GTKhelper::message_box(&window_c, "Error", format!("Invalid identity file path:\n{}", v.display()), None);
+        return;
+      }
+
+      let mut shincrypt = ShinCrypt::new(input_path, PathBuf::new(), password_v.as_str(), Some(progress_s_c.clone()));
+      shincrypt.set_keyfiles(keyfiles);
+      if let Some(v) = identity {
+        shincrypt.set_identity(v);
+      }
+      shincrypt.set_threads(aps_c.read().settings.threads);
+      shincrypt.set_cancel_token(cancel_c.clone());
+
+      start_job(&grid_c, &cancel_c, &cancel_btn_c, v_res_s.clone(), shincrypt, ShinCrypt::verify_batch);
 
       password_c.set_text("");
     });
@@ -280,9 +568,10 @@ pub fn gtk_ui() -> gtk::glib::ExitCode {
     let window_c = window.clone();
     let grid_c = grid.clone();
     let progress_c = progress.clone();
-    let input_c = input.clone();
     let output_c = output.clone();
     let browse_o_btn_c = browse_o_btn.clone();
+    let cancel_c = cancel.clone();
+    let cancel_btn_c = cancel_btn.clone();
 
     // Use glib::source::idle_add to update GUI from main thread
     gtk::glib::source::idle_add_local(move || {
@@ -294,43 +583,103 @@ pub fn gtk_ui() -> gtk::glib::ExitCode {
         browse_o_btn_c.set_sensitive(!same_dir);
       }
 
-      if let Ok(prog) = progress_r.try_recv() {
-        progress_c.set_fraction(prog);
+      // Only the latest event matters, older ones would just be redrawn over
+      if let Some(event) = progress_r.try_iter().last() {
+        match event.fraction() {
+          Some(v) => progress_c.set_fraction(v),
+          None => progress_c.pulse(),
+        }
+        progress_c.set_text(Some(&event.to_string()));
       }
 
-      let mut input_v = input_c.text().to_string();
-      input_v.retain(|c| c != '"' && c != '\'');
-      let input_path = std::path::PathBuf::from(input_v);
-
       if let Ok(e_res) = e_res_r.try_recv() {
+        let job = e_job.borrow_mut().take();
         grid_c.set_sensitive(true);
+        cancel_btn_c.set_visible(false);
         progress_c.set_fraction(0.0);
-        if e_res == "Success" {
-          if aps_c.read().settings.remove_org {
-            if let Err(e) = Global::del_path(input_path.clone()) {
-              GTKhelper::message_box(&window_c, "Error", e, None);
-            };
+        progress_c.set_text(Some(""));
+        match e_res {
+          Ok(v) => {
+            // The job's own input, the entry may have changed while it ran
+            if aps_c.read().settings.remove_org
+              && let Some(job) = &job
+            {
+              remove_source(&window_c, &grid_c, rm_res_s.clone(), job.input_path(), &aps_c.read().settings);
+            }
+
+            GTKhelper::message_box(&window_c, "Success", format!("File encrypted as {}", v.file_name().unwrap_or_default().to_string_lossy()), None);
           }
-
-          GTKhelper::message_box(&window_c, e_res, "File encrypted", None);
-        } else {
-          GTKhelper::message_box(&window_c, "Failed", e_res, None);
+          Err(ShinError::OutputExists(path)) if let Some(job) = job => {
+            let (grid_c, cancel_c, cancel_btn_c, e_res_s_c, e_job_c) = (grid_c.clone(), cancel_c.clone(), cancel_btn_c.clone(), e_res_s.clone(), e_job.clone());
+            GTKhelper::ask_conflict(&window_c, &path, move |policy| {
+              let mut job = job.clone();
+              job.set_conflict_policy(policy);
+              *e_job_c.borrow_mut() = Some(job.clone());
+              start_job(&grid_c, &cancel_c, &cancel_btn_c, e_res_s_c.clone(), job, ShinCrypt::encrypt_file);
+            });
+          }
+          Err(e) => GTKhelper::error_box(&window_c, &e),
         }
       }
 
       if let Ok(d_res) = d_res_r.try_recv() {
+        let job = d_job.borrow_mut().take();
         grid_c.set_sensitive(true);
+        cancel_btn_c.set_visible(false);
         progress_c.set_fraction(0.0);
-        if d_res == "Success" {
-          if aps_c.read().settings.remove_org {
-            if let Err(e) = Global::del_path(input_path.clone()) {
-              GTKhelper::message_box(&window_c, "Error", e, None);
-            };
+        progress_c.set_text(Some(""));
+        match d_res {
+          Ok(_) => {
+            // The job's own input, the entry may have changed while it ran
+            // v1 files have no authentication, nothing says the output is intact, so they are kept
+            let v1 = job.as_ref().is_some_and(|v| matches!(v.key_header(), Ok(None)));
+            if aps_c.read().settings.remove_org
+              && !v1
+              && let Some(job) = &job
+            {
+              remove_source(&window_c, &grid_c, rm_res_s.clone(), job.input_path(), &aps_c.read().settings);
+            }
+
+            if v1 && aps_c.read().settings.remove_org {
+              GTKhelper::message_box(&window_c, "Success", "File decrypted\n\nThe source was kept, files of the first ShinCrypt version can't be checked for damage. Remove it yourself once the output is fine", None);
+            } else {
+              GTKhelper::message_box(&window_c, "Success", "File decrypted", None);
+            }
+          }
+          Err(ShinError::OutputExists(path)) if let Some(job) = job => {
+            let (grid_c, cancel_c, cancel_btn_c, d_res_s_c, d_job_c) = (grid_c.clone(), cancel_c.clone(), cancel_btn_c.clone(), d_res_s.clone(), d_job.clone());
+            GTKhelper::ask_conflict(&window_c, &path, move |policy| {
+              let mut job = job.clone();
+              job.set_conflict_policy(policy);
+              *d_job_c.borrow_mut() = Some(job.clone());
+              start_job(&grid_c, &cancel_c, &cancel_btn_c, d_res_s_c.clone(), job, ShinCrypt::decrypt_file);
+            });
           }
+          Err(e) => GTKhelper::error_box(&window_c, &e),
+        }
+      }
+
+      if let Ok(v_res) = v_res_r.try_recv() {
+        grid_c.set_sensitive(true);
+        cancel_btn_c.set_visible(false);
+        progress_c.set_fraction(0.0);
+        progress_c.set_text(Some(""));
+        match v_res {
+          // A single file gets the same dialogs as decrypting it would
+          Ok(mut v) if v.len() == 1 => match v.remove(0) {
+            VerifyReport { result: Ok(_), path } => GTKhelper::message_box(&window_c, "Verified", format!("{} opens and is intact", path.file_name().unwrap_or_default().to_string_lossy()), None),
+            VerifyReport { result: Err(e), .. } => GTKhelper::error_box(&window_c, &e),
+          },
+          Ok(v) if v.iter().all(|v| v.status() == VerifyStatus::Ok) => GTKhelper::message_box(&window_c, "Verified", format!("All {} files open and are intact", v.len()), None),
+          Ok(v) => verify_win(&window_c, aps_c.clone(), v),
+          Err(e) => GTKhelper::error_box(&window_c, &e),
+        }
+      }
 
-          GTKhelper::message_box(&window_c, d_res, "File decrypted", None);
-        } else {
-          GTKhelper::message_box(&window_c, "Failed", d_res, None);
+      if let Ok(rm_res) = rm_res_r.try_recv() {
+        grid_c.set_sensitive(true);
+        if let Err(e) = rm_res {
+          GTKhelper::message_box(&window_c, "Error", e, None);
         }
       }
 
@@ -345,3 +694,45 @@ pub fn gtk_ui() -> gtk::glib::ExitCode {
 
   application.run()
 }
+
+// Greys out the form and runs `job` on a worker thread, the result comes back on `sender`
+fn start_job<T: Send + 'static>(grid: &gtk::Grid, cancel: &CancelToken, cancel_btn: &gtk::Button, sender: crossbeam::channel::Sender<Result<T, ShinError>>, shincrypt: ShinCrypt, job: fn(&ShinCrypt) -> Result<T, ShinError>) {
+  grid.set_sensitive(false);
+  cancel.reset();
+  cancel_btn.set_sensitive(true);
+  cancel_btn.set_visible(true);
+
+  std::thread::spawn(move || sender.send(job(&shincrypt)));
+}
+
+// Removes the source of a finished job, encrypted files were verified before they got their final name.
+// Shredding is only started once the user has seen why it may not reach the data, the trash is offered instead
+fn remove_source(window: &gtk::ApplicationWindow, grid: &gtk::Grid, sender: crossbeam::channel::Sender<Result<(), String>>, path: &std::path::Path, settings: &AppSettings) {
+  let path = path.to_path_buf();
+  let (trash, params) = (settings.trash_org, settings.shred);
+  if !settings.shred_org {
+    return start_removal(grid, sender, path, None, trash);
+  }
+
+  match shred_warning(&path) {
+    Some(warning) => {
+      let grid = grid.clone();
+      GTKhelper::ask_shred(window, &path, &warning, move |shred| start_removal(&grid, sender, path, shred.then_some(params), true));
+    }
+    None => start_removal(grid, sender, path, Some(params), trash),
+  }
+}
+
+// Shredding takes as long as writing the file, so all removals run on a worker thread
+fn start_removal(grid: &gtk::Grid, sender: crossbeam::channel::Sender<Result<(), String>>, path: PathBuf, shred: Option<ShredParams>, trash: bool) {
+  grid.set_sensitive(false);
+
+  std::thread::spawn(move || {
+    let res = match shred {
+      Some(params) => shred_path(&path, params).map_err(|e| format!("Source can't be shredded\n{}", e)),
+      None if trash => Global::trash_path(path),
+      None => Global::del_path(path),
+    };
+    sender.send(res)
+  });
+}
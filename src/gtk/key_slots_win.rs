@@ -0,0 +1,150 @@
+use crate::{AppState, gtk::gtk_ui::MarginAll, logic::{encryption::ShinCrypt, global::GTKhelper}};
+use gtk::prelude::*;
+use gtk4 as gtk;
+use parking_lot::RwLock;
+use std::{path::PathBuf, rc::Rc, sync::Arc};
+
+/// Lists the key slots of an encrypted file and adds, changes or removes passwords, unlocked with the keys `shincrypt` was given
+pub fn key_slots_win(window: &gtk::ApplicationWindow, aps: Arc<RwLock<AppState>>, shincrypt: ShinCrypt) {
+  let consts = aps.read().consts.clone();
+  let shincrypt = Rc::new(shincrypt);
+
+  let slots_win = gtk::ApplicationWindow::builder().transient_for(window).modal(true).resizable(true).title("Key slots").default_width(350).default_height(150).build();
+
+  let grid = gtk::Grid::new();
+  grid.set_row_spacing(consts.upad);
+  grid.set_column_spacing(consts.upad);
+  grid.set_margin_all(consts.margin);
+  slots_win.set_child(Some(&grid));
+
+  let slots_lbl = gtk::Label::new(None);
+  slots_lbl.set_halign(gtk::Align::Start);
+  grid.attach(&slots_lbl, 0, 0, 2, 1);
+
+  let refresh = {
+    let shincrypt_c = shincrypt.clone();
+    let slots_lbl_c = slots_lbl.clone();
+    move || {
+      let text = match shincrypt_c.key_slots() {
+        Ok(v) => v.iter().enumerate().map(|(i, slot)| format!("Slot {}: {}", i + 1, slot.label())).collect::<Vec<_>>().join("\n"),
+        Err(e) => e.to_string(),
+      };
+      slots_lbl_c.set_text(&text);
+    }
+  };
+  refresh();
+
+  let new_password = gtk::PasswordEntry::builder().placeholder_text("New password").show_peek_icon(true).hexpand(true).build();
+  grid.attach(&new_password, 0, 1, 2, 1);
+
+  let confirm_password = gtk::PasswordEntry::builder().placeholder_text("Confirm new password").hexpand(true).build();
+  grid.attach(&confirm_password, 0, 2, 2, 1);
+
+  let new_keyfile = gtk::Entry::new();
+  new_keyfile.set_placeholder_text(Some("New keyfiles (optional)"));
+  new_keyfile.set_hexpand(true);
+  GTKhelper::drag_n_drop(&new_keyfile);
+  grid.attach(&new_keyfile, 0, 3, 2, 1);
+
+  {
+    let window_c = window.clone();
+    let shincrypt_c = shincrypt.clone();
+    let refresh_c = refresh.clone();
+    let new_password_c = new_password.clone();
+    let confirm_password_c = confirm_password.clone();
+    let new_keyfile_c = new_keyfile.clone();
+
+    let add_btn = gtk::Button::with_label("Add key ➕");
+    add_btn.set_hexpand(true);
+    add_btn.set_tooltip_text(Some("Add the new password to a free slot, the current one keeps working"));
+    add_btn.connect_clicked(move |_| {
+      let password_v = GTKhelper::password_text(&new_password_c);
+      let keyfiles: Vec<PathBuf> = std::env::split_paths(new_keyfile_c.text().as_str()).filter(|v| !v.as_os_str().is_empty()).collect();
+
+      if password_v.as_str() != GTKhelper::password_text(&confirm_password_c).as_str() {
+        GTKhelper::message_box(&window_c, "Error", "The passwords don't match", None);
+        return;
+      }
+
+      if let Some(v) = keyfiles.iter().find(|v| !v.is_file()) {
+        GTKhelper::message_box(&window_c, "Error", format!("Invalid keyfile path:\n{}", v.display()), None);
+        return;
+      }
+
+      match shincrypt_c.add_password(password_v.as_str(), &keyfiles) {
+        Ok(_) => GTKhelper::message_box(&window_c, "Success", "Key added", None),
+        Err(e) => GTKhelper::error_box(&window_c, &e),
+      }
+
+      new_password_c.set_text("");
+      confirm_password_c.set_text("");
+      refresh_c();
+    });
+    grid.attach(&add_btn, 0, 4, 1, 1);
+  }
+
+  {
+    let window_c = window.clone();
+    let shincrypt_c = shincrypt.clone();
+    let refresh_c = refresh.clone();
+    let new_password_c = new_password.clone();
+    let confirm_password_c = confirm_password.clone();
+    let new_keyfile_c = new_keyfile.clone();
+
+    let slots_win_c = slots_win.clone();
+
+    let change_btn = gtk::Button::with_label("Change current key 🔁");
+    change_btn.set_hexpand(true);
+    change_btn.set_tooltip_text(Some("Replace the password, keyfiles or both from the main window with the new ones, only the key slot is rewritten"));
+    change_btn.connect_clicked(move |_| {
+      let password_v = GTKhelper::password_text(&new_password_c);
+      let keyfiles: Vec<PathBuf> = std::env::split_paths(new_keyfile_c.text().as_str()).filter(|v| !v.as_os_str().is_empty()).collect();
+
+      if password_v.as_str() != GTKhelper::password_text(&confirm_password_c).as_str() {
+        GTKhelper::message_box(&window_c, "Error", "The passwords don't match", None);
+        return;
+      }
+
+      if let Some(v) = keyfiles.iter().find(|v| !v.is_file()) {
+        GTKhelper::message_box(&window_c, "Error", format!("Invalid keyfile path:\n{}", v.display()), None);
+        return;
+      }
+
+      // The keys this window was opened with no longer work after a change
+      match shincrypt_c.change_password(password_v.as_str(), &keyfiles) {
+        Ok(_) => {
+          GTKhelper::message_box(&window_c, "Success", "Key changed, the old one no longer opens the file", None);
+          slots_win_c.close();
+        }
+        Err(e) => GTKhelper::error_box(&window_c, &e),
+      }
+
+      new_password_c.set_text("");
+      confirm_password_c.set_text("");
+      refresh_c();
+    });
+    grid.attach(&change_btn, 0, 5, 2, 1);
+  }
+
+  {
+    let window_c = window.clone();
+    let shincrypt_c = shincrypt.clone();
+    let slots_win_c = slots_win.clone();
+
+    let remove_btn = gtk::Button::with_label("Remove current key ➖");
+    remove_btn.set_hexpand(true);
+    remove_btn.set_tooltip_text(Some("Remove the slot opened by the password, keyfiles or identity from the main window"));
+    remove_btn.connect_clicked(move |_| {
+      match shincrypt_c.remove_key() {
+        Ok(_) => {
+          GTKhelper::message_box(&window_c, "Success", "Key removed", None);
+          slots_win_c.close();
+        }
+        Err(e) => GTKhelper::error_box(&window_c, &e),
+      }
+    });
+    grid.attach(&remove_btn, 1, 4, 1, 1);
+  }
+
+  slots_win.present();
+}
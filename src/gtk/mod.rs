@@ -1,3 +1,6 @@
 pub(crate) mod about_win;
+pub(crate) mod archive_win;
 pub(crate) mod gtk_ui;
+pub(crate) mod key_slots_win;
 pub(crate) mod settings_win;
+pub(crate) mod verify_win;
@@ -1,20 +1,34 @@
-use crate::{AppState, SIZE_1MB, gtk::{about_win::about_win, gtk_ui::MarginAll}, logic::{encryption::ShinCrypt, global::{GTKhelper, Global}}};
+use crate::{AppState, SIZE_1MB, gtk::{about_win::about_win, gtk_ui::MarginAll}, logic::{encryption::{CompressionAlgorithm, CompressionParams, EncMethod, KdfAlgorithm, KdfParams, MetadataPolicy, PaddingScheme, ShinCrypt}, global::{GTKhelper, Global}, identity::Identity, output::ConflictPolicy, shred::{ShredParams, ShredPattern, shred_warning}}};
 use gtk4::prelude::*;
 use parking_lot::RwLock;
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 
 const SETTINGS_FILE: &str = "settings.ron";
+// Suites offered for new files, plain XChaCha20 is only kept for reading v1 files
+const CIPHERS: [(EncMethod, &str); 3] = [(EncMethod::XChaCha20Poly1305, "XChaCha20-Poly1305"), (EncMethod::XChaCha12Poly1305, "XChaCha12-Poly1305 (faster)"), (EncMethod::Aes256GcmXChaCha20Poly1305, "AES-256-GCM + XChaCha20-Poly1305")];
 
 #[derive(Clone, Serialize, Deserialize)]
+#[serde(default)] // Settings files from older versions lack the newer fields
 pub struct AppSettings {
   pub dark_mode: bool,
   pub remove_org: bool,
+  pub trash_org: bool, // Move the source to the trash instead of deleting it
+  pub shred_org: bool, // Overwrite the source before deleting it, goes before the trash
+  pub shred: ShredParams,
   pub same_dir: bool,
+  pub obfuscate_names: bool,
+  pub metadata: MetadataPolicy,
+  pub kdf: KdfParams,
+  pub encryption: EncMethod,
+  pub compression: CompressionParams,
+  pub padding: PaddingScheme,
+  pub threads: usize, // Cipher threads, 0 uses one per CPU core
+  pub conflict: ConflictPolicy,
 }
 
 impl Default for AppSettings {
-  fn default() -> Self { Self { dark_mode: true, remove_org: false, same_dir: false } }
+  fn default() -> Self { Self { dark_mode: true, remove_org: false, trash_org: false, shred_org: false, shred: ShredParams::default(), same_dir: false, obfuscate_names: false, metadata: MetadataPolicy::default(), kdf: KdfParams::default(), encryption: EncMethod::XChaCha20Poly1305, compression: CompressionParams::default(), padding: PaddingScheme::None, threads: 0, conflict: ConflictPolicy::default() } }
 }
 
 impl AppSettings {
@@ -75,6 +89,7 @@ pub fn settings_ui(window: &gtk4::ApplicationWindow, aps: Arc<RwLock<AppState>>)
   {
     let aps_c = aps.clone();
     let remove_cb = gtk4::CheckButton::with_label("Remove source file");
+    remove_cb.set_tooltip_text(Some("Encrypted files are decrypted once more and compared with the source before it is removed"));
     remove_cb.set_active(aps_c.read().settings.remove_org);
     remove_cb.connect_toggled(move |cb| {
       aps_c.write().settings.remove_org = cb.is_active();
@@ -83,6 +98,71 @@ pub fn settings_ui(window: &gtk4::ApplicationWindow, aps: Arc<RwLock<AppState>>)
     grid.attach(&remove_cb, 0, 1, 2, 1);
   }
 
+  // Trash instead of delete checkbox
+  {
+    let aps_c = aps.clone();
+    let trash_cb = gtk4::CheckButton::with_label("Move removed files to trash");
+    trash_cb.set_active(aps_c.read().settings.trash_org);
+    trash_cb.connect_toggled(move |cb| {
+      aps_c.write().settings.trash_org = cb.is_active();
+      aps_c.read().settings.export().unwrap();
+    });
+    grid.attach(&trash_cb, 0, 2, 2, 1);
+  }
+
+  // Shred instead of delete, with how the contents get overwritten
+  {
+    let (aps_c, settings_win_c) = (aps.clone(), settings_win.clone());
+    let shred_cb = gtk4::CheckButton::with_label("Shred removed files");
+    shred_cb.set_tooltip_text(Some("Overwrite the contents before deleting, instead of moving to the trash.\nNot reliable on SSDs and copy-on-write filesystems like btrfs or ZFS, the old data can survive there"));
+    shred_cb.set_active(aps_c.read().settings.shred_org);
+    shred_cb.connect_toggled(move |cb| {
+      aps_c.write().settings.shred_org = cb.is_active();
+      aps_c.read().settings.export().unwrap();
+
+      // The home directory stands in for the files, each source is checked again before it gets shredded
+      let home = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE")).map(std::path::PathBuf::from);
+      if cb.is_active()
+        && let Some(warning) = shred_warning(home.unwrap_or_else(std::env::temp_dir))
+      {
+        GTKhelper::message_box(&settings_win_c, "Shredding may not erase files", format!("{}\n\nYou will be asked again before a file is shredded, and can move it to the trash instead", warning), None);
+      }
+    });
+    grid.attach(&shred_cb, 0, 3, 2, 1);
+  }
+
+  {
+    let aps_c = aps.clone();
+    let passes_lbl = gtk4::Label::new(Some("Shred passes"));
+    passes_lbl.set_halign(gtk4::Align::Start);
+
+    let passes_sb = gtk4::SpinButton::with_range(1.0, 35.0, 1.0);
+    passes_sb.set_tooltip_text(Some("One pass is enough on modern drives"));
+    passes_sb.set_value(aps_c.read().settings.shred.passes as f64);
+    passes_sb.connect_value_changed(move |sb| {
+      aps_c.write().settings.shred.passes = sb.value_as_int() as u32;
+      aps_c.read().settings.export().unwrap();
+    });
+    grid.attach(&passes_lbl, 0, 4, 1, 1);
+    grid.attach(&passes_sb, 1, 4, 1, 1);
+  }
+
+  {
+    let aps_c = aps.clone();
+    let pattern_lbl = gtk4::Label::new(Some("Shred with"));
+    pattern_lbl.set_halign(gtk4::Align::Start);
+
+    // Same order as ShredPattern
+    let pattern_dd = gtk4::DropDown::from_strings(&["Random data", "Zeros"]);
+    pattern_dd.set_selected(aps_c.read().settings.shred.pattern as u32);
+    pattern_dd.connect_selected_notify(move |dd| {
+      aps_c.write().settings.shred.pattern = ShredPattern::from_u8(dd.selected() as u8).unwrap_or_default();
+      aps_c.read().settings.export().unwrap();
+    });
+    grid.attach(&pattern_lbl, 0, 5, 1, 1);
+    grid.attach(&pattern_dd, 1, 5, 1, 1);
+  }
+
   // Smae directory output checkbox
   {
     let aps_c = aps.clone();
@@ -92,21 +172,278 @@ pub fn settings_ui(window: &gtk4::ApplicationWindow, aps: Arc<RwLock<AppState>>)
       aps_c.write().settings.same_dir = cb.is_active();
       aps_c.read().settings.export().unwrap();
     });
-    grid.attach(&same_dir_cb, 0, 2, 2, 1);
+    grid.attach(&same_dir_cb, 0, 6, 2, 1);
+  }
+
+  // Random output names checkbox
+  {
+    let aps_c = aps.clone();
+    let obfuscate_cb = gtk4::CheckButton::with_label("Random output names");
+    obfuscate_cb.set_tooltip_text(Some("Encrypted files get a random name, decrypting restores the original one"));
+    obfuscate_cb.set_active(aps_c.read().settings.obfuscate_names);
+    obfuscate_cb.connect_toggled(move |cb| {
+      aps_c.write().settings.obfuscate_names = cb.is_active();
+      aps_c.read().settings.export().unwrap();
+    });
+    grid.attach(&obfuscate_cb, 0, 7, 2, 1);
+  }
+
+  // What happens to outputs that already exist
+  {
+    let aps_c = aps.clone();
+    let conflict_lbl = gtk4::Label::new(Some("Existing files"));
+    conflict_lbl.set_halign(gtk4::Align::Start);
+
+    // Same order as ConflictPolicy
+    let conflict_dd = gtk4::DropDown::from_strings(&["Ask", "Skip", "Overwrite", "Keep both (rename)"]);
+    conflict_dd.set_tooltip_text(Some("Applies to encrypted files, decrypted files and every file unpacked into an existing directory"));
+    conflict_dd.set_selected(aps_c.read().settings.conflict as u32);
+    conflict_dd.connect_selected_notify(move |dd| {
+      aps_c.write().settings.conflict = ConflictPolicy::from_u8(dd.selected() as u8).unwrap_or_default();
+      aps_c.read().settings.export().unwrap();
+    });
+    grid.attach(&conflict_lbl, 0, 8, 1, 1);
+    grid.attach(&conflict_dd, 1, 8, 1, 1);
+  }
+
+  // Metadata stored in encrypted files
+  {
+    let aps_c = aps.clone();
+    let metadata_lbl = gtk4::Label::new(Some("Remember source"));
+    metadata_lbl.set_halign(gtk4::Align::Start);
+
+    let metadata_dd = gtk4::DropDown::from_strings(&["Nothing", "File name", "Full path"]);
+    metadata_dd.set_tooltip_text(Some("What the encrypted file stores about the original file"));
+    metadata_dd.set_selected(aps_c.read().settings.metadata as u32);
+    metadata_dd.connect_selected_notify(move |dd| {
+      aps_c.write().settings.metadata = MetadataPolicy::from_u8(dd.selected() as u8).unwrap_or_default();
+      aps_c.read().settings.export().unwrap();
+    });
+    grid.attach(&metadata_lbl, 0, 9, 1, 1);
+    grid.attach(&metadata_dd, 1, 9, 1, 1);
+  }
+
+  // Payload cipher suite, stored in every encrypted file
+  {
+    let aps_c = aps.clone();
+    let cipher_lbl = gtk4::Label::new(Some("Cipher"));
+    cipher_lbl.set_halign(gtk4::Align::Start);
+
+    let cipher_dd = gtk4::DropDown::from_strings(&CIPHERS.map(|v| v.1));
+    cipher_dd.set_tooltip_text(Some("XChaCha20-Poly1305 is recommended, the cascade stays secure if either cipher is ever broken"));
+    cipher_dd.set_selected(CIPHERS.iter().position(|v| v.0 == aps_c.read().settings.encryption).unwrap_or_default() as u32);
+    cipher_dd.connect_selected_notify(move |dd| {
+      aps_c.write().settings.encryption = CIPHERS.get(dd.selected() as usize).map(|v| v.0).unwrap_or(EncMethod::XChaCha20Poly1305);
+      aps_c.read().settings.export().unwrap();
+    });
+    grid.attach(&cipher_lbl, 0, 10, 1, 1);
+    grid.attach(&cipher_dd, 1, 10, 1, 1);
+  }
+
+  // Compression before encryption, stored in every encrypted file so decrypting needs no settings
+  {
+    let aps_c = aps.clone();
+    let compression_lbl = gtk4::Label::new(Some("Compression"));
+    compression_lbl.set_halign(gtk4::Align::Start);
+
+    // Same order as CompressionAlgorithm
+    let compression_dd = gtk4::DropDown::from_strings(&["Off", "zstd", "LZ4 (faster)"]);
+    compression_dd.set_tooltip_text(Some("Files that don't compress are stored as they are"));
+    compression_dd.set_selected(aps_c.read().settings.compression.algorithm as u32);
+    compression_dd.connect_selected_notify(move |dd| {
+      aps_c.write().settings.compression.algorithm = CompressionAlgorithm::from_u8(dd.selected() as u8).unwrap_or_default();
+      aps_c.read().settings.export().unwrap();
+    });
+    grid.attach(&compression_lbl, 0, 11, 1, 1);
+    grid.attach(&compression_dd, 1, 11, 1, 1);
+  }
+
+  {
+    let aps_c = aps.clone();
+    let level_lbl = gtk4::Label::new(Some("Compression level"));
+    level_lbl.set_halign(gtk4::Align::Start);
+
+    let level_sb = gtk4::SpinButton::with_range(1.0, 22.0, 1.0);
+    level_sb.set_tooltip_text(Some("Higher levels compress better and slower, only used by zstd"));
+    level_sb.set_value(aps_c.read().settings.compression.level as f64);
+    level_sb.connect_value_changed(move |sb| {
+      aps_c.write().settings.compression.level = sb.value_as_int();
+      aps_c.read().settings.export().unwrap();
+    });
+    grid.attach(&level_lbl, 0, 12, 1, 1);
+    grid.attach(&level_sb, 1, 12, 1, 1);
+  }
+
+  // Padding that hides the exact size of the encrypted file
+  {
+    let aps_c = aps.clone();
+    let padding_lbl = gtk4::Label::new(Some("Size padding"));
+    padding_lbl.set_halign(gtk4::Align::Start);
+
+    // Same order as PaddingScheme
+    let padding_dd = gtk4::DropDown::from_strings(&["Off", "PADMÉ (up to 12%)", "Power of two (up to 100%)"]);
+    padding_dd.set_tooltip_text(Some("Makes it harder to tell which file was encrypted from the size of the .snc file"));
+    padding_dd.set_selected(aps_c.read().settings.padding as u32);
+    padding_dd.connect_selected_notify(move |dd| {
+      aps_c.write().settings.padding = PaddingScheme::from_u8(dd.selected() as u8).unwrap_or_default();
+      aps_c.read().settings.export().unwrap();
+    });
+    grid.attach(&padding_lbl, 0, 13, 1, 1);
+    grid.attach(&padding_dd, 1, 13, 1, 1);
+  }
+
+  // Key derivation (Argon2), stored in every encrypted file so decrypting needs no settings
+  {
+    let aps_c = aps.clone();
+    let kdf_lbl = gtk4::Label::new(Some("Key derivation"));
+    kdf_lbl.set_halign(gtk4::Align::Start);
+
+    // Same order as KdfAlgorithm
+    let kdf_dd = gtk4::DropDown::from_strings(&["Argon2d", "Argon2i", "Argon2id"]);
+    kdf_dd.set_tooltip_text(Some("Argon2id is recommended"));
+    kdf_dd.set_selected(aps_c.read().settings.kdf.algorithm as u32);
+    kdf_dd.connect_selected_notify(move |dd| {
+      aps_c.write().settings.kdf.algorithm = KdfAlgorithm::from_u8(dd.selected() as u8).unwrap_or_default();
+      aps_c.read().settings.export().unwrap();
+    });
+    grid.attach(&kdf_lbl, 0, 14, 1, 1);
+    grid.attach(&kdf_dd, 1, 14, 1, 1);
+  }
+
+  {
+    let aps_c = aps.clone();
+    let memory_lbl = gtk4::Label::new(Some("Memory (MB)"));
+    memory_lbl.set_halign(gtk4::Align::Start);
+
+    let memory_sb = gtk4::SpinButton::with_range(8.0, 4096.0, 8.0);
+    memory_sb.set_tooltip_text(Some("More memory makes password guessing more expensive, every device that decrypts needs this much free RAM"));
+    memory_sb.set_value((aps_c.read().settings.kdf.memory_kib / 1024) as f64);
+    memory_sb.connect_value_changed(move |sb| {
+      aps_c.write().settings.kdf.memory_kib = sb.value_as_int() as u32 * 1024;
+      aps_c.read().settings.export().unwrap();
+    });
+    grid.attach(&memory_lbl, 0, 15, 1, 1);
+    grid.attach(&memory_sb, 1, 15, 1, 1);
+  }
+
+  {
+    let aps_c = aps.clone();
+    let iterations_lbl = gtk4::Label::new(Some("Iterations"));
+    iterations_lbl.set_halign(gtk4::Align::Start);
+
+    let iterations_sb = gtk4::SpinButton::with_range(1.0, 100.0, 1.0);
+    iterations_sb.set_value(aps_c.read().settings.kdf.iterations as f64);
+    iterations_sb.connect_value_changed(move |sb| {
+      aps_c.write().settings.kdf.iterations = sb.value_as_int() as u32;
+      aps_c.read().settings.export().unwrap();
+    });
+    grid.attach(&iterations_lbl, 0, 16, 1, 1);
+    grid.attach(&iterations_sb, 1, 16, 1, 1);
+  }
+
+  {
+    let aps_c = aps.clone();
+    let parallelism_lbl = gtk4::Label::new(Some("Parallelism"));
+    parallelism_lbl.set_halign(gtk4::Align::Start);
+
+    let parallelism_sb = gtk4::SpinButton::with_range(1.0, 64.0, 1.0);
+    parallelism_sb.set_value(aps_c.read().settings.kdf.parallelism as f64);
+    parallelism_sb.connect_value_changed(move |sb| {
+      aps_c.write().settings.kdf.parallelism = sb.value_as_int() as u32;
+      aps_c.read().settings.export().unwrap();
+    });
+    grid.attach(&parallelism_lbl, 0, 17, 1, 1);
+    grid.attach(&parallelism_sb, 1, 17, 1, 1);
+  }
+
+  // Threads encrypting and decrypting chunks in parallel
+  {
+    let aps_c = aps.clone();
+    let threads_lbl = gtk4::Label::new(Some("Cipher threads"));
+    threads_lbl.set_halign(gtk4::Align::Start);
+
+    let threads_sb = gtk4::SpinButton::with_range(0.0, 256.0, 1.0);
+    threads_sb.set_tooltip_text(Some("0 uses one thread per CPU core"));
+    threads_sb.set_value(aps_c.read().settings.threads as f64);
+    threads_sb.connect_value_changed(move |sb| {
+      aps_c.write().settings.threads = sb.value_as_int() as usize;
+      aps_c.read().settings.export().unwrap();
+    });
+    grid.attach(&threads_lbl, 0, 18, 1, 1);
+    grid.attach(&threads_sb, 1, 18, 1, 1);
   }
 
   {
     let window_c = window.clone();
 
+    let keyfile_btn = gtk4::Button::with_label("Generate keyfile 🎲");
+    keyfile_btn.set_hexpand(true);
+    keyfile_btn.connect_clicked(move |_| {
+      let file_dialog = gtk4::FileDialog::new();
+      file_dialog.set_title("Save new keyfile");
+      file_dialog.set_initial_name(Some("shincrypt.key"));
+      let window_c_c = window_c.clone();
+
+      file_dialog.save(Some(&window_c), gtk4::gio::Cancellable::NONE, move |result| match result {
+        Ok(v) => match ShinCrypt::gen_keyfile(v.path().unwrap_or_default()) {
+          Ok(_) => GTKhelper::message_box(&window_c_c, "Keyfile created", "Keep a backup of it, files encrypted with it can't be opened without it", None),
+          Err(e) => GTKhelper::message_box(&window_c_c, "Error", e, None),
+        },
+        Err(err) => GTKhelper::dialog_error(&window_c_c, err),
+      });
+    });
+    grid.attach(&keyfile_btn, 0, 19, 2, 1);
+  }
+
+  {
+    let window_c = window.clone();
+
+    let identity_btn = gtk4::Button::with_label("Generate identity 🪪");
+    identity_btn.set_hexpand(true);
+    identity_btn.set_tooltip_text(Some("Key pair for encrypting without a password, share the .pub file and keep the identity secret"));
+    identity_btn.connect_clicked(move |_| {
+      let file_dialog = gtk4::FileDialog::new();
+      file_dialog.set_title("Save new identity");
+      file_dialog.set_initial_name(Some("shincrypt.identity"));
+      let window_c_c = window_c.clone();
+
+      file_dialog.save(Some(&window_c), gtk4::gio::Cancellable::NONE, move |result| match result {
+        Ok(v) => {
+          let path = v.path().unwrap_or_default();
+          let public_path = std::path::PathBuf::from(format!("{}.pub", path.display()));
+          let identity = match Identity::generate() {
+            Ok(v) => v,
+            Err(e) => return GTKhelper::message_box(&window_c_c, "Error", e, None),
+          };
+
+          match identity.save(&path).and_then(|_| identity.recipient().save(&public_path)) {
+            Ok(_) => {
+              window_c_c.clipboard().set_text(&identity.recipient().to_string());
+              GTKhelper::message_box(&window_c_c, "Identity created", format!("Public key (copied to clipboard):\n{}\n\nShare {:?} with people who encrypt for you. Keep a backup of the identity, files encrypted for it can't be opened without it", identity.recipient(), public_path), None)
+            }
+            Err(e) => GTKhelper::message_box(&window_c_c, "Error", e, None),
+          }
+        }
+        Err(err) => GTKhelper::dialog_error(&window_c_c, err),
+      });
+    });
+    grid.attach(&identity_btn, 0, 20, 2, 1);
+  }
+
+  {
+    let window_c = window.clone();
+    let aps_c = aps.clone();
+
     let benchmark_btn = gtk4::Button::with_label("Benchmark 🚝");
     benchmark_btn.set_hexpand(true);
     benchmark_btn.connect_clicked(move |_| {
-      match std::thread::Builder::new().stack_size(SIZE_1MB * 4).spawn(|| ShinCrypt::benchmark()).unwrap().join().unwrap() {
-        Ok((e_time, d_time)) => GTKhelper::message_box(&window_c, "Done", format!("Encrypted 1GB:\n\nTime: {}\nSpeed: {:.2} MB/s\n\nDecrypted 1GB:\n\nTime: {}\nSpeed: {:.2} MB/s\n", Global::format_duration(e_time), Global::calculate_speed(1.0, e_time), Global::format_duration(d_time), Global::calculate_speed(1.0, d_time)), None),
-        Err(e) => GTKhelper::message_box(&window_c, "Error", e, None),
+      let threads = ShinCrypt::resolve_threads(aps_c.read().settings.threads);
+      match std::thread::Builder::new().stack_size(SIZE_1MB * 4).spawn(move || ShinCrypt::benchmark(threads)).unwrap().join().unwrap() {
+        Ok((e_time, d_time)) => GTKhelper::message_box(&window_c, "Done", format!("Threads: {}\n\nEncrypted 1GB:\n\nTime: {}\nSpeed: {:.2} MB/s\n\nDecrypted 1GB:\n\nTime: {}\nSpeed: {:.2} MB/s\n", threads, Global::format_duration(e_time), Global::calculate_speed(1.0, e_time), Global::format_duration(d_time), Global::calculate_speed(1.0, d_time)), None),
+        Err(e) => GTKhelper::error_box(&window_c, &e),
       };
     });
-    grid.attach(&benchmark_btn, 0, 3, 1, 1);
+    grid.attach(&benchmark_btn, 0, 21, 1, 1);
   }
 
   {
@@ -118,7 +455,7 @@ pub fn settings_ui(window: &gtk4::ApplicationWindow, aps: Arc<RwLock<AppState>>)
     about_btn.connect_clicked(move |_| {
       about_win(&window_c, aps_c.clone());
     });
-    grid.attach(&about_btn, 1, 3, 1, 1);
+    grid.attach(&about_btn, 1, 21, 1, 1);
   }
 
   settings_win.set_child(Some(&grid));
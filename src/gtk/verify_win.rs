@@ -0,0 +1,57 @@
+use crate::{AppState, gtk::gtk_ui::MarginAll, logic::encryption::{VerifyReport, VerifyStatus}};
+use gtk::prelude::*;
+use gtk4 as gtk;
+use parking_lot::RwLock;
+use std::sync::Arc;
+
+/// Results of a verified batch, one row per file with what was found. The full error is in the tooltip
+pub fn verify_win(window: &gtk::ApplicationWindow, aps: Arc<RwLock<AppState>>, reports: Vec<VerifyReport>) {
+  let consts = aps.read().consts.clone();
+
+  let verify_win = gtk::ApplicationWindow::builder().transient_for(window).modal(true).resizable(true).title("Verification").default_width(450).default_height(400).build();
+
+  let grid = gtk::Grid::new();
+  grid.set_row_spacing(consts.upad);
+  grid.set_column_spacing(consts.upad);
+  grid.set_margin_all(consts.margin);
+  verify_win.set_child(Some(&grid));
+
+  let count = |status: VerifyStatus| reports.iter().filter(|v| v.status() == status).count();
+  let summary = [VerifyStatus::Ok, VerifyStatus::WrongPassword, VerifyStatus::Corrupt, VerifyStatus::Failed].iter().map(|v| (v, count(*v))).filter(|v| v.1 > 0).map(|(v, n)| format!("{}: {}", v.label(), n)).collect::<Vec<_>>().join(" · ");
+  let summary_lbl = gtk::Label::new(Some(&summary));
+  summary_lbl.set_halign(gtk::Align::Start);
+  grid.attach(&summary_lbl, 0, 0, 1, 1);
+
+  let list = gtk::ListBox::new();
+  list.set_selection_mode(gtk::SelectionMode::None);
+
+  for report in &reports {
+    let row = gtk::Box::new(gtk::Orientation::Horizontal, consts.upad as i32);
+
+    let name_lbl = gtk::Label::new(Some(&report.path.display().to_string()));
+    name_lbl.set_halign(gtk::Align::Start);
+    name_lbl.set_hexpand(true);
+    name_lbl.set_ellipsize(gtk::pango::EllipsizeMode::Middle);
+    row.append(&name_lbl);
+
+    let status = report.status();
+    let icon = match status {
+      VerifyStatus::Ok => "✅",
+      VerifyStatus::WrongPassword => "🔑",
+      VerifyStatus::Corrupt => "❌",
+      VerifyStatus::Failed => "⚠️",
+    };
+    let status_lbl = gtk::Label::new(Some(&format!("{} {}", icon, status.label())));
+    if let Err(e) = &report.result {
+      status_lbl.set_tooltip_text(Some(&e.to_string()));
+    }
+    row.append(&status_lbl);
+
+    list.append(&row);
+  }
+
+  let scroll = gtk::ScrolledWindow::builder().child(&list).hexpand(true).vexpand(true).build();
+  grid.attach(&scroll, 0, 1, 1, 1);
+
+  verify_win.present();
+}
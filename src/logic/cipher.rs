@@ -0,0 +1,233 @@
+use crate::logic::{encryption::{EncMethod, ShinCrypt}, secret::SecretKey};
+use chacha20::cipher::{KeyIvInit, StreamCipher, StreamCipherSeek};
+use chacha20poly1305::aead::{AeadInPlace, KeyInit, generic_array::typenum::Unsigned};
+use std::sync::Arc;
+
+static CHUNK: u64 = crate::SIZE_1MB as u64; // Plaintext bytes per chunk, also the keystream distance between chunks
+static TAG_SIZE: usize = 16; // Poly1305 and GHASH tags
+static CASCADE_INNER_CONTEXT: &[u8] = b"ShinCrypt cascade AES-256-GCM key";
+static CASCADE_OUTER_CONTEXT: &[u8] = b"ShinCrypt cascade XChaCha20-Poly1305 key";
+
+/// A payload cipher suite, one implementation per EncMethod. Chunks are processed on their own, the chunk
+/// index and the last chunk flag are all the state there is, so the file I/O code works the same for every suite
+pub trait ChunkCipher: Send + Sync {
+  /// Bytes every chunk grows by on disk
+  fn overhead(&self) -> usize;
+
+  /// Authenticated suites end the stream with a chunk flagged as last, so truncation can be detected
+  fn authenticated(&self) -> bool;
+
+  fn encrypt(&self, index: u64, last: bool, chunk: &mut Vec<u8>) -> std::io::Result<()>;
+
+  fn decrypt(&self, index: u64, last: bool, chunk: &mut Vec<u8>) -> std::io::Result<()>;
+}
+
+/// The cipher suite for `method`, keyed with the payload key and the nonce stored in the file. Shared, worker threads encrypt chunks in parallel
+pub fn payload_cipher(method: EncMethod, key: &[u8; 32], nonce: &[u8; 24]) -> Arc<dyn ChunkCipher> {
+  match method {
+    EncMethod::XChaCha20 => Arc::new(XChaCha20::new(key, nonce, 0)),
+    EncMethod::XChaCha20Poly1305 => Arc::new(StreamAead::<chacha20poly1305::XChaCha20Poly1305>::new(key, nonce)),
+    EncMethod::XChaCha12Poly1305 => Arc::new(StreamAead::<chacha20poly1305::XChaCha12Poly1305>::new(key, nonce)),
+    EncMethod::Aes256GcmXChaCha20Poly1305 => Arc::new(Cascade {
+      inner: StreamAead::new(&ShinCrypt::subkey(key, CASCADE_INNER_CONTEXT), nonce),
+      outer: StreamAead::new(&ShinCrypt::subkey(key, CASCADE_OUTER_CONTEXT), nonce),
+    }),
+  }
+}
+
+/// v1 payloads continue the keystream of the header, `offset` bytes into it
+pub fn legacy_cipher(key: &[u8; 32], nonce: &[u8; 24], offset: u64) -> Arc<dyn ChunkCipher> { Arc::new(XChaCha20::new(key, nonce, offset)) }
+
+/// Plain XChaCha20 keystream, unauthenticated. Kept for v1 files
+struct XChaCha20 {
+  key: SecretKey,
+  nonce: [u8; 24],
+  offset: u64,
+}
+
+impl XChaCha20 {
+  fn new(key: &[u8; 32], nonce: &[u8; 24], offset: u64) -> Self { Self { key: SecretKey::from(key), nonce: *nonce, offset } }
+
+  fn apply(&self, index: u64, chunk: &mut [u8]) -> std::io::Result<()> {
+    let mut cipher = chacha20::XChaCha20::new((&*self.key).into(), &self.nonce.into());
+    cipher.try_seek(self.offset + index * CHUNK).map_err(|_| std::io::Error::other("Encrypted stream is too long"))?;
+    cipher.try_apply_keystream(chunk).map_err(|_| std::io::Error::other("Encrypted stream is too long"))
+  }
+}
+
+impl ChunkCipher for XChaCha20 {
+  fn overhead(&self) -> usize { 0 }
+
+  fn authenticated(&self) -> bool { false }
+
+  fn encrypt(&self, index: u64, _last: bool, chunk: &mut Vec<u8>) -> std::io::Result<()> { self.apply(index, chunk) }
+
+  fn decrypt(&self, index: u64, _last: bool, chunk: &mut Vec<u8>) -> std::io::Result<()> { self.apply(index, chunk) }
+}
+
+/// Any AEAD in the STREAM construction. Every chunk is sealed under its own nonce (file nonce prefix +
+/// chunk counter + last chunk flag), so modified, reordered, dropped or truncated chunks fail to open
+struct StreamAead<A> {
+  aead: A,
+  nonce_prefix: Vec<u8>,
+}
+
+impl<A: AeadInPlace + KeyInit> StreamAead<A> {
+  fn new(key: &[u8; 32], nonce: &[u8; 24]) -> Self {
+    // Counter (4 bytes) and last flag (1 byte) take the end of the nonce
+    let prefix_len = A::NonceSize::to_usize() - 5;
+    Self { aead: A::new_from_slice(key).expect("All suites use 256 bit keys"), nonce_prefix: nonce[..prefix_len].to_vec() }
+  }
+
+  fn nonce(&self, index: u64, last: bool) -> std::io::Result<chacha20poly1305::aead::Nonce<A>> {
+    let counter = u32::try_from(index).map_err(|_| std::io::Error::other("Too many chunks in encrypted stream"))?;
+    let mut nonce = chacha20poly1305::aead::Nonce::<A>::default();
+    let prefix_len = self.nonce_prefix.len();
+    nonce[..prefix_len].copy_from_slice(&self.nonce_prefix);
+    nonce[prefix_len..prefix_len + 4].copy_from_slice(&counter.to_be_bytes());
+    nonce[prefix_len + 4] = last as u8;
+    Ok(nonce)
+  }
+}
+
+impl<A: AeadInPlace + KeyInit + Send + Sync> ChunkCipher for StreamAead<A> {
+  fn overhead(&self) -> usize { TAG_SIZE }
+
+  fn authenticated(&self) -> bool { true }
+
+  fn encrypt(&self, index: u64, last: bool, chunk: &mut Vec<u8>) -> std::io::Result<()> { self.aead.encrypt_in_place(&self.nonce(index, last)?, b"", chunk).map_err(|_| std::io::Error::other("Failed to encrypt chunk")) }
+
+  fn decrypt(&self, index: u64, last: bool, chunk: &mut Vec<u8>) -> std::io::Result<()> { self.aead.decrypt_in_place(&self.nonce(index, last)?, b"", chunk).map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, "Authentication failed, the file is corrupted, truncated or has been tampered with")) }
+}
+
+/// Two unrelated designs under independent keys, the data stays protected as long as either of them holds
+struct Cascade {
+  inner: StreamAead<aes_gcm::Aes256Gcm>,
+  outer: StreamAead<chacha20poly1305::XChaCha20Poly1305>,
+}
+
+impl ChunkCipher for Cascade {
+  fn overhead(&self) -> usize { self.inner.overhead() + self.outer.overhead() }
+
+  fn authenticated(&self) -> bool { true }
+
+  fn encrypt(&self, index: u64, last: bool, chunk: &mut Vec<u8>) -> std::io::Result<()> {
+    self.inner.encrypt(index, last, chunk)?;
+    self.outer.encrypt(index, last, chunk)
+  }
+
+  fn decrypt(&self, index: u64, last: bool, chunk: &mut Vec<u8>) -> std::io::Result<()> {
+    self.outer.decrypt(index, last, chunk)?;
+    self.inner.decrypt(index, last, chunk)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  static SUITES: [EncMethod; 4] = [EncMethod::XChaCha20, EncMethod::XChaCha20Poly1305, EncMethod::XChaCha12Poly1305, EncMethod::Aes256GcmXChaCha20Poly1305];
+
+  fn suite(method: EncMethod) -> Arc<dyn ChunkCipher> { payload_cipher(method, &[7u8; 32], &[9u8; 24]) }
+
+  #[test]
+  fn round_trip_every_suite() {
+    for method in SUITES {
+      let cipher = suite(method);
+      let plain = (0..1000u32).map(|v| v as u8).collect::<Vec<_>>();
+      let mut chunk = plain.clone();
+      cipher.encrypt(3, true, &mut chunk).unwrap();
+      assert_eq!(chunk.len(), plain.len() + cipher.overhead(), "{:?}", method);
+      assert_ne!(chunk[..plain.len()], plain[..], "{:?}", method);
+      cipher.decrypt(3, true, &mut chunk).unwrap();
+      assert_eq!(chunk, plain, "{:?}", method);
+    }
+  }
+
+  #[test]
+  fn overhead_and_authentication() {
+    assert_eq!((suite(EncMethod::XChaCha20).overhead(), suite(EncMethod::XChaCha20).authenticated()), (0, false));
+    assert_eq!((suite(EncMethod::XChaCha20Poly1305).overhead(), suite(EncMethod::XChaCha20Poly1305).authenticated()), (TAG_SIZE, true));
+    assert_eq!((suite(EncMethod::XChaCha12Poly1305).overhead(), suite(EncMethod::XChaCha12Poly1305).authenticated()), (TAG_SIZE, true));
+    assert_eq!((suite(EncMethod::Aes256GcmXChaCha20Poly1305).overhead(), suite(EncMethod::Aes256GcmXChaCha20Poly1305).authenticated()), (2 * TAG_SIZE, true));
+  }
+
+  // Counter and last flag take the end of the nonce, the rest comes from the file nonce
+  #[test]
+  fn stream_nonce_layout() {
+    let stream = StreamAead::<chacha20poly1305::XChaCha20Poly1305>::new(&[1u8; 32], &[9u8; 24]);
+    let nonce = stream.nonce(0x0102_0304, true).unwrap();
+    assert_eq!(nonce[..19], [9u8; 19]);
+    assert_eq!(nonce[19..23], [1, 2, 3, 4]);
+    assert_eq!(nonce[23], 1);
+    assert_eq!(stream.nonce(0x0102_0304, false).unwrap()[23], 0);
+
+    let gcm = StreamAead::<aes_gcm::Aes256Gcm>::new(&[1u8; 32], &[9u8; 24]);
+    let nonce = gcm.nonce(5, true).unwrap();
+    assert_eq!(nonce.len(), 12);
+    assert_eq!(nonce[..7], [9u8; 7]);
+    assert_eq!(nonce[7..], [0, 0, 0, 5, 1]);
+
+    assert!(stream.nonce(u32::MAX as u64 + 1, false).is_err());
+  }
+
+  // A chunk only opens at its own position and with its own last flag, so truncated and reordered streams fail
+  #[test]
+  fn chunk_bound_to_index_and_last_flag() {
+    for method in SUITES.into_iter().filter(|v| *v != EncMethod::XChaCha20) {
+      let cipher = suite(method);
+      let mut sealed = b"chunk".to_vec();
+      cipher.encrypt(1, false, &mut sealed).unwrap();
+
+      for (index, last) in [(1, true), (0, false), (2, false)] {
+        let mut chunk = sealed.clone();
+        let e = cipher.decrypt(index, last, &mut chunk).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData, "{:?}", method);
+      }
+
+      let mut flipped = sealed.clone();
+      flipped[0] ^= 1;
+      assert_eq!(cipher.decrypt(1, false, &mut flipped).unwrap_err().kind(), std::io::ErrorKind::InvalidData, "{:?}", method);
+
+      let mut chunk = sealed.clone();
+      cipher.decrypt(1, false, &mut chunk).unwrap();
+      assert_eq!(chunk, b"chunk");
+    }
+  }
+
+  // v1 payloads are one keystream, chunk n starts CHUNK * n bytes after the offset
+  #[test]
+  fn legacy_keystream_is_continuous() {
+    let (key, nonce, offset) = ([3u8; 32], [4u8; 24], 100);
+    let mut whole = vec![0u8; CHUNK as usize + 10];
+    let mut stream = chacha20::XChaCha20::new(&key.into(), &nonce.into());
+    stream.seek(offset);
+    stream.apply_keystream(&mut whole);
+
+    let cipher = legacy_cipher(&key, &nonce, offset);
+    let mut first = vec![0u8; CHUNK as usize];
+    let mut second = vec![0u8; 10];
+    cipher.encrypt(0, false, &mut first).unwrap();
+    cipher.encrypt(1, true, &mut second).unwrap();
+    assert_eq!(first[..], whole[..CHUNK as usize]);
+    assert_eq!(second[..], whole[CHUNK as usize..]);
+  }
+
+  // The cascade layers are keyed independently, neither layer alone opens the chunk
+  #[test]
+  fn cascade_layers_use_separate_keys() {
+    let (key, nonce) = ([5u8; 32], [6u8; 24]);
+    let cipher = payload_cipher(EncMethod::Aes256GcmXChaCha20Poly1305, &key, &nonce);
+    let mut sealed = b"layers".to_vec();
+    cipher.encrypt(0, true, &mut sealed).unwrap();
+
+    let outer = StreamAead::<chacha20poly1305::XChaCha20Poly1305>::new(&key, &nonce);
+    assert!(outer.decrypt(0, true, &mut sealed.clone()).is_err());
+
+    let outer = StreamAead::<chacha20poly1305::XChaCha20Poly1305>::new(&ShinCrypt::subkey(&key, CASCADE_OUTER_CONTEXT), &nonce);
+    let inner = StreamAead::<aes_gcm::Aes256Gcm>::new(&ShinCrypt::subkey(&key, CASCADE_INNER_CONTEXT), &nonce);
+    outer.decrypt(0, true, &mut sealed).unwrap();
+    inner.decrypt(0, true, &mut sealed).unwrap();
+    assert_eq!(sealed, b"layers");
+  }
+}
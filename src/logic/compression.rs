@@ -0,0 +1,177 @@
+use crate::logic::encryption::{CompressionAlgorithm, CompressionParams};
+use std::io::{Read, Write};
+
+static SAMPLE_SIZE: usize = crate::SIZE_1MB; // Input compressed up front to decide whether compression pays off
+static MAX_RATIO_PERCENT: usize = 95; // Compressed sample size above which the input is stored raw
+
+/// A streaming compressor, compressed data is appended to `out` as it becomes available
+pub trait Compressor {
+  fn compress(&mut self, data: &[u8], out: &mut Vec<u8>) -> std::io::Result<()>;
+
+  /// Ends the stream, whatever the compressor still holds is appended to `out`
+  fn finish(self: Box<Self>, out: &mut Vec<u8>) -> std::io::Result<()>;
+}
+
+/// The compressor for `params`, None when compression is off
+pub fn compressor(params: CompressionParams) -> std::io::Result<Option<Box<dyn Compressor>>> {
+  match params.algorithm {
+    CompressionAlgorithm::None => Ok(None),
+    CompressionAlgorithm::Zstd => Ok(Some(Box::new(Zstd(zstd::stream::write::Encoder::new(Vec::new(), params.level)?)))),
+    CompressionAlgorithm::Lz4 => Ok(Some(Box::new(Lz4(lz4_flex::frame::FrameEncoder::new(Vec::new()))))),
+  }
+}
+
+/// Wraps the decrypted stream, passes it through unchanged when it wasn't compressed
+pub fn decompressor<'a>(algorithm: CompressionAlgorithm, reader: impl Read + 'a) -> std::io::Result<Box<dyn Read + 'a>> {
+  match algorithm {
+    CompressionAlgorithm::None => Ok(Box::new(reader)),
+    CompressionAlgorithm::Zstd => Ok(Box::new(zstd::stream::read::Decoder::new(reader)?)),
+    CompressionAlgorithm::Lz4 => Ok(Box::new(lz4_flex::frame::FrameDecoder::new(reader))),
+  }
+}
+
+/// Compresses a sample of the input, media, archives and encrypted data barely shrink and are better stored raw
+pub fn worth_compressing(params: CompressionParams, input: &std::path::Path) -> std::io::Result<bool> {
+  let Some(mut compressor) = compressor(params)? else {
+    return Ok(false);
+  };
+
+  let mut sample = Vec::new();
+  read_sample(input, &mut sample, true)?;
+  if sample.is_empty() {
+    return Ok(false);
+  }
+
+  let mut compressed = Vec::new();
+  compressor.compress(&sample, &mut compressed)?;
+  compressor.finish(&mut compressed)?;
+
+  Ok(compressed.len() * 100 < sample.len() * MAX_RATIO_PERCENT)
+}
+
+// The start of the file, or of the files in the directory until the sample is full. Links inside a directory aren't followed
+fn read_sample(path: &std::path::Path, sample: &mut Vec<u8>, top: bool) -> std::io::Result<()> {
+  if sample.len() >= SAMPLE_SIZE {
+    return Ok(());
+  }
+
+  let metadata = if top { std::fs::metadata(path)? } else { std::fs::symlink_metadata(path)? };
+  if metadata.is_dir() {
+    for entry in std::fs::read_dir(path)? {
+      read_sample(&entry?.path(), sample, false)?;
+    }
+  } else if metadata.is_file() {
+    std::fs::File::open(path)?.take((SAMPLE_SIZE - sample.len()) as u64).read_to_end(sample)?;
+  }
+
+  Ok(())
+}
+
+struct Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>);
+
+impl Compressor for Zstd {
+  fn compress(&mut self, data: &[u8], out: &mut Vec<u8>) -> std::io::Result<()> {
+    self.0.write_all(data)?;
+    out.append(self.0.get_mut());
+    Ok(())
+  }
+
+  fn finish(self: Box<Self>, out: &mut Vec<u8>) -> std::io::Result<()> {
+    out.append(&mut self.0.finish()?);
+    Ok(())
+  }
+}
+
+struct Lz4(lz4_flex::frame::FrameEncoder<Vec<u8>>);
+
+impl Compressor for Lz4 {
+  fn compress(&mut self, data: &[u8], out: &mut Vec<u8>) -> std::io::Result<()> {
+    self.0.write_all(data)?;
+    out.append(self.0.get_mut());
+    Ok(())
+  }
+
+  fn finish(self: Box<Self>, out: &mut Vec<u8>) -> std::io::Result<()> {
+    out.append(&mut self.0.finish().map_err(std::io::Error::other)?);
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::logic::test_util::TestDir;
+
+  fn params(algorithm: CompressionAlgorithm) -> CompressionParams { CompressionParams { algorithm, ..CompressionParams::default() } }
+
+  fn text(len: usize) -> Vec<u8> { b"The quick brown fox jumps over the lazy dog. ".iter().cycle().take(len).copied().collect() }
+
+  fn random(len: usize) -> Vec<u8> {
+    let mut data = vec![0u8; len];
+    getrandom::fill(&mut data).unwrap();
+    data
+  }
+
+  // Feeds the data in uneven pieces, like the chunks of a real job
+  fn compress(algorithm: CompressionAlgorithm, data: &[u8]) -> Vec<u8> {
+    let mut compressor = compressor(params(algorithm)).unwrap().unwrap();
+    let mut out = Vec::new();
+    for piece in data.chunks(7777) {
+      compressor.compress(piece, &mut out).unwrap();
+    }
+    compressor.finish(&mut out).unwrap();
+    out
+  }
+
+  fn decompress(algorithm: CompressionAlgorithm, data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    decompressor(algorithm, data)?.read_to_end(&mut out)?;
+    Ok(out)
+  }
+
+  #[test]
+  fn round_trip() {
+    for algorithm in [CompressionAlgorithm::Zstd, CompressionAlgorithm::Lz4] {
+      for data in [Vec::new(), text(1), text(100_000), random(20_000)] {
+        let compressed = compress(algorithm, &data);
+        assert_eq!(decompress(algorithm, &compressed).unwrap(), data, "{:?}", algorithm);
+      }
+      assert!(compress(algorithm, &text(100_000)).len() < 10_000, "{:?}", algorithm);
+    }
+  }
+
+  #[test]
+  fn none_passes_through() {
+    assert!(compressor(params(CompressionAlgorithm::None)).unwrap().is_none());
+    assert_eq!(decompress(CompressionAlgorithm::None, &text(1000)).unwrap(), text(1000));
+  }
+
+  #[test]
+  fn damaged_stream_fails() {
+    for algorithm in [CompressionAlgorithm::Zstd, CompressionAlgorithm::Lz4] {
+      let compressed = compress(algorithm, &text(100_000));
+      assert!(decompress(algorithm, &compressed[..compressed.len() / 2]).is_err(), "{:?}", algorithm);
+      assert!(decompress(algorithm, &random(1000)).is_err(), "{:?}", algorithm);
+    }
+  }
+
+  #[test]
+  fn worth_compressing_only_text() {
+    let dir = TestDir::new();
+    let (text_dir, random_dir) = (dir.subdir("text"), dir.subdir("random"));
+    let text = dir.write("text/input", &text(100_000));
+    let random = dir.write("random/input", &random(100_000));
+    let empty = dir.write("empty", &[]);
+    for algorithm in [CompressionAlgorithm::Zstd, CompressionAlgorithm::Lz4] {
+      assert!(worth_compressing(params(algorithm), &text).unwrap(), "{:?}", algorithm);
+      assert!(!worth_compressing(params(algorithm), &random).unwrap(), "{:?}", algorithm);
+      assert!(!worth_compressing(params(algorithm), &empty).unwrap(), "{:?}", algorithm);
+    }
+    assert!(!worth_compressing(params(CompressionAlgorithm::None), &text).unwrap());
+
+    // A directory is judged by the files in it
+    assert!(worth_compressing(params(CompressionAlgorithm::Zstd), &text_dir).unwrap());
+    assert!(!worth_compressing(params(CompressionAlgorithm::Zstd), &random_dir).unwrap());
+    assert!(worth_compressing(params(CompressionAlgorithm::Zstd), &dir.join("missing")).is_err());
+  }
+}
@@ -1,174 +1,641 @@
-use crate::{APPNAME, SIZE_1MB, logic::global::FileDir};
-use argon2::password_hash::PasswordHasher;
+use crate::{APPNAME, SIZE_1MB, logic::{cipher::{ChunkCipher, legacy_cipher, payload_cipher}, compression::{Compressor, compressor, decompressor, worth_compressing}, error::ShinError, global::FileDir, identity::{Identity, Recipient, STANZA_SIZE}, output::{ConflictPolicy, TempOutput, exists}, progress::{Progress, ProgressEvent, ProgressPhase}, secret::{NoCoreDumps, Secret, SecretKey}}};
+use blake2::digest::{Digest, KeyInit, Mac};
 use chacha20::cipher::{KeyIvInit, StreamCipher};
-use std::{io::{BufRead, Read, Write}, u16};
-
-static FILE_HEADER_SIZE: usize = SIZE_1MB; // 1 MB
+use chacha20poly1305::aead::AeadInPlace;
+use serde::{Deserialize, Serialize};
+use std::{io::{BufRead, Read, Seek, Write}, u16};
+use zeroize::Zeroizing;
+
+static FILE_HEADER_SIZE: usize = SIZE_1MB; // 1 MB, size of the padded v1 header and upper bound for v2 headers
+static MAGIC: &[u8] = b"SNC";
+const SLOT_EMPTY: u8 = 0;
+const SLOT_PASSWORD: u8 = 1;
+const SLOT_RECIPIENT: u8 = 2;
+const SLOT_SIZE: usize = 128; // Fixed, so a slot can be rewritten in place
+const WRAPPED_KEY_SIZE: usize = 32 + 16; // Data key + Poly1305 tag
+static KEY_SLOTS: usize = 8; // Minimum number of slots in a file, free ones are there to add keys later
+static KEY_FLAG_PASSWORD: u8 = 0b01;
+static KEY_FLAG_KEYFILE: u8 = 0b10;
+static KEYFILE_SIZE: usize = 64; // Generated keyfiles, any file can be used as one
+static MAX_KDF_MEMORY_KIB: u32 = 4 * 1024 * 1024; // 4 GB
+static MAX_KDF_ITERATIONS: u32 = 1024; // Well above the settings' 100, a crafted file could otherwise hash for hours
+static MAX_KDF_PARALLELISM: u32 = 256; // Argon2 lanes, above the settings' 64
+static HEADER_CONTEXT: &[u8] = b"ShinCrypt header key";
+static PAYLOAD_CONTEXT: &[u8] = b"ShinCrypt payload key";
+static KEY_CHECK_CONTEXT: &[u8] = b"ShinCrypt key check";
+static KEY_CHECK_SIZE: usize = 32;
+static V1_FIELDS_MAX: usize = 5 * 2 + 2 * u16::MAX as usize; // Fixed fields plus the longest name and path, the rest of a v1 header is zeros
 
 static FILE_1GB: usize = 1024 * 1024 * 1024; // 1 GB
 static CHUNK: usize = SIZE_1MB; // 1 MB
 // static CHUNK_4KB: usize = 0x1000; // 4 KB
 
 static NONCE_SIZE: usize = 24;
+static TAG_SIZE: usize = 16; // Poly1305 tag of the sealed FileHeader
 static ENCRYPTION_EXT: &str = "snc";
 static BENCHMARK_EXT: &str = "benchmark";
-static ENCRYPTION_VERSION: u16 = 1;
+pub(crate) static ENCRYPTION_VERSION: u16 = 3;
 
+/// Payload cipher suite, see logic::cipher for the implementations
 #[repr(u16)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
 pub enum EncMethod {
-  #[default]
+  /// Unauthenticated, only read for v1 files
   XChaCha20 = 1,
+  #[default]
+  XChaCha20Poly1305 = 2,
+  /// 12 instead of 20 rounds, faster with a smaller security margin
+  XChaCha12Poly1305 = 3,
+  /// AES-256-GCM inside XChaCha20-Poly1305, each under its own key
+  Aes256GcmXChaCha20Poly1305 = 4,
 }
 
 impl EncMethod {
   pub fn from_u16(num: u16) -> Option<Self> {
     match num {
       1 => Some(EncMethod::XChaCha20),
+      2 => Some(EncMethod::XChaCha20Poly1305),
+      3 => Some(EncMethod::XChaCha12Poly1305),
+      4 => Some(EncMethod::Aes256GcmXChaCha20Poly1305),
+      _ => None,
+    }
+  }
+}
+
+/// What an encrypted file remembers about its source
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum MetadataPolicy {
+  /// Nothing, decrypting restores the name from the .snc file name
+  #[default]
+  None = 0,
+  /// Only the file or directory name
+  Name = 1,
+  /// The full source path, every v1 file stored this
+  FullPath = 2,
+}
+
+impl MetadataPolicy {
+  pub fn from_u8(num: u8) -> Option<Self> {
+    match num {
+      0 => Some(MetadataPolicy::None),
+      1 => Some(MetadataPolicy::Name),
+      2 => Some(MetadataPolicy::FullPath),
+      _ => None,
+    }
+  }
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum KdfAlgorithm {
+  Argon2d = 0,
+  Argon2i = 1,
+  #[default]
+  Argon2id = 2,
+}
+
+impl KdfAlgorithm {
+  pub fn from_u8(num: u8) -> Option<Self> {
+    match num {
+      0 => Some(KdfAlgorithm::Argon2d),
+      1 => Some(KdfAlgorithm::Argon2i),
+      2 => Some(KdfAlgorithm::Argon2id),
+      _ => None,
+    }
+  }
+}
+
+/// Argon2 settings used to derive the key from the password, stored in every v2 file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct KdfParams {
+  pub algorithm: KdfAlgorithm,
+  pub memory_kib: u32,
+  pub iterations: u32,
+  pub parallelism: u32,
+}
+
+impl Default for KdfParams {
+  // Same as argon2::Argon2::default(), which is what v1 files used
+  fn default() -> Self { Self { algorithm: KdfAlgorithm::default(), memory_kib: argon2::Params::DEFAULT_M_COST, iterations: argon2::Params::DEFAULT_T_COST, parallelism: argon2::Params::DEFAULT_P_COST } }
+}
+
+impl KdfParams {
+  /// The secret (hashed keyfiles) is mixed into the derivation next to the password
+  pub fn argon2<'a>(&self, secret: Option<&'a [u8]>) -> Result<argon2::Argon2<'a>, String> {
+    self.check_limits()?;
+
+    let algorithm = match self.algorithm {
+      KdfAlgorithm::Argon2d => argon2::Algorithm::Argon2d,
+      KdfAlgorithm::Argon2i => argon2::Algorithm::Argon2i,
+      KdfAlgorithm::Argon2id => argon2::Algorithm::Argon2id,
+    };
+    let params = argon2::Params::new(self.memory_kib, self.iterations, self.parallelism, None).map_err(|e| format!("Invalid Argon2 parameters: {}", e))?;
+
+    match secret {
+      Some(secret) => argon2::Argon2::new_with_secret(secret, algorithm, argon2::Version::V0x13, params).map_err(|e| format!("Invalid keyfile secret: {}", e)),
+      None => Ok(argon2::Argon2::new(algorithm, argon2::Version::V0x13, params)),
+    }
+  }
+
+  /// Parameters come from the file when decrypting, a crafted one must not allocate unbounded memory or hash forever
+  pub fn check_limits(&self) -> Result<(), String> {
+    if self.memory_kib > MAX_KDF_MEMORY_KIB {
+      return Err(format!("Argon2 memory cost of {} MiB is above the {} MiB limit", self.memory_kib / 1024, MAX_KDF_MEMORY_KIB / 1024));
+    }
+    if self.iterations > MAX_KDF_ITERATIONS {
+      return Err(format!("Argon2 iterations of {} are above the limit of {}", self.iterations, MAX_KDF_ITERATIONS));
+    }
+    if self.parallelism > MAX_KDF_PARALLELISM {
+      return Err(format!("Argon2 parallelism of {} is above the limit of {}", self.parallelism, MAX_KDF_PARALLELISM));
+    }
+    Ok(())
+  }
+}
+
+/// Compression applied before encryption, see logic::compression for the implementations
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum CompressionAlgorithm {
+  #[default]
+  None = 0,
+  Zstd = 1,
+  /// Much faster than zstd with a lower ratio, has no levels
+  Lz4 = 2,
+}
+
+impl CompressionAlgorithm {
+  pub fn from_u8(num: u8) -> Option<Self> {
+    match num {
+      0 => Some(CompressionAlgorithm::None),
+      1 => Some(CompressionAlgorithm::Zstd),
+      2 => Some(CompressionAlgorithm::Lz4),
+      _ => None,
+    }
+  }
+}
+
+/// Compression settings, stored in every v2 file so decrypting knows how to decompress
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct CompressionParams {
+  pub algorithm: CompressionAlgorithm,
+  pub level: i32,
+}
+
+impl Default for CompressionParams {
+  // Off, with zstd's own default level once it gets enabled
+  fn default() -> Self { Self { algorithm: CompressionAlgorithm::default(), level: zstd::DEFAULT_COMPRESSION_LEVEL } }
+}
+
+/// Zero bytes added to the end of the payload, so the file size only tells a size range instead of the exact size
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum PaddingScheme {
+  #[default]
+  None = 0,
+  /// PADMÉ, at most 12% overhead, the size only leaks O(log log n) bits
+  Padme = 1,
+  /// Next power of two, fewer and wider buckets than PADMÉ for up to 100% overhead
+  PowerOfTwo = 2,
+}
+
+impl PaddingScheme {
+  pub fn from_u8(num: u8) -> Option<Self> {
+    match num {
+      0 => Some(PaddingScheme::None),
+      1 => Some(PaddingScheme::Padme),
+      2 => Some(PaddingScheme::PowerOfTwo),
       _ => None,
     }
   }
+
+  /// Size `len` bytes of data get padded to
+  pub fn padded_len(&self, len: u64) -> u64 {
+    match self {
+      PaddingScheme::None => len,
+      PaddingScheme::Padme => {
+        if len < 2 {
+          return len;
+        }
+        // Keep the exponent and only the top log2(exponent) bits of the mantissa, round the rest up
+        let exponent = len.ilog2();
+        let bits = exponent.ilog2() + 1;
+        let mask = (1u64 << (exponent - bits)) - 1;
+        // Sizes this close to u64::MAX stay unpadded instead of wrapping around
+        len.checked_add(mask).map_or(len, |v| v & !mask)
+      }
+      PaddingScheme::PowerOfTwo => len.checked_next_power_of_two().unwrap_or(len),
+    }
+  }
+}
+
+/// Runs the chunk cipher on worker threads. Chunks are independent (the index picks the nonce or keystream position),
+/// so they can be processed in any order, results are handed back in chunk order
+struct ChunkPool {
+  jobs: Option<crossbeam::channel::Sender<(u64, bool, Vec<u8>)>>, // Dropped to stop the workers
+  results: crossbeam::channel::Receiver<(u64, std::io::Result<Vec<u8>>)>,
+  pending: std::collections::BTreeMap<u64, std::io::Result<Vec<u8>>>, // Results that came back ahead of their turn
+  next: u64,      // Index of the next result handed out
+  submitted: u64, // Chunks submitted so far
+  workers: Vec<std::thread::JoinHandle<()>>,
+}
+
+impl ChunkPool {
+  fn new(cipher: std::sync::Arc<dyn ChunkCipher>, threads: usize, encrypt: bool) -> Self {
+    let (jobs_sender, jobs_receiver) = crossbeam::channel::unbounded::<(u64, bool, Vec<u8>)>();
+    let (results_sender, results_receiver) = crossbeam::channel::unbounded();
+
+    let workers = (0..threads)
+      .map(|_| {
+        let cipher = cipher.clone();
+        let jobs_receiver = jobs_receiver.clone();
+        let results_sender = results_sender.clone();
+        std::thread::spawn(move || {
+          for (index, last, mut chunk) in jobs_receiver.iter() {
+            let result = if encrypt { cipher.encrypt(index, last, &mut chunk) } else { cipher.decrypt(index, last, &mut chunk) };
+            if results_sender.send((index, result.map(|_| chunk))).is_err() {
+              break;
+            }
+          }
+        })
+      })
+      .collect();
+
+    Self { jobs: Some(jobs_sender), results: results_receiver, pending: std::collections::BTreeMap::new(), next: 0, submitted: 0, workers }
+  }
+
+  fn submit(&mut self, last: bool, chunk: Vec<u8>) -> std::io::Result<()> {
+    let sent = match &self.jobs {
+      Some(v) => v.send((self.submitted, last, chunk)).is_ok(),
+      None => false,
+    };
+    if !sent {
+      return Err(std::io::Error::other("Cipher workers have stopped"));
+    }
+    self.submitted += 1;
+    Ok(())
+  }
+
+  // Chunks submitted but not handed back yet
+  fn in_flight(&self) -> usize { (self.submitted - self.next) as usize }
+
+  /// Waits for the next chunk in order, None when every submitted chunk has been handed back
+  fn next_result(&mut self) -> Option<std::io::Result<Vec<u8>>> {
+    if self.next == self.submitted {
+      return None;
+    }
+
+    loop {
+      if let Some(v) = self.pending.remove(&self.next) {
+        self.next += 1;
+        return Some(v);
+      }
+      match self.results.recv() {
+        Ok((index, result)) => self.pending.insert(index, result),
+        Err(_) => return Some(Err(std::io::Error::other("Cipher workers have stopped"))),
+      };
+    }
+  }
+}
+
+impl Drop for ChunkPool {
+  fn drop(&mut self) {
+    // Closing the job channel ends the workers once they are through the queue
+    self.jobs = None;
+    for worker in self.workers.drain(..) {
+      let _ = worker.join();
+    }
+  }
+}
+
+/// Stops a running encryption or decryption. The streams check it before every chunk, so a job stops within one chunk
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(std::sync::Arc<std::sync::atomic::AtomicBool>);
+
+impl CancelToken {
+  pub fn cancel(&self) { self.0.store(true, std::sync::atomic::Ordering::Relaxed); }
+
+  // Ready for the next job
+  pub fn reset(&self) { self.0.store(false, std::sync::atomic::Ordering::Relaxed); }
+
+  pub fn is_cancelled(&self) -> bool { self.0.load(std::sync::atomic::Ordering::Relaxed) }
+
+  fn check(&self) -> std::io::Result<()> { if self.is_cancelled() { Err(std::io::Error::other("Cancelled")) } else { Ok(()) } }
 }
 
 struct EncryptingWriter<W: Write> {
   inner: W,
-  cipher: chacha20::XChaCha20,
+  cipher: std::sync::Arc<dyn ChunkCipher>,
+  pool: Option<ChunkPool>, // None when running on a single thread
+  queue: usize,            // Chunks kept in flight, bounds the memory used by the pool
+  index: u64,              // Chunks encrypted so far without the pool
+  compressor: Option<Box<dyn Compressor>>,
+  padding: PaddingScheme,
+  data_len: u64, // Bytes going into the cipher, after compression and before padding
   buffer: Vec<u8>,
-  progress_sender: Option<crossbeam::channel::Sender<f64>>, // Sends progress as a fraction (0.0 to 1.0)
-  total_bytes_processed: usize,
-  total_input_size: Option<usize>, // Optional: Needed for percentage calculation
+  finished: bool,
+  cancel: CancelToken,
+  progress: Progress,
 }
 
 impl<W: Write> EncryptingWriter<W> {
-  fn new(inner: W, cipher: chacha20::XChaCha20) -> Self {
+  fn new(inner: W, cipher: std::sync::Arc<dyn ChunkCipher>, threads: usize) -> Self {
+    let pool = if threads > 1 { Some(ChunkPool::new(cipher.clone(), threads, true)) } else { None };
+
     Self {
       inner,
       cipher,
+      pool,
+      queue: threads * 2,
+      index: 0,
+      compressor: None,
+      padding: PaddingScheme::None,
+      data_len: 0,
       buffer: Vec::with_capacity(CHUNK),
-      progress_sender: None,
-      total_bytes_processed: 0,
-      total_input_size: None,
+      finished: false,
+      cancel: CancelToken::default(),
+      progress: Progress::new(None),
     }
   }
 
-  // Set the progress sender (if you want to track progress)
-  fn set_progress_sender(&mut self, sender: crossbeam::channel::Sender<f64>) { self.progress_sender = Some(sender); }
+  // Report the bytes going through, the caller starts the phase with its total
+  fn set_progress(&mut self, progress: Progress) { self.progress = progress; }
 
-  // Set total input size (if known, for percentage tracking)
-  fn set_total_input_size(&mut self, size: usize) { self.total_input_size = Some(size); }
+  // Compress everything written before it gets encrypted
+  fn set_compressor(&mut self, compressor: Box<dyn Compressor>) { self.compressor = Some(compressor); }
 
-  fn send_progress_update(&self) {
-    if let Some(sender) = &self.progress_sender {
-      let progress = if let Some(total_size) = self.total_input_size {
-        // Send as a fraction (0.0 to 1.0)
-        self.total_bytes_processed as f64 / total_size as f64
-      } else {
-        // Just send raw bytes if total size is unknown
-        self.total_bytes_processed as f64
-      };
-      sender.send(progress).unwrap(); // Ignore errors if receiver is dropped
+  // Pad the end of the stream to hide its exact length
+  fn set_padding(&mut self, padding: PaddingScheme) { self.padding = padding; }
+
+  // Fail the next chunk once the token is cancelled
+  fn set_cancel_token(&mut self, cancel: CancelToken) { self.cancel = cancel; }
+
+  // Always keeps the last chunk back, only finish() knows it is the final one
+  fn encrypt_full_chunks(&mut self) -> std::io::Result<()> {
+    while self.buffer.len() > CHUNK {
+      let chunk: Vec<u8> = self.buffer.drain(..CHUNK).collect();
+      self.encrypt_chunk(chunk, false)?;
     }
+
+    Ok(())
   }
-}
 
-impl<W: Write> Write for EncryptingWriter<W> {
-  fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-    self.buffer.extend_from_slice(buf);
+  fn encrypt_chunk(&mut self, mut chunk: Vec<u8>, last: bool) -> std::io::Result<()> {
+    self.cancel.check()?;
+
+    let Some(pool) = &mut self.pool else {
+      self.cipher.encrypt(self.index, last, &mut chunk)?;
+      self.index += 1;
+      return self.write_chunk(chunk);
+    };
+
+    pool.submit(last, chunk)?;
+
+    // Enough chunks queued to keep every worker busy, write out the oldest before reading more
+    while let Some(pool) = &mut self.pool
+      && pool.in_flight() >= self.queue
+    {
+      let chunk = pool.next_result().expect("Chunks are in flight")?;
+      self.write_chunk(chunk)?;
+    }
+
+    Ok(())
+  }
+
+  // Writes an encrypted chunk, chunks always arrive here in order
+  fn write_chunk(&mut self, chunk: Vec<u8>) -> std::io::Result<()> {
+    self.inner.write_all(&chunk)?;
 
-    while self.buffer.len() >= CHUNK {
-      let chunk = &mut self.buffer[..CHUNK];
+    // Update progress, counted in write() as input bytes so compression doesn't skew it
+    self.progress.send();
 
-      self.cipher.apply_keystream(chunk);
+    Ok(())
+  }
+
+  /// Encrypts whatever is left in the buffer as the final chunk and returns the data length before padding. Must be called after all data has been written
+  fn finish(&mut self) -> std::io::Result<u64> {
+    if self.finished {
+      return Ok(self.data_len);
+    }
+    self.finished = true;
+
+    if let Some(compressor) = self.compressor.take() {
+      let before = self.buffer.len();
+      compressor.finish(&mut self.buffer)?;
+      self.data_len += (self.buffer.len() - before) as u64;
+      self.encrypt_full_chunks()?;
+    }
+
+    // Zeros up to the padded length, a chunk at a time so large padding never sits in memory
+    let mut padding = self.padding.padded_len(self.data_len) - self.data_len;
+    while padding > 0 {
+      let n = std::cmp::min(padding, CHUNK as u64) as usize;
+      self.buffer.resize(self.buffer.len() + n, 0);
+      self.encrypt_full_chunks()?;
+      padding -= n as u64;
+    }
 
-      self.inner.write_all(chunk)?;
+    // An authenticated stream always ends with a final chunk, even an empty one, so truncation can be detected
+    let remaining = std::mem::take(&mut self.buffer);
+    if !remaining.is_empty() || self.cipher.authenticated() {
+      self.encrypt_chunk(remaining, true)?;
+    }
+
+    // Write out what the workers still hold
+    while let Some(result) = self.pool.as_mut().and_then(|v| v.next_result()) {
+      self.write_chunk(result?)?;
+    }
 
-      self.buffer.drain(..CHUNK);
+    self.inner.flush()?;
+    Ok(self.data_len)
+  }
+}
 
-      // Update progress
-      self.total_bytes_processed += CHUNK;
-      self.send_progress_update();
+impl<W: Write> Write for EncryptingWriter<W> {
+  fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+    if self.finished {
+      return Err(std::io::Error::other("Write after the encrypted stream was finished"));
     }
 
+    let before = self.buffer.len();
+    match &mut self.compressor {
+      Some(v) => v.compress(buf, &mut self.buffer)?,
+      None => self.buffer.extend_from_slice(buf),
+    };
+    self.data_len += (self.buffer.len() - before) as u64;
+    self.progress.add(buf.len() as u64);
+
+    self.encrypt_full_chunks()?;
+
     Ok(buf.len())
   }
 
-  fn flush(&mut self) -> std::io::Result<()> {
-    // Process remaining bytes
-    if !self.buffer.is_empty() {
-      let remaining = self.buffer.len();
-      self.cipher.apply_keystream(&mut self.buffer);
-      self.inner.write_all(&self.buffer).unwrap();
-      self.buffer.clear();
+  fn flush(&mut self) -> std::io::Result<()> { self.inner.flush() }
+}
+
+// Passes writes through and hashes them when a checksum is wanted, the plaintext a verification pass compares against
+struct HashingWriter<W: Write> {
+  inner: W,
+  hasher: Option<blake2::Blake2b<blake2::digest::consts::U32>>,
+}
+
+impl<W: Write> HashingWriter<W> {
+  fn new(inner: W, hash: bool) -> Self { Self { inner, hasher: hash.then(blake2::Blake2b::<blake2::digest::consts::U32>::new) } }
+
+  fn checksum(self) -> Option<[u8; 32]> { self.hasher.map(|v| v.finalize().into()) }
+}
 
-      // Update progress for remaining bytes
-      self.total_bytes_processed += remaining;
-      self.send_progress_update();
+impl<W: Write> Write for HashingWriter<W> {
+  fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+    let n = self.inner.write(buf)?;
+    if let Some(hasher) = &mut self.hasher {
+      Digest::update(hasher, &buf[..n]);
     }
-    self.inner.flush()
+    Ok(n)
   }
+
+  fn flush(&mut self) -> std::io::Result<()> { self.inner.flush() }
 }
 
 struct DecryptingReader<R: Read> {
   inner: R,
-  cipher: chacha20::XChaCha20,
+  cipher: std::sync::Arc<dyn ChunkCipher>,
+  pool: Option<ChunkPool>, // None when running on a single thread
+  queue: usize,            // Chunks read ahead for the pool, bounds the memory it uses
+  index: u64,              // Chunks decrypted so far without the pool
   buffer: Vec<u8>,
   pos: usize,
-  progress_sender: Option<crossbeam::channel::Sender<f64>>, // Sends progress as a fraction (0.0 to 1.0)
-  total_bytes_processed: usize,
-  total_input_size: Option<usize>, // Optional: Needed for percentage calculation
+  next: Option<Vec<u8>>, // Read ahead chunk, tells whether the current one is the last
+  exhausted: bool,       // Every encrypted chunk has been read
+  cancel: CancelToken,
+  progress: Progress,
 }
 
 impl<R: Read> DecryptingReader<R> {
-  fn new(inner: R, cipher: chacha20::XChaCha20) -> Self {
+  fn new(inner: R, cipher: std::sync::Arc<dyn ChunkCipher>, threads: usize) -> Self {
+    let pool = if threads > 1 { Some(ChunkPool::new(cipher.clone(), threads, false)) } else { None };
+
     Self {
       inner,
       cipher,
+      pool,
+      queue: threads * 2,
+      index: 0,
       buffer: Vec::new(),
       pos: 0,
-      progress_sender: None,
-      total_bytes_processed: 0,
-      total_input_size: None,
+      next: None,
+      exhausted: false,
+      cancel: CancelToken::default(),
+      progress: Progress::new(None),
     }
   }
 
-  // Set the progress sender (if you want to track progress)
-  fn set_progress_sender(&mut self, sender: crossbeam::channel::Sender<f64>) { self.progress_sender = Some(sender); }
+  // Report the bytes going through, the caller starts the phase with its total
+  fn set_progress(&mut self, progress: Progress) { self.progress = progress; }
 
-  // Set total input size (if known, for percentage tracking)
-  fn set_total_input_size(&mut self, size: usize) { self.total_input_size = Some(size); }
+  // Fail the next chunk once the token is cancelled
+  fn set_cancel_token(&mut self, cancel: CancelToken) { self.cancel = cancel; }
+}
 
-  fn send_progress_update(&self) {
-    if let Some(sender) = &self.progress_sender {
-      let progress = if let Some(total_size) = self.total_input_size {
-        // Send as a fraction (0.0 to 1.0)
-        self.total_bytes_processed as f64 / total_size as f64
-      } else {
-        // Just send raw bytes if total size is unknown
-        self.total_bytes_processed as f64
+impl<R: Read> DecryptingReader<R> {
+  // Size of a full encrypted chunk on disk
+  fn chunk_size(&self) -> usize { CHUNK + self.cipher.overhead() }
+
+  // Reads up to one full encrypted chunk, short only at the end of the stream
+  fn read_chunk(&mut self) -> std::io::Result<Vec<u8>> {
+    let size = self.chunk_size();
+    let mut chunk = Vec::with_capacity(size);
+    (&mut self.inner).take(size as u64).read_to_end(&mut chunk)?;
+    Ok(chunk)
+  }
+
+  // Reads the next encrypted chunk and whether it is the last one, None at the end of the stream
+  fn next_encrypted(&mut self) -> std::io::Result<Option<(Vec<u8>, bool)>> {
+    if self.exhausted {
+      return Ok(None);
+    }
+
+    let chunk = match self.next.take() {
+      Some(v) => v,
+      None => self.read_chunk()?,
+    };
+    let n = chunk.len();
+
+    // Only the authenticated stream needs to know about the last chunk, look one chunk ahead for it
+    let mut last = n < self.chunk_size();
+    if !last && self.cipher.authenticated() {
+      let next = self.read_chunk()?;
+      last = next.is_empty();
+      if !last {
+        self.next = Some(next);
+      }
+    }
+
+    if n == 0 {
+      // An authenticated stream always ends with a chunk flagged as last, running out of data before that means truncation
+      if self.cipher.authenticated() {
+        return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Encrypted data is truncated"));
+      }
+      self.exhausted = true;
+      return Ok(None);
+    }
+
+    self.exhausted = last;
+    Ok(Some((chunk, last)))
+  }
+
+  // Keeps the workers busy with the chunks after the one being read
+  fn fill_pool(&mut self) -> std::io::Result<()> {
+    while self.pool.as_ref().is_some_and(|v| v.in_flight() < self.queue) {
+      let Some((chunk, last)) = self.next_encrypted()? else {
+        break;
       };
-      sender.send(progress).unwrap(); // Ignore errors if receiver is dropped
+      if let Some(pool) = &mut self.pool {
+        pool.submit(last, chunk)?;
+      }
     }
+
+    Ok(())
+  }
+
+  // Decrypts the next chunk into the buffer, returns false at the end of the stream
+  fn refill(&mut self) -> std::io::Result<bool> {
+    self.cancel.check()?;
+    self.fill_pool()?;
+
+    let chunk = match &mut self.pool {
+      Some(pool) => match pool.next_result() {
+        Some(v) => v?,
+        None => return Ok(false),
+      },
+      None => match self.next_encrypted()? {
+        Some((mut chunk, last)) => {
+          self.cipher.decrypt(self.index, last, &mut chunk)?;
+          self.index += 1;
+          chunk
+        }
+        None => return Ok(false),
+      },
+    };
+
+    // Update progress after decrypting each chunk, counted as encrypted bytes
+    self.progress.add((chunk.len() + self.cipher.overhead()) as u64);
+    self.progress.send();
+
+    self.buffer = chunk;
+    self.pos = 0;
+
+    Ok(true)
   }
 }
 
 impl<R: Read> Read for DecryptingReader<R> {
   fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-    if self.pos == self.buffer.len() {
-      // refill buffer
-      self.buffer.resize(CHUNK, 0);
-      let n = self.inner.read(&mut self.buffer).unwrap();
-      self.buffer.truncate(n);
-      if n == 0 {
+    // A chunk can decrypt to nothing (empty final chunk), so keep going until there is data or the stream ends
+    while self.pos == self.buffer.len() {
+      if !self.refill()? {
         return Ok(0);
       }
-
-      // decrypt the buffer in place
-      self.cipher.apply_keystream(&mut self.buffer);
-      self.pos = 0;
-
-      // Update progress after decrypting each chunk
-      self.total_bytes_processed += n;
-      self.send_progress_update();
     }
 
     let n = std::cmp::min(buf.len(), self.buffer.len() - self.pos);
@@ -179,6 +646,139 @@ impl<R: Read> Read for DecryptingReader<R> {
   }
 }
 
+/// Random access to the decrypted payload. The nonce (or keystream position) of every chunk follows from its
+/// index, so any chunk can be decrypted on its own and only the chunks that are actually read get decrypted
+pub struct SeekableReader<R: Read + Seek> {
+  inner: R,
+  cipher: std::sync::Arc<dyn ChunkCipher>,
+  payload_start: u64, // Where the encrypted payload starts in `inner`
+  payload_len: u64,   // Encrypted size
+  chunks: u64,
+  size: u64, // Decrypted size
+  pos: u64,
+  cached: Option<(u64, Vec<u8>)>, // Last decrypted chunk and its index
+}
+
+impl<R: Read + Seek> SeekableReader<R> {
+  fn new(mut inner: R, cipher: std::sync::Arc<dyn ChunkCipher>, payload_start: u64) -> std::io::Result<Self> {
+    let end = inner.seek(std::io::SeekFrom::End(0))?;
+    let payload_len = end.checked_sub(payload_start).ok_or_else(|| std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Encrypted data is truncated"))?;
+    let chunk_size = (CHUNK + cipher.overhead()) as u64;
+    let overhead = cipher.overhead() as u64;
+    let chunks = payload_len.div_ceil(chunk_size);
+
+    // An authenticated stream has at least the final chunk, and every chunk at least its tag
+    if cipher.authenticated() && (chunks == 0 || payload_len - (chunks - 1) * chunk_size < overhead) {
+      return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Encrypted data is truncated"));
+    }
+
+    Ok(Self { inner, payload_start, payload_len, chunks, size: payload_len - chunks * overhead, pos: 0, cached: None, cipher })
+  }
+
+  /// Size of the decrypted payload
+  pub fn size(&self) -> u64 { self.size }
+
+  // Hides the padding at the end of the payload
+  fn truncate(&mut self, len: u64) { self.size = std::cmp::min(self.size, len); }
+
+  fn load_chunk(&mut self, index: u64) -> std::io::Result<()> {
+    if self.cached.as_ref().is_some_and(|v| v.0 == index) {
+      return Ok(());
+    }
+
+    let chunk_size = (CHUNK + self.cipher.overhead()) as u64;
+    let start = index * chunk_size;
+    let mut chunk = vec![0u8; std::cmp::min(chunk_size, self.payload_len - start) as usize];
+    self.inner.seek(std::io::SeekFrom::Start(self.payload_start + start))?;
+    self.inner.read_exact(&mut chunk)?;
+
+    // The size of the file tells which chunk is the last one, cutting the file at a chunk boundary still fails to authenticate
+    self.cipher.decrypt(index, index + 1 == self.chunks, &mut chunk)?;
+    self.cached = Some((index, chunk));
+
+    Ok(())
+  }
+}
+
+impl<R: Read + Seek> Read for SeekableReader<R> {
+  fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+    if self.pos >= self.size || buf.is_empty() {
+      return Ok(0);
+    }
+
+    let index = self.pos / CHUNK as u64;
+    let offset = (self.pos % CHUNK as u64) as usize;
+    self.load_chunk(index)?;
+
+    let chunk = self.cached.as_ref().map(|v| v.1.as_slice()).unwrap_or_default();
+    let n = std::cmp::min(buf.len(), chunk.len().saturating_sub(offset)).min((self.size - self.pos) as usize);
+    buf[..n].copy_from_slice(&chunk[offset..offset + n]);
+    self.pos += n as u64;
+
+    Ok(n)
+  }
+}
+
+impl<R: Read + Seek> Seek for SeekableReader<R> {
+  fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+    let (base, offset) = match pos {
+      std::io::SeekFrom::Start(v) => (v, 0),
+      std::io::SeekFrom::End(v) => (self.size, v),
+      std::io::SeekFrom::Current(v) => (self.pos, v),
+    };
+
+    self.pos = base.checked_add_signed(offset).ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid seek to a negative or overflowing position"))?;
+    Ok(self.pos)
+  }
+}
+
+/// An entry of an encrypted directory archive
+#[derive(Clone, Debug)]
+pub struct ArchiveEntry {
+  pub path: std::path::PathBuf,
+  pub size: u64,
+  pub offset: u64, // Position of the entry data in the decrypted archive
+  pub file: bool,  // Regular file, everything else can't be extracted on its own
+}
+
+/// Bounds checked reads over a serialized header
+struct HeaderCursor<'a> {
+  vec: &'a [u8],
+  pos: usize,
+}
+
+impl<'a> HeaderCursor<'a> {
+  fn new(vec: &'a [u8]) -> Self { Self { vec, pos: 0 } }
+
+  fn bytes(&mut self, len: usize) -> Result<&'a [u8], Box<dyn std::error::Error>> {
+    let slice = self.vec.get(self.pos..self.pos + len).ok_or("Header is truncated")?;
+    self.pos += len;
+    Ok(slice)
+  }
+
+  fn u8(&mut self) -> Result<u8, Box<dyn std::error::Error>> { Ok(self.bytes(1)?[0]) }
+
+  fn u16(&mut self) -> Result<u16, Box<dyn std::error::Error>> { Ok(u16::from_le_bytes(self.bytes(2)?.try_into()?)) }
+
+  fn u32(&mut self) -> Result<u32, Box<dyn std::error::Error>> { Ok(u32::from_le_bytes(self.bytes(4)?.try_into()?)) }
+
+  fn u64(&mut self) -> Result<u64, Box<dyn std::error::Error>> { Ok(u64::from_le_bytes(self.bytes(8)?.try_into()?)) }
+
+  // u16 length followed by UTF-8 bytes
+  fn string(&mut self) -> Result<String, Box<dyn std::error::Error>> {
+    let len = self.u16()? as usize;
+    Ok(String::from_utf8(self.bytes(len)?.to_vec()).map_err(|e| format!("Invalid UTF-8 in header {}", e))?)
+  }
+}
+
+// u16 length followed by UTF-8 bytes, the length has to fit
+fn push_string(vec: &mut Vec<u8>, what: &str, value: &str) -> Result<(), Box<dyn std::error::Error>> {
+  let len = u16::try_from(value.len()).map_err(|_| format!("The {} is too long to be stored in the header ({} bytes, max {})", what, value.len(), u16::MAX))?;
+  vec.extend_from_slice(&len.to_le_bytes());
+  vec.extend_from_slice(value.as_bytes());
+  Ok(())
+}
+
 #[repr(C)]
 #[derive(Clone, Debug, Default)]
 pub struct FileHeader {
@@ -186,81 +786,106 @@ pub struct FileHeader {
   pub file: bool,
   pub version: u16,
   pub encryption: EncMethod,
+  pub metadata: MetadataPolicy,
   pub name_len: u16,
   pub name: String,
   pub path_len: u16,
   pub path: std::path::PathBuf,
+  pub compression: CompressionParams,
+  pub padding: PaddingScheme,
+  pub data_len: Option<u64>, // Payload length before padding, unknown for v1 files
 }
 
 impl FileHeader {
-  pub fn new(packed: bool, file: bool, version: u16, encryption: EncMethod, name: impl AsRef<str>, path: impl AsRef<std::path::Path>) -> Self {
-    let name = name.as_ref().to_string();
-    let path = path.as_ref().to_path_buf();
+  /// A header for the current version. Name and path are only kept as far as the metadata policy allows
+  pub fn new(packed: bool, file: bool, encryption: EncMethod, metadata: MetadataPolicy, name: impl AsRef<str>, path: impl AsRef<std::path::Path>) -> Self {
+    let name = if metadata == MetadataPolicy::None { String::new() } else { name.as_ref().to_string() };
+    let path = if metadata == MetadataPolicy::FullPath { path.as_ref().to_path_buf() } else { std::path::PathBuf::new() };
     Self {
       packed,
       file,
-      version,
+      version: ENCRYPTION_VERSION,
       encryption,
-      name_len: name.len() as u16,
+      metadata,
+      name_len: u16::try_from(name.len()).unwrap_or(u16::MAX),
+      path_len: u16::try_from(path.as_os_str().len()).unwrap_or(u16::MAX),
       name,
-      path_len: path.to_str().unwrap().len() as u16,
       path,
+      compression: CompressionParams::default(),
+      padding: PaddingScheme::default(),
+      data_len: None,
     }
   }
 
-  pub fn to_vec(&self) -> Vec<u8> {
-    let packed = self.packed as u16;
-    let file = self.file as u16;
-    let version = self.version;
-    let encryption = self.encryption as u16;
-    let name_len = self.name.len() as u16;
-    let name_bytes = self.name.as_bytes();
-    let path_len = self.path_len;
-    let path = self.path.to_str().unwrap().as_bytes();
-
-    let mut file_header = vec![0u8; FILE_HEADER_SIZE];
-    let mut pos = 0; // Track current write position
-
-    let var_size = size_of::<u16>();
-
-    // Write packed (2 bytes)
-    file_header[pos..pos + var_size].copy_from_slice(&packed.to_le_bytes());
-    pos += var_size;
-
-    // Write file (2 bytes)
-    file_header[pos..pos + var_size].copy_from_slice(&file.to_le_bytes());
-    pos += var_size;
-
-    // Write version (2 bytes)
-    file_header[pos..pos + var_size].copy_from_slice(&version.to_le_bytes());
-    pos += var_size;
+  /// Serializes the header in the current (v2) layout: magic, version, then length prefixed fields
+  pub fn to_vec(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let path = self.path.to_str().ok_or("The path is not valid UTF-8")?;
+
+    let mut file_header = Vec::new();
+    file_header.extend_from_slice(MAGIC);
+    file_header.extend_from_slice(&ENCRYPTION_VERSION.to_le_bytes());
+    file_header.push(self.packed as u8);
+    file_header.push(self.file as u8);
+    file_header.extend_from_slice(&(self.encryption as u16).to_le_bytes());
+    file_header.push(self.metadata as u8);
+    push_string(&mut file_header, "file name", &self.name)?;
+    push_string(&mut file_header, "path", path)?;
+    file_header.push(self.compression.algorithm as u8);
+    file_header.extend_from_slice(&self.compression.level.to_le_bytes());
+    file_header.push(self.padding as u8);
+    file_header.extend_from_slice(&self.data_len.unwrap_or_default().to_le_bytes());
+
+    Ok(file_header)
+  }
 
-    // Write encryption (2 bytes)
-    file_header[pos..pos + var_size].copy_from_slice(&encryption.to_le_bytes());
-    pos += var_size;
+  /// Parses a header of any supported version
+  pub fn from_vec(vec: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+    // v1 headers have no magic, they start with the packed flag
+    if !vec.starts_with(MAGIC) {
+      return Self::from_vec_v1(vec);
+    }
 
-    // Write name_len (2 bytes)
-    file_header[pos..pos + var_size].copy_from_slice(&name_len.to_le_bytes());
-    pos += var_size;
+    let mut cursor = HeaderCursor::new(vec);
+    cursor.bytes(MAGIC.len())?;
+    let version = cursor.u16()?;
 
-    // Write name_bytes (variable length)
-    let name_end = pos + name_bytes.len();
-    file_header[pos..name_end].copy_from_slice(name_bytes);
-    pos += name_bytes.len();
+    match version {
+      2 | 3 => Self::from_vec_v2(&mut cursor, version),
+      _ => Err(format!("Unsupported header version {}", version).into()),
+    }
+  }
 
-    // Write path_len (2 bytes)
-    file_header[pos..pos + var_size].copy_from_slice(&path_len.to_le_bytes());
-    pos += var_size;
+  fn from_vec_v2(cursor: &mut HeaderCursor, version: u16) -> Result<Self, Box<dyn std::error::Error>> {
+    let packed = cursor.u8()? != 0;
+    let file = cursor.u8()? != 0;
+    let encryption = EncMethod::from_u16(cursor.u16()?).ok_or("Invalid encryption method")?;
+    let metadata = MetadataPolicy::from_u8(cursor.u8()?).ok_or("Invalid metadata policy")?;
+    let name = cursor.string()?;
+    let path = std::path::PathBuf::from(cursor.string()?);
 
-    // Write path (2 bytes)
-    let path_end = pos + path.len();
-    file_header[pos..path_end].copy_from_slice(path);
-    pos += path.len();
+    let algorithm = CompressionAlgorithm::from_u8(cursor.u8()?).ok_or("Invalid compression algorithm")?;
+    let compression = CompressionParams { algorithm, level: cursor.u32()? as i32 };
+    let padding = PaddingScheme::from_u8(cursor.u8()?).ok_or("Invalid padding scheme")?;
+    let data_len = Some(cursor.u64()?);
 
-    file_header
+    Ok(Self {
+      packed,
+      file,
+      version,
+      encryption,
+      metadata,
+      name_len: name.len() as u16,
+      name,
+      path_len: path.as_os_str().len() as u16,
+      path,
+      compression,
+      padding,
+      data_len,
+    })
   }
 
-  pub fn from_vec(vec: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+  // v1 layout: fixed fields, no magic, zero padded to FILE_HEADER_SIZE
+  fn from_vec_v1(vec: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
     if vec.len() != FILE_HEADER_SIZE {
       return Err("Incorrect header".into());
     }
@@ -323,39 +948,397 @@ impl FileHeader {
       file,
       version,
       encryption,
+      metadata: MetadataPolicy::FullPath,
       name_len: name_len as u16,
       name,
       path_len: path_len as u16,
       path,
+      compression: CompressionParams::default(),
+      padding: PaddingScheme::None,
+      data_len: None,
     })
   }
 }
 
-pub struct ShinCrypt {
-  input_path: std::path::PathBuf,
-  output_dir: std::path::PathBuf,
-  password: String,
-  progress: Option<crossbeam::channel::Sender<f64>>,
+/// One key slot of a v2 file, the data key wrapped for a password or for a public key
+#[derive(Clone, Debug, Default)]
+pub enum KeySlot {
+  #[default]
+  Empty,
+  /// Wrapped under a key derived from a password, keyfiles or both
+  Password { password: bool, keyfile: bool, kdf: KdfParams, salt: String, wrapped: [u8; WRAPPED_KEY_SIZE] },
+  /// Wrapped for the public key of a recipient
+  Recipient([u8; STANZA_SIZE]),
 }
 
-impl ShinCrypt {
-  pub fn new(input_path: impl AsRef<std::path::Path>, output_dir: impl AsRef<std::path::Path>, password: impl AsRef<str>, progress: Option<crossbeam::channel::Sender<f64>>) -> Self {
-    return Self {
-      input_path: input_path.as_ref().to_path_buf(),
-      output_dir: output_dir.as_ref().to_path_buf(),
-      password: password.as_ref().into(),
-      progress,
+impl KeySlot {
+  /// What opens the slot
+  pub fn label(&self) -> &'static str {
+    match self {
+      KeySlot::Empty => "empty",
+      KeySlot::Password { password: true, keyfile: true, .. } => "password + keyfiles",
+      KeySlot::Password { password: true, .. } => "password",
+      KeySlot::Password { .. } => "keyfiles",
+      KeySlot::Recipient(_) => "public key",
+    }
+  }
+
+  // Every slot takes SLOT_SIZE bytes, so slots can be replaced without moving anything after them
+  fn to_vec(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut slot = Vec::with_capacity(SLOT_SIZE);
+    match self {
+      KeySlot::Empty => slot.push(SLOT_EMPTY),
+      KeySlot::Password { password, keyfile, kdf, salt, wrapped } => {
+        let salt_len = u8::try_from(salt.len()).map_err(|_| "Salt is too long")?;
+        slot.push(SLOT_PASSWORD);
+        slot.push((*password as u8 * KEY_FLAG_PASSWORD) | (*keyfile as u8 * KEY_FLAG_KEYFILE));
+        slot.push(kdf.algorithm as u8);
+        slot.extend_from_slice(&kdf.memory_kib.to_le_bytes());
+        slot.extend_from_slice(&kdf.iterations.to_le_bytes());
+        slot.extend_from_slice(&kdf.parallelism.to_le_bytes());
+        slot.push(salt_len);
+        slot.extend_from_slice(salt.as_bytes());
+        slot.extend_from_slice(wrapped);
+      }
+      KeySlot::Recipient(stanza) => {
+        slot.push(SLOT_RECIPIENT);
+        slot.extend_from_slice(stanza);
+      }
+    }
+
+    if slot.len() > SLOT_SIZE {
+      return Err("Key slot is too large".into());
+    }
+    slot.resize(SLOT_SIZE, 0);
+
+    Ok(slot)
+  }
+
+  fn from_slice(slot: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+    let mut cursor = HeaderCursor::new(slot);
+    match cursor.u8()? {
+      SLOT_EMPTY => Ok(KeySlot::Empty),
+      SLOT_PASSWORD => {
+        let flags = cursor.u8()?;
+        let algorithm = KdfAlgorithm::from_u8(cursor.u8()?).ok_or("Invalid key derivation algorithm")?;
+        let kdf = KdfParams { algorithm, memory_kib: cursor.u32()?, iterations: cursor.u32()?, parallelism: cursor.u32()? };
+        let salt_len = cursor.u8()? as usize;
+        let salt = String::from_utf8(cursor.bytes(salt_len)?.to_vec())?;
+        let wrapped = cursor.bytes(WRAPPED_KEY_SIZE)?.try_into()?;
+
+        Ok(KeySlot::Password { password: flags & KEY_FLAG_PASSWORD != 0, keyfile: flags & KEY_FLAG_KEYFILE != 0, kdf, salt, wrapped })
+      }
+      SLOT_RECIPIENT => Ok(KeySlot::Recipient(cursor.bytes(STANZA_SIZE)?.try_into()?)),
+      kind => Err(format!("Unsupported key slot type {}", kind).into()),
+    }
+  }
+}
+
+/// Unencrypted start of a v2 file, the key slots and everything needed to open the FileHeader
+#[derive(Clone, Debug)]
+pub struct KeyHeader {
+  pub version: u16,
+  pub slots: Vec<KeySlot>,
+  pub nonce: [u8; NONCE_SIZE],
+  pub header_len: u32,                           // Size of the sealed FileHeader that follows
+  pub key_check: Option<[u8; KEY_CHECK_SIZE]>, // Commits the file to its data key, since v3
+}
+
+impl KeyHeader {
+  /// Unused slots are kept free so keys can be added later
+  pub fn new(mut slots: Vec<KeySlot>, nonce: [u8; NONCE_SIZE], header_len: u32, key: &[u8; 32]) -> Self {
+    if slots.len() < KEY_SLOTS {
+      slots.resize(KEY_SLOTS, KeySlot::Empty);
+    }
+    Self { version: ENCRYPTION_VERSION, slots, nonce, header_len, key_check: Some(*ShinCrypt::subkey(key, KEY_CHECK_CONTEXT)) }
+  }
+
+  /// Whether the key is the data key of this file. v2 files have no check value, their slots are trusted
+  pub fn check_key(&self, key: &[u8; 32]) -> bool {
+    match &self.key_check {
+      Some(v) => *ShinCrypt::subkey(key, KEY_CHECK_CONTEXT) == *v,
+      None => true,
+    }
+  }
+
+  pub fn to_vec(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let slot_count = u8::try_from(self.slots.len()).map_err(|_| format!("Too many key slots ({}, max {})", self.slots.len(), u8::MAX))?;
+
+    let mut key_header = Vec::new();
+    key_header.extend_from_slice(MAGIC);
+    key_header.extend_from_slice(&self.version.to_le_bytes());
+    key_header.extend_from_slice(&self.nonce);
+    key_header.extend_from_slice(&self.header_len.to_le_bytes());
+    if let Some(v) = &self.key_check {
+      key_header.extend_from_slice(v);
+    }
+    key_header.push(slot_count);
+    for slot in &self.slots {
+      key_header.extend_from_slice(&slot.to_vec()?);
+    }
+
+    Ok(key_header)
+  }
+
+  /// Authenticated along with the FileHeader. The slots are left out, they change when keys are added or removed
+  pub fn aad(&self) -> Vec<u8> {
+    let mut aad = Vec::new();
+    aad.extend_from_slice(MAGIC);
+    aad.extend_from_slice(&self.version.to_le_bytes());
+    aad.extend_from_slice(&self.nonce);
+    aad.extend_from_slice(&self.header_len.to_le_bytes());
+    if let Some(v) = &self.key_check {
+      aad.extend_from_slice(v);
+    }
+    aad
+  }
+
+  /// Reads the key header from the start of a file
+  pub fn read_from(reader: &mut impl Read) -> Result<Self, ShinError> {
+    let mut prefix = [0u8; 5]; // Magic and version
+    if reader.read_exact(&mut prefix).is_err() || !prefix.starts_with(MAGIC) {
+      return Err(ShinError::NotShinCrypt);
+    }
+
+    let version = u16::from_le_bytes([prefix[3], prefix[4]]);
+    if !(2..=ENCRYPTION_VERSION).contains(&version) {
+      return Err(ShinError::UnsupportedVersion(version));
+    }
+
+    Self::read_fields(reader, version).map_err(|e| ShinError::CorruptHeader(format!("Invalid key header: {}", e)))
+  }
+
+  fn read_fields(reader: &mut impl Read, version: u16) -> Result<Self, Box<dyn std::error::Error>> {
+    // The key check sits between the header length and the slot count since v3
+    let check_size = if version >= 3 { KEY_CHECK_SIZE } else { 0 };
+    let mut fixed = vec![0u8; NONCE_SIZE + size_of::<u32>() + check_size + 1];
+    reader.read_exact(&mut fixed)?;
+
+    let mut cursor = HeaderCursor::new(&fixed);
+    let nonce = cursor.bytes(NONCE_SIZE)?.try_into()?;
+    let header_len = cursor.u32()?;
+    let key_check = if version >= 3 { Some(cursor.bytes(KEY_CHECK_SIZE)?.try_into()?) } else { None };
+    let slot_count = cursor.u8()? as usize;
+
+    if header_len as usize > FILE_HEADER_SIZE {
+      return Err("Header is too large".into());
+    }
+
+    let mut slots = vec![0u8; slot_count * SLOT_SIZE];
+    reader.read_exact(&mut slots)?;
+    let slots = slots.chunks(SLOT_SIZE).map(KeySlot::from_slice).collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Self { version, slots, nonce, header_len, key_check })
+  }
+}
+
+/// What verifying a file found out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VerifyStatus {
+  Ok,
+  /// The password, keyfiles or identity don't open the file
+  WrongPassword,
+  /// A header, a chunk or the archive inside is damaged
+  Corrupt,
+  /// Verifying didn't get that far, e.g. the file can't be read or isn't a ShinCrypt file
+  Failed,
+}
+
+impl VerifyStatus {
+  pub fn label(&self) -> &'static str {
+    match self {
+      Self::Ok => "OK",
+      Self::WrongPassword => "Wrong password",
+      Self::Corrupt => "Corrupt",
+      Self::Failed => "Failed",
+    }
+  }
+}
+
+/// One file of a verified batch
+#[derive(Debug)]
+pub struct VerifyReport {
+  pub path: std::path::PathBuf,
+  pub result: Result<(), ShinError>,
+}
+
+impl VerifyReport {
+  pub fn status(&self) -> VerifyStatus {
+    match &self.result {
+      Ok(_) => VerifyStatus::Ok,
+      Err(ShinError::WrongPassword | ShinError::WrongIdentity | ShinError::KeyRequired(_)) => VerifyStatus::WrongPassword,
+      Err(ShinError::CorruptHeader(_) | ShinError::CorruptData(_)) => VerifyStatus::Corrupt,
+      Err(_) => VerifyStatus::Failed,
+    }
+  }
+}
+
+#[derive(Clone)]
+pub struct ShinCrypt {
+  input_path: std::path::PathBuf,
+  output_dir: std::path::PathBuf,
+  password: Secret<String>,
+  keyfiles: Vec<std::path::PathBuf>,
+  recipients: Vec<Recipient>,
+  identity: Option<std::path::PathBuf>,
+  progress: Progress,
+  metadata: MetadataPolicy,
+  kdf: KdfParams,
+  encryption: EncMethod,
+  compression: CompressionParams,
+  padding: PaddingScheme,
+  obfuscate_name: bool,
+  threads: usize,
+  conflict: ConflictPolicy,
+  verify: bool,
+  cancel: CancelToken,
+  _no_core_dumps: std::sync::Arc<NoCoreDumps>, // Off as long as a job holding the password exists
+}
+
+impl ShinCrypt {
+  pub fn new(input_path: impl AsRef<std::path::Path>, output_dir: impl AsRef<std::path::Path>, password: impl AsRef<str>, progress: Option<crossbeam::channel::Sender<ProgressEvent>>) -> Self {
+    return Self {
+      input_path: input_path.as_ref().to_path_buf(),
+      output_dir: output_dir.as_ref().to_path_buf(),
+      password: Secret::from(password.as_ref()),
+      keyfiles: Vec::new(),
+      recipients: Vec::new(),
+      identity: None,
+      progress: Progress::new(progress),
+      metadata: MetadataPolicy::default(),
+      kdf: KdfParams::default(),
+      encryption: EncMethod::XChaCha20Poly1305,
+      compression: CompressionParams::default(),
+      padding: PaddingScheme::None,
+      obfuscate_name: false,
+      threads: 0,
+      conflict: ConflictPolicy::default(),
+      verify: false,
+      cancel: CancelToken::default(),
+      _no_core_dumps: std::sync::Arc::new(NoCoreDumps::new()),
     };
   }
 
+  // Set the keyfiles, used on their own or together with the password
+  pub fn set_keyfiles(&mut self, keyfiles: Vec<std::path::PathBuf>) { self.keyfiles = keyfiles; }
+
+  // Encrypt for these public keys instead of a password (only used when encrypting)
+  pub fn set_recipients(&mut self, recipients: Vec<Recipient>) { self.recipients = recipients; }
+
+  // Set the identity file that opens files encrypted for its public key (only used when decrypting)
+  pub fn set_identity(&mut self, identity: impl AsRef<std::path::Path>) { self.identity = Some(identity.as_ref().to_path_buf()); }
+
+  // Set what the encrypted file remembers about its source (only used when encrypting)
+  pub fn set_metadata_policy(&mut self, metadata: MetadataPolicy) { self.metadata = metadata; }
+
+  // Set the payload cipher suite (only used when encrypting, decryption reads it from the file)
+  pub fn set_encryption(&mut self, encryption: EncMethod) { self.encryption = encryption; }
+
+  // Set the Argon2 parameters (only used when encrypting, decryption reads them from the file)
+  pub fn set_kdf_params(&mut self, kdf: KdfParams) { self.kdf = kdf; }
+
+  // Set the compression applied before encryption (only used when encrypting, decryption reads it from the file)
+  pub fn set_compression(&mut self, compression: CompressionParams) { self.compression = compression; }
+
+  // Set the padding that hides the exact size (only used when encrypting, decryption reads it from the file)
+  pub fn set_padding(&mut self, padding: PaddingScheme) { self.padding = padding; }
+
+  // Write the encrypted file under a random name, decrypting restores the original one from the header (only used when encrypting)
+  pub fn set_obfuscate_name(&mut self, obfuscate_name: bool) { self.obfuscate_name = obfuscate_name; }
+
+  // Set the number of cipher threads, 0 uses one per CPU core
+  pub fn set_threads(&mut self, threads: usize) { self.threads = threads; }
+
+  // Set what happens to outputs that already exist
+  pub fn set_conflict_policy(&mut self, conflict: ConflictPolicy) { self.conflict = conflict; }
+
+  // Decrypt the encrypted file once more and compare it with the input before it is moved into place (only used when encrypting)
+  pub fn set_verify(&mut self, verify: bool) { self.verify = verify; }
+
+  // Set the token that stops encrypt_file and decrypt_file, whatever they wrote so far is removed
+  pub fn set_cancel_token(&mut self, cancel: CancelToken) { self.cancel = cancel; }
+
+  pub fn input_path(&self) -> &std::path::Path { &self.input_path }
+
+  /// The number of cipher threads a setting of `threads` ends up with
+  pub fn resolve_threads(threads: usize) -> usize {
+    match threads {
+      0 => std::thread::available_parallelism().map(|v| v.get()).unwrap_or(1),
+      v => v,
+    }
+  }
+
   fn get_salt(salt: Option<String>) -> argon2::password_hash::SaltString { if salt.is_some() { argon2::password_hash::SaltString::from_b64(salt.unwrap().trim()).unwrap() } else { argon2::password_hash::SaltString::generate(&mut argon2::password_hash::rand_core::OsRng) } }
 
-  fn get_key(password: String, salt: &argon2::password_hash::SaltString) -> argon2::password_hash::Output {
-    let argon2 = argon2::Argon2::default();
-    let password_hash = argon2.hash_password(password.as_bytes(), salt).unwrap();
-    let key = password_hash.hash.unwrap();
+  // The same bytes PasswordHasher::hash_password() puts in its hash field, written straight into locked memory
+  fn get_key(password: &str, keyfiles: &[std::path::PathBuf], salt: &argon2::password_hash::SaltString, kdf: &KdfParams) -> Result<SecretKey, ShinError> {
+    let secret = if keyfiles.is_empty() { None } else { Some(Self::hash_keyfiles(keyfiles)?) };
+    let argon2 = kdf.argon2(secret.as_ref().map(|v| v.as_slice())).map_err(ShinError::InvalidParams)?;
+    let mut salt_buf = [0u8; 64];
+    let salt = salt.as_salt().decode_b64(&mut salt_buf).map_err(|e| format!("Failed to derive key: {}", e))?;
+
+    let mut key = SecretKey::zeroed();
+    argon2.hash_password_into(password.as_bytes(), salt, &mut *key).map_err(|e| format!("Failed to derive key: {}", e))?;
+    Ok(key)
+  }
+
+  // Every keyfile is hashed on its own, sorting the hashes makes the order they are given in irrelevant
+  fn hash_keyfiles(keyfiles: &[std::path::PathBuf]) -> Result<SecretKey, ShinError> {
+    let mut hashes = Zeroizing::new(Vec::with_capacity(keyfiles.len()));
+    for keyfile in keyfiles {
+      let mut file = std::fs::File::open(keyfile).map_err(|e| ShinError::io("Failed to open keyfile", keyfile, e))?;
+      let mut hasher = blake2::Blake2b::<blake2::digest::consts::U32>::new();
+      std::io::copy(&mut file, &mut hasher).map_err(|e| ShinError::io("Failed to read keyfile", keyfile, e))?;
+      hashes.push(<[u8; 32]>::from(hasher.finalize()));
+    }
+    hashes.sort();
+
+    let mut hasher = blake2::Blake2b::<blake2::digest::consts::U32>::new();
+    Digest::update(&mut hasher, b"ShinCrypt keyfiles");
+    for hash in hashes.iter() {
+      Digest::update(&mut hasher, hash);
+    }
+    let mut key = SecretKey::zeroed();
+    key.copy_from_slice(&hasher.finalize());
+    Ok(key)
+  }
+
+  /// Writes a new random keyfile, never overwrites an existing file
+  pub fn gen_keyfile(path: impl AsRef<std::path::Path>) -> Result<(), String> {
+    let path = path.as_ref();
+    let mut keyfile = [0u8; KEYFILE_SIZE];
+    getrandom::fill(&mut keyfile).map_err(|e| format!("Failed to generate keyfile: {}", e))?;
+
+    let mut file = std::fs::OpenOptions::new().write(true).create_new(true).open(path).map_err(|e| format!("Failed to create keyfile at {:?}: {}", path, e))?;
+    file.write_all(&keyfile).map_err(|e| format!("Failed to write keyfile: {}", e))?;
+    file.sync_all().map_err(|e| format!("Failed to write keyfile: {}", e))?;
 
-    key
+    Ok(())
+  }
+
+  // Independent keys for the header and the payload, so their nonces can never collide
+  pub(crate) fn subkey(key: &[u8; 32], context: &[u8]) -> SecretKey {
+    let mut mac = <blake2::Blake2bMac<blake2::digest::consts::U32> as KeyInit>::new_from_slice(key).expect("A 32 byte key is a valid BLAKE2b key");
+    mac.update(context);
+    let mut subkey = SecretKey::zeroed();
+    subkey.copy_from_slice(&mac.finalize().into_bytes());
+    subkey
+  }
+
+  // The v2 FileHeader is sealed on its own, the key header is authenticated along with it
+  fn seal_header(key: &[u8; 32], nonce: &[u8; NONCE_SIZE], key_header: &[u8], file_header: &[u8]) -> Result<Vec<u8>, String> {
+    let aead = chacha20poly1305::XChaCha20Poly1305::new((&*Self::subkey(key, HEADER_CONTEXT)).into());
+    // Room for the tag up front, growing would leave a copy of the plaintext in the old allocation
+    let mut sealed = Vec::with_capacity(file_header.len() + TAG_SIZE);
+    sealed.extend_from_slice(file_header);
+    aead.encrypt_in_place(nonce.into(), key_header, &mut sealed).map_err(|_| "Failed to encrypt file header".to_string())?;
+    Ok(sealed)
+  }
+
+  fn open_header(key: &[u8; 32], nonce: &[u8; NONCE_SIZE], key_header: &[u8], mut sealed: Vec<u8>) -> Result<Zeroizing<Vec<u8>>, ShinError> {
+    let aead = chacha20poly1305::XChaCha20Poly1305::new((&*Self::subkey(key, HEADER_CONTEXT)).into());
+    aead.decrypt_in_place(nonce.into(), key_header, &mut sealed).map_err(|_| ShinError::CorruptHeader("The file header is corrupted".to_string()))?;
+    Ok(Zeroizing::new(sealed))
   }
 
   fn gen_nonce() -> [u8; NONCE_SIZE] {
@@ -368,10 +1351,21 @@ impl ShinCrypt {
 
   // pub fn encrypt_chunk(cipher: &mut chacha20::cipher::StreamCipherCoreWrapper<chacha20::XChaChaCore<chacha20::cipher::typenum::UInt<chacha20::cipher::typenum::UInt<chacha20::cipher::typenum::UInt<chacha20::cipher::typenum::UInt<chacha20::cipher::typenum::UTerm, chacha20::cipher::consts::B1>, chacha20::cipher::consts::B0>, chacha20::cipher::consts::B1>, chacha20::cipher::consts::B0>>>, chunk: &mut [u8]) { cipher.apply_keystream(chunk); }
 
-  pub fn encrypt_file(&self) -> Result<(), String> {
+  /// Encrypts the input and returns the path of the encrypted file
+  pub fn encrypt_file(&self) -> Result<std::path::PathBuf, ShinError> { self.encrypt().map_err(|e| if self.cancel.is_cancelled() { ShinError::Cancelled } else { e }) }
+
+  fn encrypt(&self) -> Result<std::path::PathBuf, ShinError> {
     // Validate input path exists
     if !self.input_path.exists() {
-      return Err(format!("Input path does not exist: {:?}", self.input_path));
+      return Err(ShinError::io("Failed to open input", &self.input_path, std::io::ErrorKind::NotFound.into()));
+    }
+
+    if self.recipients.is_empty() && self.password.is_empty() && self.keyfiles.is_empty() {
+      return Err(ShinError::KeyRequired("A password, a keyfile or a recipient is required".to_string()));
+    }
+
+    if self.encryption == EncMethod::XChaCha20 {
+      return Err(ShinError::InvalidParams("XChaCha20 without authentication is only supported for reading v1 files".to_string()));
     }
 
     let file = FileDir::what(&self.input_path).map(|v| if v == FileDir::Directory { true } else { false }).unwrap();
@@ -380,230 +1374,1875 @@ impl ShinCrypt {
     // Get file name with better error handling
     let file_name = self.input_path.file_name().ok_or_else(|| "Input path has no file name".to_string())?.to_str().ok_or_else(|| "File name is not valid UTF-8".to_string())?;
 
+    // Keep the full original name (extension included), so it can be restored even when the header doesn't store it
+    let file_path = if self.obfuscate_name { self.output_dir.join(format!("{}.{}", Self::random_name()?, ENCRYPTION_EXT)) } else { self.output_dir.join(format!("{}.{}", file_name, ENCRYPTION_EXT)) };
+    let file_path = self.resolve_output(&file_path)?;
+
     // Create file header
-    let file_h = FileHeader::new(packed, file, ENCRYPTION_VERSION, EncMethod::XChaCha20, file_name, &self.input_path);
-    let mut file_h_vec = file_h.to_vec();
+    let full_path = std::fs::canonicalize(&self.input_path).unwrap_or_else(|_| self.input_path.clone());
+    // An obfuscated output name can't restore the original one, the header has to keep it
+    let metadata = if self.obfuscate_name && self.metadata == MetadataPolicy::None { MetadataPolicy::Name } else { self.metadata };
+    let mut file_h = FileHeader::new(packed, file, self.encryption, metadata, file_name, full_path);
+
+    // Input that doesn't compress is stored raw, the header records what was actually done
+    self.progress.start(ProgressPhase::Scanning, None);
+    file_h.compression = match worth_compressing(self.compression, &self.input_path) {
+      Ok(true) => self.compression,
+      Ok(false) => CompressionParams { algorithm: CompressionAlgorithm::None, ..self.compression },
+      Err(e) => return Err(ShinError::io("Failed to read input", &self.input_path, e)),
+    };
+    file_h.padding = self.padding;
+    file_h.data_len = Some(0); // Known once the payload is written, the field has a fixed size so the header size already is
+
+    let file_h_vec = match file_h.to_vec() {
+      Ok(v) => Zeroizing::new(v),
+      Err(e) => return Err(format!("Failed to create file header: {}", e).into()),
+    };
 
     // Get file size with error handling
     let file_size = fs_extra::dir::get_size(self.input_path.clone()).map_err(|e| format!("Failed to get input size: {}", e))? as usize;
 
-    // Prepare encryption
-    let salt = Self::get_salt(None);
-    let key = Self::get_key(self.password.clone(), &salt);
+    // Prepare encryption, the random data key is wrapped in a slot for the password and for every recipient
+    let key = SecretKey::random()?;
     let nonce = Self::gen_nonce();
-    let mut cipher = chacha20::XChaCha20::new(key.as_bytes().into(), &nonce.into());
-
-    let def_output = self.output_dir.join(file_name).with_extension(ENCRYPTION_EXT);
 
-    // Create output file with better error handling
-    let file_path = if self.input_path == def_output {
-      let mut v = def_output;
-      v.set_file_name(format!("{} - new", self.input_path.file_stem().unwrap().to_str().unwrap()));
-      v.set_extension(ENCRYPTION_EXT);
-      v
-    } else {
-      def_output
-    };
+    let mut slots = Vec::new();
+    if !self.password.is_empty() || !self.keyfiles.is_empty() {
+      self.progress.start(ProgressPhase::KeyDerivation, None);
+      slots.push(Self::password_slot(&key, &self.password, &self.keyfiles, &self.kdf)?);
+    }
+    for recipient in &self.recipients {
+      slots.push(KeySlot::Recipient(recipient.wrap(&key)?));
+    }
 
-    let mut out_file = match std::fs::File::create(&file_path) {
+    let key_h = KeyHeader::new(slots, nonce, (file_h_vec.len() + TAG_SIZE) as u32, &key);
+    let key_h_vec = match key_h.to_vec() {
       Ok(v) => v,
-      Err(e) => return Err(format!("Failed to create output file at {:?}: {}", file_path, e)),
+      Err(e) => return Err(format!("Failed to create key header: {}", e).into()),
     };
 
-    // Write salt + nonce with error handling
-    match writeln!(out_file, "{}", salt.as_str()) {
+    // Written under a temporary name, a failed or cancelled job leaves nothing at file_path
+    TempOutput::clean_stale(&self.output_dir);
+    let output = match TempOutput::new(&file_path) {
       Ok(v) => v,
-      Err(e) => return Err(format!("Failed to write salt: {}", e)),
+      Err(e) => return Err(ShinError::io("Failed to create output file", &file_path, e)),
     };
-    match out_file.write_all(&nonce) {
+    let mut out_file = match std::fs::File::create(output.path()) {
       Ok(v) => v,
-      Err(e) => return Err(format!("Failed to write nonce: {}", e)),
+      Err(e) => return Err(ShinError::io("Failed to create output file", &file_path, e)),
     };
 
-    // Write encrypted file info
-    cipher.apply_keystream(&mut file_h_vec);
-    match out_file.write_all(&file_h_vec) {
+    // Write key header (not encrypted), the file header is sealed once the payload length is known and space is kept for it
+    match out_file.write_all(&key_h_vec) {
+      Ok(v) => v,
+      Err(e) => return Err(ShinError::io("Failed to write key header to", &file_path, e)),
+    };
+    match out_file.write_all(&vec![0u8; file_h_vec.len() + TAG_SIZE]) {
       Ok(v) => v,
-      Err(e) => return Err(format!("Failed to write file header: {}", e)),
+      Err(e) => return Err(ShinError::io("Failed to write file header to", &file_path, e)),
     };
 
     // Create an encrypting writer that wraps the output file
-    let mut encrypting_writer = EncryptingWriter::new(out_file, cipher);
-
-    // Set progress sender if provided
-    if let Some(sender) = self.progress.clone() {
-      encrypting_writer.set_progress_sender(sender);
-    }
+    let payload_cipher = payload_cipher(file_h.encryption, &Self::subkey(&key, PAYLOAD_CONTEXT), &nonce);
+    let mut encrypting_writer = EncryptingWriter::new(&mut out_file, payload_cipher, Self::resolve_threads(self.threads));
+
+    // Progress counts input bytes, a directory is packed and encrypted in one go
+    self.progress.start(if packed { ProgressPhase::Packing } else { ProgressPhase::Encrypting }, Some(file_size as u64));
+    encrypting_writer.set_progress(self.progress.clone());
+    encrypting_writer.set_cancel_token(self.cancel.clone());
+
+    match compressor(file_h.compression) {
+      Ok(Some(v)) => encrypting_writer.set_compressor(v),
+      Ok(None) => (),
+      Err(e) => return Err(format!("Failed to start compression: {}", e).into()),
+    };
+    encrypting_writer.set_padding(file_h.padding);
 
-    encrypting_writer.set_total_input_size(file_size);
+    // The plaintext checksum is only needed for the verification pass
+    let mut hashing_writer = HashingWriter::new(&mut encrypting_writer, self.verify);
 
     if packed {
       // Stream the tar with better error handling
-      let mut tar_builder = tar::Builder::new(&mut encrypting_writer);
+      let mut tar_builder = tar::Builder::new(&mut hashing_writer);
 
-      let result = if self.input_path.is_dir() { tar_builder.append_dir_all(file_name, &self.input_path) } else { tar_builder.append_path_with_name(&self.input_path, file_name) };
+      // Without a stored name the entries go in relative to the archive root, so the name doesn't leak through the tar either
+      let root = if file_h.name.is_empty() { "." } else { file_name };
+      let result = if self.input_path.is_dir() { self.append_dir(&mut tar_builder, std::path::Path::new(root)) } else { tar_builder.append_path_with_name(&self.input_path, root) };
 
       match result {
         Ok(v) => v,
-        Err(e) => return Err(format!("Failed to add files to archive: {}", e)),
+        Err(e) => return Err(ShinError::io("Failed to add files to archive from", &self.input_path, e)),
       };
 
       match tar_builder.finish() {
         Ok(v) => v,
-        Err(e) => return Err(format!("Failed to finalize archive: {}", e)),
+        Err(e) => return Err(ShinError::io("Failed to finalize archive in", &file_path, e)),
       };
     } else {
       // Open input file for reading
       let mut in_file = match std::fs::File::open(&self.input_path) {
         Ok(v) => v,
-        Err(e) => return Err(format!("Failed to open input file: {}", e)),
+        Err(e) => return Err(ShinError::io("Failed to open input file", &self.input_path, e)),
       };
 
       // Stream the input file through the encrypting writer
-      match std::io::copy(&mut in_file, &mut encrypting_writer) {
+      match std::io::copy(&mut in_file, &mut hashing_writer) {
         Ok(v) => v,
-        Err(e) => return Err(format!("Failed to write encrypted file: {}", e)),
+        Err(e) => return Err(ShinError::io("Failed to write encrypted file", &file_path, e)),
       };
     }
 
-    match encrypting_writer.flush() {
+    let checksum = hashing_writer.checksum();
+
+    file_h.data_len = match encrypting_writer.finish() {
+      Ok(v) => Some(v),
+      Err(e) => return Err(ShinError::io("Failed to finish encrypted stream in", &file_path, e)),
+    };
+    drop(encrypting_writer);
+
+    // Sealed exactly once, so the header nonce is never reused
+    let file_h_vec = match file_h.to_vec() {
+      Ok(v) => Zeroizing::new(v),
+      Err(e) => return Err(format!("Failed to create file header: {}", e).into()),
+    };
+    let sealed_h = Self::seal_header(&key, &nonce, &key_h.aad(), &file_h_vec)?;
+
+    match out_file.seek(std::io::SeekFrom::Start(key_h_vec.len() as u64)) {
       Ok(v) => v,
-      Err(e) => return Err(format!("Failed to flush writer: {}", e)),
+      Err(e) => return Err(ShinError::io("Failed to write file header to", &file_path, e)),
+    };
+    match out_file.write_all(&sealed_h) {
+      Ok(v) => v,
+      Err(e) => return Err(ShinError::io("Failed to write file header to", &file_path, e)),
     };
 
-    Ok(())
+    drop(out_file);
+
+    // Nothing is at file_path until the output is known to decrypt back to the input
+    if let Some(checksum) = checksum {
+      self.verify_output(output.path(), &key, &checksum)?;
+    }
+
+    match output.commit() {
+      Ok(v) => v,
+      Err(e) => return Err(ShinError::io("Failed to move output into place at", &file_path, e)),
+    };
+    Ok(file_path)
   }
 
-  pub fn decrypt_file(&self) -> Result<(), String> {
-    // Open input file
-    let mut in_file = match std::fs::File::open(&self.input_path) {
+  // Decrypts a v2 file just written with `key` into a sink and compares the plaintext with `checksum`.
+  // The key is unlocked from the written slots with the job's password and keyfiles, like decrypting will do it.
+  // Recipient slots need the identity, a job with only recipients can check the data key against the key header and no more
+  fn verify_output(&self, path: &std::path::Path, key: &[u8; 32], checksum: &[u8; 32]) -> Result<(), ShinError> {
+    let in_file = match std::fs::File::open(path) {
+      Ok(v) => v,
+      Err(e) => return Err(ShinError::io("Failed to open encrypted file", path, e)),
+    };
+    let size = in_file.metadata().map(|v| v.len()).ok();
+    let mut buf_reader = std::io::BufReader::new(in_file);
+
+    let key_h = KeyHeader::read_from(&mut buf_reader)?;
+    let key = if self.password.is_empty() && self.keyfiles.is_empty() {
+      if !key_h.check_key(key) {
+        return Err(ShinError::CorruptHeader("Verification failed, the key header doesn't match the data key".to_string()));
+      }
+      SecretKey::from(key)
+    } else {
+      match self.unlock_key(&key_h) {
+        Ok((_, v)) => v,
+        Err(ShinError::WrongPassword) => return Err(ShinError::CorruptHeader("Verification failed, the password and keyfiles don't open the written key slot".to_string())),
+        Err(e) => return Err(e),
+      }
+    };
+    let key = &*key;
+    let mut sealed_h = vec![0u8; key_h.header_len as usize];
+    match buf_reader.read_exact(&mut sealed_h) {
+      Ok(v) => v,
+      Err(e) => return Err(ShinError::decrypt("Failed to read file header from", path, e)),
+    };
+    let file_h = match FileHeader::from_vec(&Self::open_header(key, &key_h.nonce, &key_h.aad(), sealed_h)?) {
       Ok(v) => v,
-      Err(e) => return Err(format!("Failed to open input file: {}", e)),
+      Err(e) => return Err(ShinError::CorruptHeader(format!("Invalid file header: {}", e))),
     };
-    let mut buf_reader = std::io::BufReader::new(&mut in_file);
 
-    // File size for progress
-    let file_size = std::fs::metadata(&self.input_path).map_err(|e| format!("Failed to get file size: {}", e))?.len() as usize;
+    let payload_cipher = payload_cipher(file_h.encryption, &Self::subkey(key, PAYLOAD_CONTEXT), &key_h.nonce);
+    let mut decrypting_reader = DecryptingReader::new(buf_reader, payload_cipher, Self::resolve_threads(self.threads));
+    self.progress.start(ProgressPhase::Verifying, size);
+    decrypting_reader.set_progress(self.progress.clone());
+    decrypting_reader.set_cancel_token(self.cancel.clone());
 
-    // 1. Read salt (text line, not encrypted)
-    let mut salt_str = String::new();
-    match buf_reader.read_line(&mut salt_str) {
+    let data = (&mut decrypting_reader).take(file_h.data_len.unwrap_or(u64::MAX));
+    let mut decompressing_reader = match decompressor(file_h.compression.algorithm, data) {
       Ok(v) => v,
-      Err(e) => return Err(format!("Failed to read salt: {}", e)),
+      Err(e) => return Err(format!("Failed to start decompression: {}", e).into()),
     };
-    let salt = match argon2::password_hash::SaltString::from_b64(salt_str.trim()) {
+    let mut hashing_writer = HashingWriter::new(std::io::sink(), true);
+    match std::io::copy(&mut decompressing_reader, &mut hashing_writer) {
       Ok(v) => v,
-      Err(e) => return Err(format!("Invalid salt format: {}", e)),
+      Err(e) => return Err(ShinError::decrypt("Failed to verify", path, e)),
     };
+    drop(decompressing_reader);
 
-    // 2. Read nonce (not encrypted)
-    let mut nonce = [0u8; 24];
-    match buf_reader.read_exact(&mut nonce) {
+    // The padding and the final chunk are authenticated too
+    match std::io::copy(&mut decrypting_reader, &mut std::io::sink()) {
       Ok(v) => v,
-      Err(e) => return Err(format!("Failed to read nonce: {}", e)),
+      Err(e) => return Err(ShinError::decrypt("Failed to verify", path, e)),
     };
 
-    // 3. Prepare cipher
-    let key = Self::get_key(self.password.clone(), &salt);
-    let cipher = chacha20::XChaCha20::new(key.as_bytes().into(), &nonce.into());
+    if hashing_writer.checksum().as_ref() != Some(checksum) {
+      return Err(ShinError::CorruptData("Verification failed, the encrypted file doesn't decrypt back to the input".to_string()));
+    }
+
+    Ok(())
+  }
+
+  // Adds the input directory like tar's append_dir_all, entry by entry so progress can name the file and a cancel stops between files
+  fn append_dir(&self, tar_builder: &mut tar::Builder<impl Write>, root: &std::path::Path) -> std::io::Result<()> {
+    let mut stack = vec![(self.input_path.clone(), true, false)];
+    while let Some((src, is_dir, is_symlink)) = stack.pop() {
+      self.cancel.check()?;
+
+      let dest = root.join(src.strip_prefix(&self.input_path).unwrap());
+      // Links are followed, a link to a directory is packed as the directory
+      if is_dir || (is_symlink && src.is_dir()) {
+        for entry in std::fs::read_dir(&src)? {
+          let entry = entry?;
+          let file_type = entry.file_type()?;
+          stack.push((entry.path(), file_type.is_dir(), file_type.is_symlink()));
+        }
+        if dest != std::path::Path::new("") {
+          tar_builder.append_dir(&dest, &src)?;
+        }
+      } else {
+        self.progress.set_file(&dest);
+        tar_builder.append_path_with_name(&src, &dest)?;
+      }
+    }
+
+    Ok(())
+  }
+
+  // 128 random bits as hex, unrelated to the input so the name tells nothing about it
+  fn random_name() -> Result<String, String> {
+    let mut bytes = [0u8; 16];
+    getrandom::fill(&mut bytes).map_err(|e| format!("Failed to generate output name: {}", e))?;
+    Ok(bytes.iter().map(|v| format!("{:02x}", v)).collect())
+  }
+
+  // Where an output for `path` goes under the conflict policy, a skipped output ends the job
+  fn resolve_output(&self, path: &std::path::Path) -> Result<std::path::PathBuf, ShinError> {
+    match self.conflict.resolve(path, exists) {
+      Ok(Some(v)) => Ok(v),
+      Ok(None) => Err(ShinError::Skipped(path.to_path_buf())),
+      // Ask only fails when something is at the path, Rename also when no numbered name is free
+      Err(_) if self.conflict == ConflictPolicy::Ask && exists(path) => Err(ShinError::OutputExists(path.to_path_buf())),
+      Err(e) => Err(ShinError::io("Failed to create output file", path, e)),
+    }
+  }
+
+  /// Decrypts the input into the output directory
+  pub fn decrypt_file(&self) -> Result<(), ShinError> { self.decrypt().map_err(|e| if self.cancel.is_cancelled() { ShinError::Cancelled } else { e }) }
 
-    // 4. Wrap the reader so ALL encrypted bytes come through the decryptor
-    let mut decrypting_reader = DecryptingReader::new(buf_reader, cipher);
+  fn decrypt(&self) -> Result<(), ShinError> {
+    // 1. Read the headers, 2. the rest of the encrypted bytes come through the decryptor
+    let (file_h, mut decrypting_reader) = self.open_payload(ProgressPhase::Decrypting)?;
 
-    // 5. Read and parse header directly from decrypting reader
-    let mut header = [0u8; FILE_HEADER_SIZE];
-    match decrypting_reader.read_exact(&mut header) {
+    // Padding follows the data, it is authenticated with the rest but never handed out
+    let data = (&mut decrypting_reader).take(file_h.data_len.unwrap_or(u64::MAX));
+    let mut decompressing_reader = match decompressor(file_h.compression.algorithm, data) {
       Ok(v) => v,
-      Err(e) => return Err(format!("Failed to read file header: {}", e)),
+      Err(e) => return Err(format!("Failed to start decompression: {}", e).into()),
     };
-    let file_h = match FileHeader::from_vec(&header.to_vec()) {
+
+    // Written under a temporary name and moved into place once every chunk is authenticated
+    // An existing directory is merged with the archive, the conflict policy decides about every file in it
+    let target = self.output_dir.join(self.restored_name(&file_h)?);
+    let target = if file_h.packed && target.is_dir() { target } else { self.resolve_output(&target)? };
+    TempOutput::clean_stale(&self.output_dir);
+    let output = match TempOutput::new(&target) {
       Ok(v) => v,
-      // Err(e) => return Err(format!("Invalid file header: {}", e)),
-      Err(_) => return Err(format!("Wrong password maybe")),
+      Err(e) => return Err(ShinError::io("Failed to create output file", &target, e)),
     };
 
-    // 6. Progress tracking (still using the same decrypting_reader)
-    if let Some(sender) = self.progress.clone() {
-      decrypting_reader.set_progress_sender(sender);
-      decrypting_reader.set_total_input_size(file_size);
-    }
-
+    let mut skipped = Vec::new();
     if file_h.packed {
-      // 7. Extract tar archive (already positioned after header)
-      let mut tar_archive = tar::Archive::new(decrypting_reader);
-      match tar_archive.unpack(&self.output_dir) {
-        Ok(v) => v,
-        Err(e) => return Err(format!("Failed to unpack archive: {}", e)),
-      };
+      // 3. Extract tar archive (already positioned after header), a stored name is the root directory of the archive
+      // Every entry is read through the decrypting reader, so a cancel stops the unpacking between chunks
+      let mut tar_archive = tar::Archive::new(&mut decompressing_reader);
+      skipped = self.unpack(&mut tar_archive, output.path(), &target, !file_h.name.is_empty())?;
     } else {
-      // 7. Output the single file (already positioned after header)
-      let output_path = self.output_dir.join(&file_h.name);
-      let mut out_file = match std::fs::File::create(&output_path) {
+      // 3. Output the single file (already positioned after header)
+      let mut out_file = match std::fs::File::create(output.path()) {
         Ok(v) => v,
-        Err(e) => return Err(format!("Failed to create output file at {:?}: {}", output_path, e)),
+        Err(e) => return Err(ShinError::io("Failed to create output file", &target, e)),
       };
 
       // Copy all remaining decrypted data into the output file
-      match std::io::copy(&mut decrypting_reader, &mut out_file) {
+      match std::io::copy(&mut decompressing_reader, &mut out_file) {
         Ok(v) => v,
-        Err(e) => return Err(format!("Failed to write decrypted file: {}", e)),
+        Err(e) => return Err(ShinError::decrypt("Failed to write decrypted file", &target, e)),
       };
     }
+    drop(decompressing_reader);
+
+    // tar and the decompressor stop at their end markers, read the rest so the final chunk gets authenticated too
+    match std::io::copy(&mut decrypting_reader, &mut std::io::sink()) {
+      Ok(v) => v,
+      Err(e) => return Err(ShinError::decrypt("Failed to read encrypted data from", &self.input_path, e)),
+    };
+
+    match output.commit() {
+      Ok(v) => v,
+      Err(e) => return Err(ShinError::io("Failed to move output into place at", &target, e)),
+    };
 
+    // The rest of the archive is in place, but the input doesn't have an exact copy anymore
+    if !skipped.is_empty() {
+      return Err(ShinError::SkippedEntries(skipped));
+    }
     Ok(())
   }
 
-  pub fn benchmark() -> Result<(std::time::Duration, std::time::Duration), String> {
-    let path = match Self::gen_file() {
+  // Reads the headers of the input file and wraps the rest in a decrypting reader, progress counts the encrypted payload
+  fn open_payload(&self, phase: ProgressPhase) -> Result<(FileHeader, DecryptingReader<std::io::BufReader<std::fs::File>>), ShinError> {
+    let in_file = match std::fs::File::open(&self.input_path) {
       Ok(v) => v,
-      Err(e) => return Err(e),
+      Err(e) => return Err(ShinError::io("Failed to open input file", &self.input_path, e)),
     };
+    let file_size = in_file.metadata().map_err(|e| ShinError::io("Failed to get the size of", &self.input_path, e))?.len();
+    let mut buf_reader = std::io::BufReader::new(in_file);
 
-    let output_dir = path.parent().unwrap();
+    let (file_h, payload_cipher) = self.read_headers(&mut buf_reader)?;
+    let payload_start = match buf_reader.stream_position() {
+      Ok(v) => v,
+      Err(e) => return Err(ShinError::io("Failed to read input file", &self.input_path, e)),
+    };
 
-    let encrypt_time = {
-      let time = std::time::Instant::now();
+    let mut decrypting_reader = DecryptingReader::new(buf_reader, payload_cipher, Self::resolve_threads(self.threads));
+    self.progress.start(phase, Some(file_size.saturating_sub(payload_start)));
+    decrypting_reader.set_progress(self.progress.clone());
+    decrypting_reader.set_cancel_token(self.cancel.clone());
 
-      let shincrypt = ShinCrypt::new(&path, output_dir, APPNAME, None);
-      if let Err(e) = shincrypt.encrypt_file() {
-        println!("{}", e);
-      };
+    Ok((file_h, decrypting_reader))
+  }
 
-      time.elapsed()
+  /// Decrypts the input file into a sink, nothing is written. Checks the key, every chunk and, for directories, the archive
+  pub fn verify_file(&self) -> Result<(), ShinError> { self.verify().map_err(|e| if self.cancel.is_cancelled() { ShinError::Cancelled } else { e }) }
+
+  fn verify(&self) -> Result<(), ShinError> {
+    let (file_h, mut decrypting_reader) = self.open_payload(ProgressPhase::Verifying)?;
+    let failed = |e| ShinError::decrypt("Failed to verify", &self.input_path, e);
+
+    let data = (&mut decrypting_reader).take(file_h.data_len.unwrap_or(u64::MAX));
+    let mut decompressing_reader = match decompressor(file_h.compression.algorithm, data) {
+      Ok(v) => v,
+      Err(e) => return Err(format!("Failed to start decompression: {}", e).into()),
     };
 
-    let mut input_path = path.clone();
-    input_path.set_extension(ENCRYPTION_EXT);
+    if file_h.packed {
+      // Parsed entry by entry like when unpacking, a damaged archive fails here even if every chunk opens
+      let mut tar_archive = tar::Archive::new(&mut decompressing_reader);
+      for entry in tar_archive.entries().map_err(failed)? {
+        let mut entry = entry.map_err(failed)?;
+        self.progress.set_file(&entry.path().map_err(failed)?);
+        std::io::copy(&mut entry, &mut std::io::sink()).map_err(failed)?;
+      }
+    } else {
+      std::io::copy(&mut decompressing_reader, &mut std::io::sink()).map_err(failed)?;
+    }
+    drop(decompressing_reader);
 
-    let decrypt_time = {
-      let time = std::time::Instant::now();
+    // The padding and the final chunk are authenticated too
+    std::io::copy(&mut decrypting_reader, &mut std::io::sink()).map_err(failed)?;
+    Ok(())
+  }
+
+  /// Verifies every file with the keys of this job, one after the other. A cancel stops the batch, the cancelled file is the last report
+  pub fn verify_files(&self, paths: &[std::path::PathBuf]) -> Vec<VerifyReport> {
+    let mut reports = Vec::with_capacity(paths.len());
+    for path in paths {
+      let mut job = self.clone();
+      job.input_path = path.clone();
+      let result = job.verify_file();
+
+      let cancelled = matches!(result, Err(ShinError::Cancelled));
+      reports.push(VerifyReport { path: path.clone(), result });
+      if cancelled {
+        break;
+      }
+    }
+
+    reports
+  }
+
+  /// Verifies the input, every .snc file below it when it is a directory
+  pub fn verify_batch(&self) -> Result<Vec<VerifyReport>, ShinError> {
+    let paths = Self::encrypted_files(&self.input_path)?;
+    if paths.is_empty() {
+      return Err(format!("No .{} files in {:?}", ENCRYPTION_EXT, self.input_path).into());
+    }
+    Ok(self.verify_files(&paths))
+  }
+
+  /// `path` itself when it is a file, otherwise the .snc files below it in sorted order. Links are not followed
+  pub fn encrypted_files(path: impl AsRef<std::path::Path>) -> Result<Vec<std::path::PathBuf>, ShinError> {
+    let path = path.as_ref();
+    if !path.is_dir() {
+      return Ok(vec![path.to_path_buf()]);
+    }
+
+    let mut files = Vec::new();
+    let mut stack = vec![path.to_path_buf()];
+    while let Some(dir) = stack.pop() {
+      let entries = match std::fs::read_dir(&dir) {
+        Ok(v) => v,
+        Err(e) => return Err(ShinError::io("Failed to read directory", &dir, e)),
+      };
+      for entry in entries {
+        let entry = entry.map_err(|e| ShinError::io("Failed to read directory", &dir, e))?;
+        let file_type = entry.file_type().map_err(|e| ShinError::io("Failed to read directory", &dir, e))?;
+        let entry_path = entry.path();
+        if file_type.is_dir() {
+          stack.push(entry_path);
+        } else if file_type.is_file() && entry_path.extension().is_some_and(|v| v == ENCRYPTION_EXT) {
+          files.push(entry_path);
+        }
+      }
+    }
+    files.sort();
+
+    Ok(files)
+  }
+
+  // Unpacks like tar's Archive::unpack into `dir`, entry by entry so progress can name the file and every file goes through the conflict policy.
+  // `dir` is moved to `target` afterwards, so the files already there are the ones that conflict. Returns the existing files that were kept
+  fn unpack(&self, tar_archive: &mut tar::Archive<impl Read>, dir: &std::path::Path, target: &std::path::Path, strip_root: bool) -> Result<Vec<std::path::PathBuf>, ShinError> {
+    let failed = |e| ShinError::decrypt("Failed to unpack archive to", target, e);
+    std::fs::create_dir_all(dir).map_err(failed)?;
+
+    // Directories go last, so their permissions can't keep the files inside from being written
+    let mut directories = Vec::new();
+    let mut skipped = Vec::new();
+    for entry in tar_archive.entries().map_err(failed)? {
+      let mut entry = entry.map_err(failed)?;
+
+      // Like unpack_in, nothing may point outside the output
+      let path = entry.path().map_err(failed)?.into_owned();
+      if path.components().any(|v| !matches!(v, std::path::Component::Normal(_) | std::path::Component::CurDir)) {
+        continue;
+      }
+      let rel: std::path::PathBuf = path.components().filter(|v| matches!(v, std::path::Component::Normal(_))).skip(strip_root as usize).collect();
+
+      let entry_type = entry.header().entry_type();
+      if entry_type.is_dir() {
+        directories.push((rel, entry));
+        continue;
+      }
+      // ShinCrypt follows links when packing, its archives only hold files and directories
+      if !entry_type.is_file() || rel.as_os_str().is_empty() {
+        continue;
+      }
 
-      let shincrypt = ShinCrypt::new(input_path, output_dir, APPNAME, None);
-      if let Err(e) = shincrypt.decrypt_file() {
-        println!("{}", e);
+      let dest = match self.conflict.resolve(&target.join(&rel), |v| exists(v) || v.strip_prefix(target).is_ok_and(|v| exists(&dir.join(v)))) {
+        Ok(Some(v)) => dir.join(v.strip_prefix(target).unwrap()),
+        Ok(None) => {
+          skipped.push(target.join(&rel));
+          continue;
+        }
+        Err(_) => return Err(ShinError::OutputExists(target.join(&rel))),
       };
 
-      time.elapsed()
+      self.progress.set_file(&rel);
+      std::fs::create_dir_all(dest.parent().unwrap()).map_err(failed)?;
+      entry.unpack(&dest).map_err(failed)?;
+    }
+
+    directories.sort_by(|a, b| b.0.cmp(&a.0));
+    for (rel, mut entry) in directories {
+      let dest = dir.join(rel);
+      std::fs::create_dir_all(&dest).map_err(failed)?;
+      entry.unpack(&dest).map_err(failed)?;
+    }
+
+    Ok(skipped)
+  }
+
+  // Name to restore, from the header or, when it wasn't stored, from the .snc file name
+  fn restored_name(&self, file_h: &FileHeader) -> Result<std::ffi::OsString, String> {
+    let name = if file_h.name.is_empty() {
+      // "report.pdf.snc" -> "report.pdf"
+      self.input_path.file_stem()
+    } else {
+      // Never let a stored name point outside the output directory
+      std::path::Path::new(&file_h.name).file_name()
     };
 
-    std::fs::remove_dir_all(output_dir).unwrap();
-    Ok((encrypt_time, decrypt_time))
+    name.map(|v| v.to_os_string()).ok_or_else(|| "Can't determine the output file name".to_string())
   }
 
-  fn gen_file() -> Result<std::path::PathBuf, String> {
-    let current_dir = std::env::current_exe().unwrap().parent().unwrap().to_path_buf();
-    let benchmark_dir = current_dir.join(BENCHMARK_EXT);
-    std::fs::create_dir_all(&benchmark_dir).unwrap();
-    let file_path = benchmark_dir.join(ENCRYPTION_EXT).with_extension(BENCHMARK_EXT);
+  // v2 files start with the magic, v1 files with the salt line
+  fn read_headers(&self, reader: &mut impl BufRead) -> Result<(FileHeader, std::sync::Arc<dyn ChunkCipher>), ShinError> {
+    let is_v2 = match reader.fill_buf() {
+      Ok(v) => v.starts_with(MAGIC),
+      Err(e) => return Err(ShinError::io("Failed to read input file", &self.input_path, e)),
+    };
 
-    let file = std::fs::File::create(file_path.clone()).unwrap();
-    let mut file_buf = std::io::BufWriter::new(file);
+    if is_v2 { self.read_headers_v2(reader) } else { self.read_headers_v1(reader) }
+  }
 
-    let mut buffer = vec![0u8; FILE_1GB];
+  /// Opens the encrypted file for random access, nothing past the headers is decrypted until it is read.
+  /// The reader returns the payload as stored without padding, still compressed if FileHeader::compression says so
+  pub fn open_reader(&self) -> Result<(FileHeader, SeekableReader<std::io::BufReader<std::fs::File>>), ShinError> {
+    let in_file = match std::fs::File::open(&self.input_path) {
+      Ok(v) => v,
+      Err(e) => return Err(ShinError::io("Failed to open input file", &self.input_path, e)),
+    };
+    let mut buf_reader = std::io::BufReader::new(in_file);
+
+    let (file_h, payload_cipher) = self.read_headers(&mut buf_reader)?;
+    let payload_start = match buf_reader.stream_position() {
+      Ok(v) => v,
+      Err(e) => return Err(ShinError::io("Failed to read input file", &self.input_path, e)),
+    };
 
-    getrandom::fill(&mut buffer).unwrap();
-    file_buf.write_all(&buffer).unwrap();
+    let mut reader = SeekableReader::new(buf_reader, payload_cipher, payload_start).map_err(|e| ShinError::decrypt("Failed to read encrypted data from", &self.input_path, e))?;
+    if let Some(v) = file_h.data_len {
+      reader.truncate(v);
+    }
+    Ok((file_h, reader))
+  }
 
-    Ok(file_path)
+  /// Entries of an encrypted directory archive, only the chunks holding tar headers get decrypted
+  pub fn list_entries(&self) -> Result<Vec<ArchiveEntry>, ShinError> {
+    let (file_h, reader) = self.open_reader()?;
+    if !file_h.packed {
+      return Err("This file is not an encrypted directory".into());
+    }
+
+    // A compressed archive has no fixed positions in the encrypted data, it has to be decompressed from the start
+    if file_h.compression.algorithm != CompressionAlgorithm::None {
+      let reader = match decompressor(file_h.compression.algorithm, reader) {
+        Ok(v) => v,
+        Err(e) => return Err(format!("Failed to start decompression: {}", e).into()),
+      };
+      let mut tar_archive = tar::Archive::new(reader);
+      return match tar_archive.entries() {
+        Ok(v) => self.collect_entries(v),
+        Err(e) => Err(ShinError::decrypt("Failed to read archive", &self.input_path, e)),
+      };
+    }
+
+    let mut tar_archive = tar::Archive::new(reader);
+    match tar_archive.entries_with_seek() {
+      Ok(v) => self.collect_entries(v),
+      Err(e) => Err(ShinError::decrypt("Failed to read archive", &self.input_path, e)),
+    }
+  }
+
+  fn collect_entries<R: Read>(&self, entries: tar::Entries<R>) -> Result<Vec<ArchiveEntry>, ShinError> {
+    let mut list = Vec::new();
+    for entry in entries {
+      let entry = entry.map_err(|e| ShinError::decrypt("Failed to read archive", &self.input_path, e))?;
+      let path = entry.path().map_err(|e| ShinError::decrypt("Failed to read archive", &self.input_path, e))?.to_path_buf();
+      list.push(ArchiveEntry { path, size: entry.size(), offset: entry.raw_file_position(), file: entry.header().entry_type().is_file() });
+    }
+
+    Ok(list)
+  }
+
+  /// Extracts one file of an encrypted archive into the output directory, seeking straight to its data when the archive isn't compressed
+  pub fn extract_entry(&self, entry: &ArchiveEntry) -> Result<(), ShinError> {
+    if !entry.file {
+      return Err(format!("{:?} is not a file", entry.path).into());
+    }
+
+    let (file_h, mut reader) = self.open_reader()?;
+    let reader: Box<dyn Read> = if file_h.compression.algorithm == CompressionAlgorithm::None {
+      match reader.seek(std::io::SeekFrom::Start(entry.offset)) {
+        Ok(v) => v,
+        Err(e) => return Err(ShinError::decrypt("Failed to read archive", &self.input_path, e)),
+      };
+      Box::new(reader)
+    } else {
+      // Compressed data can't be seeked, decompress up to the entry
+      let mut reader = match decompressor(file_h.compression.algorithm, reader) {
+        Ok(v) => v,
+        Err(e) => return Err(format!("Failed to start decompression: {}", e).into()),
+      };
+      match std::io::copy(&mut (&mut reader).take(entry.offset), &mut std::io::sink()) {
+        Ok(v) => v,
+        Err(e) => return Err(ShinError::decrypt("Failed to read archive", &self.input_path, e)),
+      };
+      reader
+    };
+
+    // Never let an entry name point outside the output directory
+    let name = entry.path.file_name().ok_or_else(|| "Can't determine the output file name".to_string())?;
+    let output_path = self.resolve_output(&self.output_dir.join(name))?;
+    TempOutput::clean_stale(&self.output_dir);
+    let output = match TempOutput::new(&output_path) {
+      Ok(v) => v,
+      Err(e) => return Err(ShinError::io("Failed to create output file", &output_path, e)),
+    };
+    let mut out_file = match std::fs::File::create(output.path()) {
+      Ok(v) => v,
+      Err(e) => return Err(ShinError::io("Failed to create output file", &output_path, e)),
+    };
+
+    let copied = match std::io::copy(&mut reader.take(entry.size), &mut out_file) {
+      Ok(v) => v,
+      Err(e) => return Err(ShinError::decrypt("Failed to write decrypted file", &output_path, e)),
+    };
+    if copied != entry.size {
+      return Err(ShinError::CorruptData("Encrypted data is truncated".to_string()));
+    }
+    drop(out_file);
+
+    match output.commit() {
+      Ok(v) => v,
+      Err(e) => return Err(ShinError::io("Failed to move output into place at", &output_path, e)),
+    };
+    Ok(())
+  }
+
+  // v1: salt line, nonce, then the 1 MB header and the payload in one XChaCha20 keystream
+  fn read_headers_v1(&self, reader: &mut impl BufRead) -> Result<(FileHeader, std::sync::Arc<dyn ChunkCipher>), ShinError> {
+    // Salt (text line, not encrypted)
+    let mut salt_str = String::new();
+    match reader.read_line(&mut salt_str) {
+      Ok(v) => v,
+      Err(e) if e.kind() == std::io::ErrorKind::InvalidData => return Err(ShinError::NotShinCrypt),
+      Err(e) => return Err(ShinError::io("Failed to read salt from", &self.input_path, e)),
+    };
+    // Neither a key header nor a v1 salt line
+    let salt = match argon2::password_hash::SaltString::from_b64(salt_str.trim()) {
+      Ok(v) => v,
+      Err(_) => return Err(ShinError::NotShinCrypt),
+    };
+
+    // Nonce (not encrypted)
+    let mut nonce = [0u8; NONCE_SIZE];
+    match reader.read_exact(&mut nonce) {
+      Ok(v) => v,
+      Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Err(ShinError::CorruptHeader("The file header is truncated".to_string())),
+      Err(e) => return Err(ShinError::io("Failed to read nonce from", &self.input_path, e)),
+    };
+
+    // v1 files always used the Argon2 defaults
+    self.progress.start(ProgressPhase::KeyDerivation, None);
+    let key = Self::get_key(&self.password, &[], &salt, &KdfParams::default())?;
+    let mut cipher = chacha20::XChaCha20::new((&*key).into(), &nonce.into());
+
+    // The header always uses the plain XChaCha20 keystream
+    let mut header = Zeroizing::new(vec![0u8; FILE_HEADER_SIZE]);
+    match reader.read_exact(&mut header) {
+      Ok(v) => v,
+      Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Err(ShinError::CorruptHeader("The file header is truncated".to_string())),
+      Err(e) => return Err(ShinError::io("Failed to read file header from", &self.input_path, e)),
+    };
+    cipher.apply_keystream(&mut header);
+
+    // v1 has no check value, but the end of its header is always zeros. A wrong key turns it into noise, damage only flips a few bytes
+    let tail = &header[V1_FIELDS_MAX..];
+    if tail.iter().filter(|v| **v == 0).count() < tail.len() / 2 {
+      return Err(ShinError::WrongPassword);
+    }
+    let file_h = match FileHeader::from_vec(&header) {
+      Ok(v) => v,
+      Err(e) => return Err(ShinError::CorruptHeader(format!("The file header is corrupted: {}", e))),
+    };
+
+    let payload_cipher = match file_h.encryption {
+      EncMethod::XChaCha20 => legacy_cipher(&key, &nonce, FILE_HEADER_SIZE as u64),
+      method => payload_cipher(method, &Self::subkey(&key, PAYLOAD_CONTEXT), &nonce),
+    };
+
+    Ok((file_h, payload_cipher))
+  }
+
+  // v2: key header, sealed file header, then the payload under its own key
+  fn read_headers_v2(&self, reader: &mut impl Read) -> Result<(FileHeader, std::sync::Arc<dyn ChunkCipher>), ShinError> {
+    let key_h = KeyHeader::read_from(reader)?;
+
+    let mut sealed_h = vec![0u8; key_h.header_len as usize];
+    match reader.read_exact(&mut sealed_h) {
+      Ok(v) => v,
+      Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Err(ShinError::CorruptHeader("The file header is truncated".to_string())),
+      Err(e) => return Err(ShinError::io("Failed to read file header from", &self.input_path, e)),
+    };
+
+    let (_, key) = self.unlock_key(&key_h)?;
+    let file_h_vec = Self::open_header(&key, &key_h.nonce, &key_h.aad(), sealed_h)?;
+    let file_h = match FileHeader::from_vec(&file_h_vec) {
+      Ok(v) => v,
+      Err(e) => return Err(ShinError::CorruptHeader(format!("Invalid file header: {}", e))),
+    };
+
+    let payload_cipher = payload_cipher(file_h.encryption, &Self::subkey(&key, PAYLOAD_CONTEXT), &key_h.nonce);
+    Ok((file_h, payload_cipher))
+  }
+
+  // The data key of a v2 file and the slot it was unwrapped from, with the password and keyfiles or with the identity
+  fn unlock_key(&self, key_h: &KeyHeader) -> Result<(usize, SecretKey), ShinError> {
+    if let Some(path) = &self.identity {
+      let identity = Identity::from_file(path)?;
+      for (i, slot) in key_h.slots.iter().enumerate() {
+        if let KeySlot::Recipient(stanza) = slot
+          && let Some(key) = identity.unwrap(stanza)
+        {
+          return Self::checked_key(key_h, i, key);
+        }
+      }
+    }
+
+    // Keyfiles given for a slot that was made without any are ignored
+    let has_password = !self.password.is_empty();
+    let mut tried = false;
+    for (i, slot) in key_h.slots.iter().enumerate() {
+      if let KeySlot::Password { password, keyfile, kdf, salt, wrapped } = slot {
+        if *password != has_password || (*keyfile && self.keyfiles.is_empty()) {
+          continue;
+        }
+        tried = true;
+
+        let salt = match argon2::password_hash::SaltString::from_b64(salt) {
+          Ok(v) => v,
+          Err(e) => return Err(ShinError::CorruptHeader(format!("Invalid salt format: {}", e))),
+        };
+        let keyfiles = if *keyfile { self.keyfiles.as_slice() } else { &[] };
+        self.progress.start(ProgressPhase::KeyDerivation, None);
+        // The parameters come from the file, ones Argon2 can't use or above the limits mean it is damaged or crafted
+        let kek = match Self::get_key(&self.password, keyfiles, &salt, kdf) {
+          Ok(v) => v,
+          Err(ShinError::InvalidParams(e)) => return Err(ShinError::CorruptHeader(e)),
+          Err(e) => return Err(e),
+        };
+        if let Some(key) = Self::unwrap_key(&kek, wrapped) {
+          return Self::checked_key(key_h, i, key);
+        }
+      }
+    }
+
+    if tried {
+      return Err(ShinError::WrongPassword);
+    }
+
+    let password_slots = key_h.slots.iter().filter_map(|v| if let KeySlot::Password { password, keyfile, .. } = v { Some((*password, *keyfile)) } else { None }).collect::<Vec<_>>();
+    if self.identity.is_some() {
+      Err(ShinError::WrongIdentity)
+    } else if password_slots.is_empty() {
+      Err(ShinError::KeyRequired("This file was encrypted for public keys, it needs an identity file".to_string()))
+    } else if password_slots.iter().all(|v| v.1) && self.keyfiles.is_empty() {
+      Err(ShinError::KeyRequired("This file needs a keyfile".to_string()))
+    } else if password_slots.iter().all(|v| v.0) && self.password.is_empty() {
+      Err(ShinError::KeyRequired("This file needs a password".to_string()))
+    } else {
+      Err(ShinError::KeyRequired("No key slot matches the given password and keyfiles".to_string()))
+    }
+  }
+
+  // A slot that opens but holds a key the file wasn't made with was tampered with or damaged, the password itself was right
+  fn checked_key(key_h: &KeyHeader, slot: usize, key: SecretKey) -> Result<(usize, SecretKey), ShinError> {
+    if !key_h.check_key(&key) {
+      return Err(ShinError::CorruptHeader(format!("The key header is corrupted, slot {} doesn't hold the key of this file", slot + 1)));
+    }
+    Ok((slot, key))
+  }
+
+  // A new slot with the data key wrapped under the password and keyfiles, every slot gets its own salt
+  fn password_slot(key: &[u8; 32], password: &str, keyfiles: &[std::path::PathBuf], kdf: &KdfParams) -> Result<KeySlot, ShinError> {
+    let salt = Self::get_salt(None);
+    let kek = Self::get_key(password, keyfiles, &salt, kdf)?;
+    Ok(KeySlot::Password { password: !password.is_empty(), keyfile: !keyfiles.is_empty(), kdf: *kdf, salt: salt.as_str().to_string(), wrapped: Self::wrap_key(&kek, key)? })
+  }
+
+  // The key encryption key is unique per slot (fresh salt), so the all zero nonce is never reused
+  fn wrap_key(kek: &[u8; 32], key: &[u8; 32]) -> Result<[u8; WRAPPED_KEY_SIZE], String> {
+    // Room for the tag up front, growing would leave a copy of the key in the old allocation
+    let mut wrapped = Vec::with_capacity(WRAPPED_KEY_SIZE);
+    wrapped.extend_from_slice(key);
+    chacha20poly1305::XChaCha20Poly1305::new(kek.into()).encrypt_in_place(&Default::default(), b"", &mut wrapped).map_err(|_| "Failed to wrap data key".to_string())?;
+    wrapped.try_into().map_err(|_| "Failed to wrap data key".to_string())
+  }
+
+  fn unwrap_key(kek: &[u8; 32], wrapped: &[u8; WRAPPED_KEY_SIZE]) -> Option<SecretKey> {
+    let mut key = Zeroizing::new(wrapped.to_vec());
+    chacha20poly1305::XChaCha20Poly1305::new(kek.into()).decrypt_in_place(&Default::default(), b"", &mut *key).ok()?;
+    SecretKey::from_slice(&key)
+  }
+
+  /// Key slots of a v2 file, reading them needs no password
+  pub fn key_slots(&self) -> Result<Vec<KeySlot>, ShinError> { Ok(self.read_key_header()?.slots) }
+
+  /// Adds a slot for another password (and keyfiles), unlocked with the current ones. Only the key header is rewritten
+  pub fn add_password(&self, new_password: impl AsRef<str>, new_keyfiles: &[std::path::PathBuf]) -> Result<(), ShinError> {
+    let new_password = new_password.as_ref();
+    if new_password.is_empty() && new_keyfiles.is_empty() {
+      return Err(ShinError::KeyRequired("The new key needs a password or a keyfile".to_string()));
+    }
+
+    let mut key_h = self.read_key_header()?;
+    let (_, key) = self.unlock_key(&key_h)?;
+    let free = key_h.slots.iter().position(|v| matches!(v, KeySlot::Empty)).ok_or("No free key slot left, remove a key first")?;
+
+    key_h.slots[free] = Self::password_slot(&key, new_password, new_keyfiles, &self.kdf)?;
+    self.write_key_header(&key_h)
+  }
+
+  /// Replaces the password (and keyfiles) of the slot the current ones open, the payload is left untouched
+  pub fn change_password(&self, new_password: impl AsRef<str>, new_keyfiles: &[std::path::PathBuf]) -> Result<(), ShinError> {
+    let new_password = new_password.as_ref();
+    if new_password.is_empty() && new_keyfiles.is_empty() {
+      return Err(ShinError::KeyRequired("The new key needs a password or a keyfile".to_string()));
+    }
+
+    let mut key_h = self.read_key_header()?;
+    let (slot, key) = self.unlock_key(&key_h)?;
+    if matches!(key_h.slots[slot], KeySlot::Recipient(_)) {
+      return Err("Public key slots have no password to change, add a password instead".into());
+    }
+    let new_slot = Self::password_slot(&key, new_password, new_keyfiles, &self.kdf)?;
+
+    // With a free slot the old key is only dropped once the new one is on disk, so a crash in between loses nothing
+    match key_h.slots.iter().position(|v| matches!(v, KeySlot::Empty)) {
+      Some(free) => {
+        key_h.slots[free] = new_slot;
+        self.write_key_header(&key_h)?;
+        key_h.slots[slot] = KeySlot::Empty;
+      }
+      None => key_h.slots[slot] = new_slot,
+    }
+    self.write_key_header(&key_h)
+  }
+
+  /// Removes the slot the current password (or identity) opens. Only the key header is rewritten
+  pub fn remove_key(&self) -> Result<(), ShinError> {
+    let mut key_h = self.read_key_header()?;
+    let (slot, _) = self.unlock_key(&key_h)?;
+
+    if key_h.slots.iter().filter(|v| !matches!(v, KeySlot::Empty)).count() < 2 {
+      return Err("This is the only key of the file, without it the file could never be opened again".into());
+    }
+
+    key_h.slots[slot] = KeySlot::Empty;
+    self.write_key_header(&key_h)
+  }
+
+  fn read_key_header(&self) -> Result<KeyHeader, ShinError> { self.key_header()?.ok_or_else(|| "Key slots need a v2 file, re-encrypt older files first".into()) }
+
+  /// Key header of the input, None for v1 files which have none. Reading it needs no password
+  pub fn key_header(&self) -> Result<Option<KeyHeader>, ShinError> {
+    let mut in_file = match std::fs::File::open(&self.input_path) {
+      Ok(v) => v,
+      Err(e) => return Err(ShinError::io("Failed to open input file", &self.input_path, e)),
+    };
+
+    let mut magic = [0u8; 3];
+    if in_file.read_exact(&mut magic).is_err() || magic != MAGIC {
+      return Ok(None);
+    }
+    match std::io::Seek::rewind(&mut in_file) {
+      Ok(v) => v,
+      Err(e) => return Err(ShinError::io("Failed to read input file", &self.input_path, e)),
+    };
+
+    KeyHeader::read_from(&mut in_file).map(Some)
+  }
+
+  // Slots have a fixed size, the new key header exactly overwrites the old one
+  fn write_key_header(&self, key_h: &KeyHeader) -> Result<(), ShinError> {
+    let key_h_vec = match key_h.to_vec() {
+      Ok(v) => v,
+      Err(e) => return Err(format!("Failed to create key header: {}", e).into()),
+    };
+
+    let mut out_file = match std::fs::OpenOptions::new().write(true).open(&self.input_path) {
+      Ok(v) => v,
+      Err(e) => return Err(ShinError::io("Failed to open file for writing", &self.input_path, e)),
+    };
+    match out_file.write_all(&key_h_vec) {
+      Ok(v) => v,
+      Err(e) => return Err(ShinError::io("Failed to write key header to", &self.input_path, e)),
+    };
+    out_file.sync_all().map_err(|e| ShinError::io("Failed to write key header to", &self.input_path, e))
+  }
+
+  /// Times encrypting and decrypting 1 GB of random data. Runs in a scratch directory next to the executable, which is removed afterwards
+  pub fn benchmark(threads: usize) -> Result<(std::time::Duration, std::time::Duration), ShinError> {
+    let path = Self::gen_file()?;
+    let output_dir = path.parent().unwrap().to_path_buf();
+
+    let res = Self::run_benchmark(&path, &output_dir, threads);
+    let _ = std::fs::remove_dir_all(&output_dir);
+    res
+  }
+
+  fn run_benchmark(path: &std::path::Path, output_dir: &std::path::Path, threads: usize) -> Result<(std::time::Duration, std::time::Duration), ShinError> {
+    let (encrypt_time, encrypted) = {
+      let time = std::time::Instant::now();
+
+      let mut shincrypt = ShinCrypt::new(path, output_dir, APPNAME, None);
+      shincrypt.set_threads(threads);
+      let encrypted = shincrypt.encrypt_file()?;
+
+      (time.elapsed(), encrypted)
+    };
+
+    // The source is still there, the decrypted copy gets a directory of its own
+    let decrypt_dir = output_dir.join("decrypted");
+    if let Err(e) = std::fs::create_dir_all(&decrypt_dir) {
+      return Err(ShinError::io("Failed to create directory", &decrypt_dir, e));
+    }
+
+    let decrypt_time = {
+      let time = std::time::Instant::now();
+
+      let mut shincrypt = ShinCrypt::new(encrypted, &decrypt_dir, APPNAME, None);
+      shincrypt.set_threads(threads);
+      shincrypt.decrypt_file()?;
+
+      time.elapsed()
+    };
+
+    Ok((encrypt_time, decrypt_time))
+  }
+
+  fn gen_file() -> Result<std::path::PathBuf, ShinError> {
+    let current_dir = std::env::current_exe().map_err(|e| format!("Failed to find the executable: {}", e))?.parent().unwrap().to_path_buf();
+    let benchmark_dir = current_dir.join(BENCHMARK_EXT);
+    if let Err(e) = std::fs::create_dir_all(&benchmark_dir) {
+      return Err(ShinError::io("Failed to create directory", &benchmark_dir, e));
+    }
+    let file_path = benchmark_dir.join(ENCRYPTION_EXT).with_extension(BENCHMARK_EXT);
+
+    let file = match std::fs::File::create(&file_path) {
+      Ok(v) => v,
+      Err(e) => return Err(ShinError::io("Failed to create benchmark file", &file_path, e)),
+    };
+    let mut file_buf = std::io::BufWriter::new(file);
+
+    let mut buffer = vec![0u8; FILE_1GB];
+
+    getrandom::fill(&mut buffer).map_err(|e| format!("Failed to generate benchmark data: {}", e))?;
+    match file_buf.write_all(&buffer).and_then(|_| file_buf.flush()) {
+      Ok(v) => v,
+      Err(e) => return Err(ShinError::io("Failed to write benchmark file", &file_path, e)),
+    };
+
+    Ok(file_path)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::logic::test_util::{TestDir, sample};
+
+  static SUITES: [EncMethod; 4] = [EncMethod::XChaCha20, EncMethod::XChaCha20Poly1305, EncMethod::XChaCha12Poly1305, EncMethod::Aes256GcmXChaCha20Poly1305];
+  static KEY: [u8; 32] = [7u8; 32];
+  static NONCE: [u8; NONCE_SIZE] = [9u8; NONCE_SIZE];
+
+  fn seal_stream(method: EncMethod, threads: usize, data: &[u8]) -> Vec<u8> {
+    let mut sealed = Vec::new();
+    let mut writer = EncryptingWriter::new(&mut sealed, payload_cipher(method, &KEY, &NONCE), threads);
+    writer.write_all(data).unwrap();
+    writer.finish().unwrap();
+    drop(writer);
+    sealed
+  }
+
+  fn open_stream(method: EncMethod, threads: usize, sealed: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut reader = DecryptingReader::new(sealed, payload_cipher(method, &KEY, &NONCE), threads);
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    Ok(data)
+  }
+
+  // Cheap key derivation, the defaults would make every test take seconds
+  fn fast_kdf() -> KdfParams { KdfParams { memory_kib: 1024, iterations: 1, parallelism: 1, ..KdfParams::default() } }
+
+  fn job(input: impl AsRef<std::path::Path>, output_dir: impl AsRef<std::path::Path>, password: &str) -> ShinCrypt {
+    let mut shincrypt = ShinCrypt::new(input, output_dir, password, None);
+    shincrypt.set_kdf_params(fast_kdf());
+    shincrypt
+  }
+
+  // Encrypts `input`, decrypts the result into its own directory and returns what came out
+  fn round_trip(dir: &TestDir, input: &std::path::Path, setup: impl Fn(&mut ShinCrypt)) -> Vec<u8> {
+    let mut encrypt = job(input, dir.subdir("encrypted"), "password");
+    setup(&mut encrypt);
+    encrypt.encrypt_file().unwrap();
+    let encrypted = dir.join("encrypted").join(format!("{}.{}", input.file_name().unwrap().to_str().unwrap(), ENCRYPTION_EXT));
+
+    let out_dir = dir.subdir("decrypted");
+    let mut decrypt = job(&encrypted, &out_dir, "password");
+    setup(&mut decrypt);
+    decrypt.decrypt_file().unwrap();
+
+    let output = std::fs::read(out_dir.join(input.file_name().unwrap())).unwrap();
+    std::fs::remove_dir_all(dir.join("encrypted")).unwrap();
+    std::fs::remove_dir_all(out_dir).unwrap();
+    output
+  }
+
+  // Every chunk is CHUNK bytes plus the tag, only the final one is shorter and flagged as last. An authenticated stream always has a final chunk
+  #[test]
+  fn stream_chunk_framing() {
+    let method = EncMethod::XChaCha20Poly1305;
+    let cipher = payload_cipher(method, &KEY, &NONCE);
+    for (len, chunks) in [(0, 1), (10, 1), (CHUNK, 1), (CHUNK + 1, 2), (2 * CHUNK + 10, 3)] {
+      let sealed = seal_stream(method, 1, &sample(len));
+      assert_eq!(sealed.len(), len + chunks * TAG_SIZE, "{} bytes", len);
+
+      let pieces = sealed.chunks(CHUNK + TAG_SIZE).collect::<Vec<_>>();
+      assert_eq!(pieces.len().max(1), chunks, "{} bytes", len);
+      for (i, piece) in pieces.iter().enumerate() {
+        let last = i + 1 == pieces.len();
+        assert!(cipher.decrypt(i as u64, !last, &mut piece.to_vec()).is_err(), "chunk {} of {} bytes", i, len);
+        cipher.decrypt(i as u64, last, &mut piece.to_vec()).unwrap();
+      }
+    }
+  }
+
+  #[test]
+  fn stream_round_trip_across_suites_and_threads() {
+    for method in SUITES {
+      for len in [0, 1, CHUNK - 1, CHUNK, CHUNK + 10] {
+        let data = sample(len);
+        let sealed = seal_stream(method, 1, &data);
+        for threads in [1, 4] {
+          // The pool only changes how fast it is, not the bytes
+          assert!(seal_stream(method, threads, &data) == sealed, "{:?}, {} bytes, {} threads", method, len, threads);
+          assert!(open_stream(method, threads, &sealed).unwrap() == data, "{:?}, {} bytes, {} threads", method, len, threads);
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn stream_detects_truncation_and_reordering() {
+    let method = EncMethod::XChaCha20Poly1305;
+    let sealed = seal_stream(method, 1, &sample(2 * CHUNK + 10));
+    let full = CHUNK + TAG_SIZE;
+
+    for threads in [1, 4] {
+      // Cut at a chunk boundary, every remaining chunk is intact but none is flagged as last
+      assert_eq!(open_stream(method, threads, &sealed[..2 * full]).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+      assert_eq!(open_stream(method, threads, &sealed[..sealed.len() - 1]).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+      assert_eq!(open_stream(method, threads, &[]).unwrap_err().kind(), std::io::ErrorKind::UnexpectedEof);
+
+      let mut swapped = sealed[full..2 * full].to_vec();
+      swapped.extend_from_slice(&sealed[..full]);
+      swapped.extend_from_slice(&sealed[2 * full..]);
+      assert_eq!(open_stream(method, threads, &swapped).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+    }
+  }
+
+  #[test]
+  fn file_round_trip_across_suites_threads_and_compression() {
+    let dir = TestDir::new();
+    for len in [0, CHUNK + 10] {
+      let input = dir.write("input.txt", &sample(len));
+      // Every suite against every thread count is covered by the stream test already, plain XChaCha20 only reads v1 files
+      for (method, threads) in SUITES[1..].iter().copied().zip([1, 4, 1]) {
+        assert!(round_trip(&dir, &input, |v| { v.set_encryption(method); v.set_threads(threads) }) == sample(len), "{:?}, {} threads, {} bytes", method, threads, len);
+      }
+      for algorithm in [CompressionAlgorithm::Zstd, CompressionAlgorithm::Lz4] {
+        assert!(round_trip(&dir, &input, |v| v.set_compression(CompressionParams { algorithm, level: 3 })) == sample(len), "{:?}, {} bytes", algorithm, len);
+      }
+    }
+  }
+
+  #[test]
+  fn directory_round_trip() {
+    let dir = TestDir::new();
+    let input = dir.subdir("folder");
+    std::fs::create_dir_all(input.join("nested/empty")).unwrap();
+    std::fs::write(input.join("a.txt"), sample(100)).unwrap();
+    std::fs::write(input.join("nested/b.txt"), sample(CHUNK + 5)).unwrap();
+
+    job(&input, dir.subdir("encrypted"), "password").encrypt_file().unwrap();
+    let out_dir = dir.subdir("decrypted");
+    job(dir.join("encrypted/folder.snc"), &out_dir, "password").decrypt_file().unwrap();
+
+    assert_eq!(std::fs::read(out_dir.join("folder/a.txt")).unwrap(), sample(100));
+    assert_eq!(std::fs::read(out_dir.join("folder/nested/b.txt")).unwrap(), sample(CHUNK + 5));
+    assert!(out_dir.join("folder/nested/empty").is_dir());
+  }
+
+  // Damage to the payload fails authentication instead of decrypting to garbage, nothing is left at the output
+  #[test]
+  fn damaged_payload_fails() {
+    let dir = TestDir::new();
+    let input = dir.write("input.txt", &sample(CHUNK + 10));
+    job(&input, dir.subdir("encrypted"), "password").encrypt_file().unwrap();
+    let encrypted = dir.join("encrypted/input.txt.snc");
+    let original = std::fs::read(&encrypted).unwrap();
+    let last_chunk = 10 + TAG_SIZE;
+
+    let damaged = [
+      original[..original.len() - last_chunk].to_vec(), // Ends on a chunk boundary
+      original[..original.len() - 1].to_vec(),
+      { let mut v = original.clone(); v[original.len() - 5] ^= 0x01; v },
+      { let mut v = original.clone(); v[original.len() - last_chunk - CHUNK] ^= 0x80; v },
+    ];
+    for (i, bytes) in damaged.iter().enumerate() {
+      std::fs::write(&encrypted, bytes).unwrap();
+      assert!(matches!(job(&encrypted, dir.subdir("decrypted"), "password").decrypt_file(), Err(ShinError::CorruptData(_))), "damage {}", i);
+      assert!(matches!(job(&encrypted, dir.subdir("decrypted"), "password").verify_file(), Err(ShinError::CorruptData(_))), "damage {}", i);
+      assert_eq!(dir.entries("decrypted").len(), 0, "damage {}", i);
+    }
+  }
+
+  // Laid out like the first release wrote it: salt line, nonce, then the zero padded header and the payload in one XChaCha20 keystream under the default Argon2 key
+  fn write_v1(path: &std::path::Path, password: &str, name: &str, data: &[u8]) {
+    use argon2::PasswordHasher;
+
+    let salt = ShinCrypt::get_salt(None);
+    let hash = argon2::Argon2::default().hash_password(password.as_bytes(), &salt).unwrap().hash.unwrap();
+    let source = format!("/home/user/{}", name);
+
+    // packed, file, version, encryption, then the name and the path, every number a u16
+    let mut fields = [0u16, 0, 1, EncMethod::XChaCha20 as u16, name.len() as u16].iter().flat_map(|v| v.to_le_bytes()).collect::<Vec<_>>();
+    fields.extend_from_slice(name.as_bytes());
+    fields.extend_from_slice(&(source.len() as u16).to_le_bytes());
+    fields.extend_from_slice(source.as_bytes());
+
+    let mut body = vec![0u8; FILE_HEADER_SIZE];
+    body[..fields.len()].copy_from_slice(&fields);
+    body.extend_from_slice(data);
+    chacha20::XChaCha20::new(hash.as_bytes().into(), &NONCE.into()).apply_keystream(&mut body);
+
+    let mut file = format!("{}\n", salt.as_str()).into_bytes();
+    file.extend_from_slice(&NONCE);
+    file.extend_from_slice(&body);
+    std::fs::write(path, file).unwrap();
+  }
+
+  #[test]
+  fn reads_v1_files() {
+    let dir = TestDir::new();
+    let input = dir.join("old.txt.snc");
+    write_v1(&input, "password", "old.txt", &sample(CHUNK + 10));
+
+    let out_dir = dir.subdir("decrypted");
+    job(&input, &out_dir, "password").decrypt_file().unwrap();
+    assert!(std::fs::read(out_dir.join("old.txt")).unwrap() == sample(CHUNK + 10));
+  }
+
+  #[test]
+  fn unauthenticated_suite_is_not_written() {
+    assert_eq!(EncMethod::default(), EncMethod::XChaCha20Poly1305);
+
+    let dir = TestDir::new();
+    let input = dir.write("input.txt", &sample(100));
+    let out_dir = dir.subdir("encrypted");
+    let mut shincrypt = job(&input, &out_dir, "password");
+    shincrypt.set_encryption(EncMethod::XChaCha20);
+    assert!(shincrypt.encrypt_file().is_err());
+    assert!(dir.entries(&out_dir).is_empty());
+  }
+
+  #[test]
+  fn file_header_round_trip() {
+    let mut file_h = FileHeader::new(true, true, EncMethod::XChaCha20Poly1305, MetadataPolicy::FullPath, "name.txt", "/some/where/name.txt");
+    file_h.compression = CompressionParams { algorithm: CompressionAlgorithm::Lz4, level: 5 };
+    file_h.padding = PaddingScheme::Padme;
+    file_h.data_len = Some(12345);
+    let vec = file_h.to_vec().unwrap();
+    assert!(vec.starts_with(MAGIC));
+
+    let parsed = FileHeader::from_vec(&vec).unwrap();
+    assert_eq!((parsed.packed, parsed.file, parsed.version, parsed.encryption, parsed.metadata), (true, true, ENCRYPTION_VERSION, EncMethod::XChaCha20Poly1305, MetadataPolicy::FullPath));
+    assert_eq!((parsed.name.as_str(), parsed.path.as_path()), ("name.txt", std::path::Path::new("/some/where/name.txt")));
+    assert_eq!((parsed.compression, parsed.padding, parsed.data_len), (file_h.compression, PaddingScheme::Padme, Some(12345)));
+
+    assert!(FileHeader::from_vec(&vec[..vec.len() - 1]).is_err());
+    let mut newer = vec.clone();
+    newer[3..5].copy_from_slice(&9u16.to_le_bytes());
+    assert!(FileHeader::from_vec(&newer).is_err());
+    assert!(FileHeader::new(false, false, EncMethod::XChaCha20Poly1305, MetadataPolicy::Name, "a".repeat(u16::MAX as usize + 1), "").to_vec().is_err());
+  }
+
+  #[test]
+  fn key_header_round_trip() {
+    let password = KeySlot::Password { password: true, keyfile: false, kdf: fast_kdf(), salt: "c2FsdHNhbHRzYWx0".to_string(), wrapped: [2u8; WRAPPED_KEY_SIZE] };
+    let key_h = KeyHeader::new(vec![password, KeySlot::Recipient([1u8; STANZA_SIZE])], NONCE, 100, &KEY);
+    let vec = key_h.to_vec().unwrap();
+
+    // Free slots are kept for keys added later, every slot has the same size
+    let parsed = KeyHeader::read_from(&mut vec.as_slice()).unwrap();
+    assert_eq!((parsed.version, parsed.nonce, parsed.header_len, parsed.slots.len()), (ENCRYPTION_VERSION, NONCE, 100, KEY_SLOTS));
+    assert!(matches!(&parsed.slots[0], KeySlot::Password { password: true, keyfile: false, kdf, salt, wrapped } if *kdf == fast_kdf() && salt == "c2FsdHNhbHRzYWx0" && *wrapped == [2u8; WRAPPED_KEY_SIZE]));
+    assert!(matches!(parsed.slots[1], KeySlot::Recipient(v) if v == [1u8; STANZA_SIZE]));
+    assert!(parsed.slots[2..].iter().all(|v| matches!(v, KeySlot::Empty)));
+    assert_eq!(vec.len(), KeyHeader::new(Vec::new(), NONCE, 100, &KEY).to_vec().unwrap().len());
+
+    assert!(matches!(KeyHeader::read_from(&mut &vec[..vec.len() - 1]), Err(ShinError::CorruptHeader(_))));
+    let too_large = KeyHeader { header_len: FILE_HEADER_SIZE as u32 + 1, ..key_h }.to_vec().unwrap();
+    assert!(matches!(KeyHeader::read_from(&mut too_large.as_slice()), Err(ShinError::CorruptHeader(_))));
+    let long_salt = KeySlot::Password { password: true, keyfile: false, kdf: fast_kdf(), salt: "s".repeat(SLOT_SIZE), wrapped: [0u8; WRAPPED_KEY_SIZE] };
+    assert!(KeyHeader::new(vec![long_salt], NONCE, 100, &KEY).to_vec().is_err());
+  }
+
+  #[test]
+  fn damaged_headers_fail() {
+    let dir = TestDir::new();
+    let input = dir.write("input.txt", &sample(100));
+    job(&input, dir.subdir("encrypted"), "password").encrypt_file().unwrap();
+    let encrypted = dir.join("encrypted/input.txt.snc");
+    let original = std::fs::read(&encrypted).unwrap();
+    let key_h_len = KeyHeader::read_from(&mut original.as_slice()).unwrap().to_vec().unwrap().len();
+    let decrypt = || job(&encrypted, dir.subdir("decrypted"), "password").decrypt_file();
+
+    let mut newer = original.clone();
+    newer[3..5].copy_from_slice(&9u16.to_le_bytes());
+    std::fs::write(&encrypted, newer).unwrap();
+    assert!(matches!(decrypt(), Err(ShinError::UnsupportedVersion(9))));
+
+    // The sealed file header is authenticated, and so is the nonce in front of the slots
+    for pos in [key_h_len + 3, 10] {
+      let mut flipped = original.clone();
+      flipped[pos] ^= 0x01;
+      std::fs::write(&encrypted, flipped).unwrap();
+      assert!(matches!(decrypt(), Err(ShinError::CorruptHeader(_))), "byte {}", pos);
+    }
+
+    std::fs::write(&encrypted, &original[..key_h_len + 5]).unwrap();
+    assert!(matches!(decrypt(), Err(ShinError::CorruptHeader(_))));
+
+    std::fs::write(&encrypted, sample(100)).unwrap();
+    assert!(matches!(decrypt(), Err(ShinError::NotShinCrypt)));
+  }
+
+  #[test]
+  fn wrong_password_is_reported() {
+    let dir = TestDir::new();
+    let input = dir.write("input.txt", &sample(100));
+    let encrypted = job(&input, dir.subdir("encrypted"), "password").encrypt_file().unwrap();
+    assert!(matches!(job(&encrypted, dir.subdir("decrypted"), "wrong").decrypt_file(), Err(ShinError::WrongPassword)));
+    assert!(matches!(job(&encrypted, dir.subdir("decrypted"), "wrong").verify_file(), Err(ShinError::WrongPassword)));
+    assert_eq!(dir.entries("decrypted").len(), 0);
+
+    let v1 = dir.join("old.txt.snc");
+    write_v1(&v1, "password", "old.txt", &sample(100));
+    assert!(matches!(job(&v1, dir.subdir("decrypted"), "wrong").decrypt_file(), Err(ShinError::WrongPassword)));
+  }
+
+  #[test]
+  fn key_header_versions() {
+    let slots = vec![KeySlot::Recipient([1u8; STANZA_SIZE])];
+    let key_h = KeyHeader::new(slots, NONCE, 100, &KEY);
+    let vec = key_h.to_vec().unwrap();
+
+    // v3 commits to the data key
+    let parsed = KeyHeader::read_from(&mut vec.as_slice()).unwrap();
+    assert_eq!(parsed.version, 3);
+    assert!(parsed.check_key(&KEY));
+    assert!(!parsed.check_key(&[8u8; 32]));
+
+    // v2 has no key check, any key that unwraps is taken
+    let v2 = KeyHeader { version: 2, key_check: None, ..key_h.clone() }.to_vec().unwrap();
+    assert_eq!(v2.len(), vec.len() - KEY_CHECK_SIZE);
+    let parsed = KeyHeader::read_from(&mut v2.as_slice()).unwrap();
+    assert_eq!((parsed.version, parsed.key_check, parsed.slots.len()), (2, None, KEY_SLOTS));
+    assert!(parsed.check_key(&[8u8; 32]));
+
+    let v4 = KeyHeader { version: 4, ..key_h.clone() }.to_vec().unwrap();
+    assert!(matches!(KeyHeader::read_from(&mut v4.as_slice()), Err(ShinError::UnsupportedVersion(4))));
+    assert!(matches!(KeyHeader::read_from(&mut &b"not a shincrypt file"[..]), Err(ShinError::NotShinCrypt)));
+    assert!(matches!(KeyHeader::read_from(&mut &vec[..vec.len() - 1]), Err(ShinError::CorruptHeader(_))));
+  }
+
+  // A slot that still opens but holds some other key was tampered with, that's damage and not a wrong password
+  #[test]
+  fn slot_with_a_foreign_key_is_corrupt() {
+    let dir = TestDir::new();
+    let input = dir.write("input.txt", &sample(100));
+    let encrypted = job(&input, dir.subdir("encrypted"), "password").encrypt_file().unwrap();
+    let shincrypt = job(&encrypted, dir.subdir("decrypted"), "password");
+    let mut key_h = shincrypt.read_key_header().unwrap();
+    key_h.slots[0] = ShinCrypt::password_slot(&[8u8; 32], "password", &[], &fast_kdf()).unwrap();
+    shincrypt.write_key_header(&key_h).unwrap();
+    assert!(matches!(shincrypt.decrypt_file(), Err(ShinError::CorruptHeader(_))));
+  }
+
+  #[test]
+  fn metadata_policy_limits_the_header() {
+    for (policy, name, path) in [(MetadataPolicy::None, "", ""), (MetadataPolicy::Name, "name.txt", ""), (MetadataPolicy::FullPath, "name.txt", "/some/where/name.txt")] {
+      let file_h = FileHeader::new(false, false, EncMethod::XChaCha20Poly1305, policy, "name.txt", "/some/where/name.txt");
+      let parsed = FileHeader::from_vec(&file_h.to_vec().unwrap()).unwrap();
+      assert_eq!((parsed.metadata, parsed.name.as_str(), parsed.path.as_path()), (policy, name, std::path::Path::new(path)), "{:?}", policy);
+    }
+  }
+
+  // Without a stored name the .snc file name is all there is, renaming it renames the output
+  #[test]
+  fn name_restored_from_the_file_name() {
+    let dir = TestDir::new();
+    let input = dir.write("input.txt", &sample(100));
+    let folder = dir.subdir("folder");
+    std::fs::write(folder.join("a.txt"), sample(10)).unwrap();
+
+    let out_dir = dir.subdir("decrypted");
+    for (source, renamed) in [(&input, "renamed.txt.snc"), (&folder, "other.snc")] {
+      job(source, dir.subdir("encrypted"), "password").encrypt_file().unwrap();
+      let encrypted = dir.join("encrypted").join(format!("{}.{}", source.file_name().unwrap().to_str().unwrap(), ENCRYPTION_EXT));
+      std::fs::rename(&encrypted, dir.join(renamed)).unwrap();
+      job(dir.join(renamed), &out_dir, "password").decrypt_file().unwrap();
+    }
+    assert_eq!(std::fs::read(out_dir.join("renamed.txt")).unwrap(), sample(100));
+    assert_eq!(std::fs::read(out_dir.join("other/a.txt")).unwrap(), sample(10));
+  }
+
+  // A stored name never points outside the output directory
+  #[test]
+  fn stored_name_stays_in_the_output_directory() {
+    let shincrypt = ShinCrypt::new("/files/input.txt.snc", "/out", "", None);
+    let file_h = FileHeader::new(false, false, EncMethod::XChaCha20Poly1305, MetadataPolicy::Name, "../../etc/evil.txt", "");
+    assert_eq!(shincrypt.restored_name(&file_h).unwrap(), "evil.txt");
+    let file_h = FileHeader::new(false, false, EncMethod::XChaCha20Poly1305, MetadataPolicy::None, "", "");
+    assert_eq!(shincrypt.restored_name(&file_h).unwrap(), "input.txt");
+  }
+
+  // Decrypting takes the parameters from the file, whatever the job was set to
+  #[test]
+  fn kdf_params_come_from_the_file() {
+    let dir = TestDir::new();
+    let input = dir.write("input.txt", &sample(100));
+    let kdf = KdfParams { algorithm: KdfAlgorithm::Argon2d, memory_kib: 2048, iterations: 2, parallelism: 2 };
+    let mut encrypt = job(&input, dir.subdir("encrypted"), "password");
+    encrypt.set_kdf_params(kdf);
+    encrypt.encrypt_file().unwrap();
+
+    let encrypted = dir.join("encrypted/input.txt.snc");
+    let key_h = KeyHeader::read_from(&mut std::fs::File::open(&encrypted).unwrap()).unwrap();
+    assert!(matches!(key_h.slots[0], KeySlot::Password { kdf: v, .. } if v == kdf));
+
+    let out_dir = dir.subdir("decrypted");
+    ShinCrypt::new(&encrypted, &out_dir, "password", None).decrypt_file().unwrap();
+    assert_eq!(std::fs::read(out_dir.join("input.txt")).unwrap(), sample(100));
+  }
+
+  #[test]
+  fn kdf_memory_limit() {
+    assert!(KdfParams { memory_kib: MAX_KDF_MEMORY_KIB + 1, ..fast_kdf() }.argon2(None).is_err());
+    assert!(KdfParams { iterations: 0, ..fast_kdf() }.argon2(None).is_err());
+    assert!(KdfParams { iterations: MAX_KDF_ITERATIONS + 1, ..fast_kdf() }.argon2(None).is_err());
+    assert!(KdfParams { parallelism: MAX_KDF_PARALLELISM + 1, ..fast_kdf() }.argon2(None).is_err());
+    fast_kdf().argon2(Some(&[1u8; 32])).unwrap();
+
+    // Settings Argon2 can't use fail encryption with their own error, before anything is written
+    let dir = TestDir::new();
+    let input = dir.write("input.txt", &sample(100));
+    let out_dir = dir.subdir("encrypted");
+    let mut shincrypt = job(&input, &out_dir, "password");
+    shincrypt.set_kdf_params(KdfParams { iterations: MAX_KDF_ITERATIONS + 1, ..fast_kdf() });
+    assert!(matches!(shincrypt.encrypt_file(), Err(ShinError::InvalidParams(_))));
+    assert!(dir.entries(&out_dir).is_empty());
+  }
+
+  fn decrypts(encrypted: &std::path::Path, password: &str, keyfiles: &[std::path::PathBuf]) -> Result<(), ShinError> {
+    let out_dir = encrypted.with_file_name("decrypted");
+    std::fs::create_dir_all(&out_dir).unwrap();
+    let mut shincrypt = job(encrypted, out_dir, password);
+    shincrypt.set_keyfiles(keyfiles.to_vec());
+    shincrypt.set_conflict_policy(ConflictPolicy::Overwrite); // The same file is decrypted again and again
+    shincrypt.decrypt_file()
+  }
+
+  #[test]
+  fn keyfiles_alone_or_with_a_password() {
+    let dir = TestDir::new();
+    let input = dir.write("input.txt", &sample(100));
+    let keyfiles = ["a.key", "b.key", "c.key"].map(|v| dir.join(v));
+    for keyfile in &keyfiles {
+      ShinCrypt::gen_keyfile(keyfile).unwrap();
+    }
+    assert!(ShinCrypt::gen_keyfile(&keyfiles[0]).is_err(), "an existing keyfile must not be overwritten");
+
+    let encrypted = dir.subdir("encrypted").join("input.txt.snc");
+    for password in ["", "password"] {
+      let mut encrypt = job(&input, dir.join("encrypted"), password);
+      encrypt.set_keyfiles(keyfiles[..2].to_vec());
+      encrypt.set_conflict_policy(ConflictPolicy::Overwrite);
+      encrypt.encrypt_file().unwrap();
+
+      // The order doesn't matter, every keyfile does
+      decrypts(&encrypted, password, &[keyfiles[1].clone(), keyfiles[0].clone()]).unwrap();
+      assert_eq!(std::fs::read(dir.join("encrypted/decrypted/input.txt")).unwrap(), sample(100));
+      assert!(matches!(decrypts(&encrypted, password, &keyfiles[..1]), Err(ShinError::WrongPassword)));
+      assert!(matches!(decrypts(&encrypted, password, &[keyfiles[0].clone(), keyfiles[2].clone()]), Err(ShinError::WrongPassword)));
+      assert!(matches!(decrypts(&encrypted, password, &[]), Err(ShinError::KeyRequired(_))));
+    }
+    assert!(matches!(decrypts(&encrypted, "", &keyfiles[..2]), Err(ShinError::KeyRequired(_))));
+    let missing = dir.join("missing.key");
+    assert!(matches!(decrypts(&encrypted, "password", std::slice::from_ref(&missing)), Err(ShinError::Io { path, .. }) if path == missing));
+    assert!(job(&input, dir.join("encrypted"), "").encrypt_file().is_err());
+  }
+
+  #[test]
+  fn recipients_open_with_their_identity() {
+    let dir = TestDir::new();
+    let input = dir.write("input.txt", &sample(100));
+    let identities = [Identity::generate().unwrap(), Identity::generate().unwrap(), Identity::generate().unwrap()];
+    for (i, identity) in identities.iter().enumerate() {
+      identity.save(dir.join(format!("identity{}.txt", i))).unwrap();
+    }
+
+    let mut encrypt = job(&input, dir.subdir("encrypted"), "");
+    encrypt.set_recipients(vec![identities[0].recipient(), identities[1].recipient()]);
+    encrypt.encrypt_file().unwrap();
+    let encrypted = dir.join("encrypted/input.txt.snc");
+    assert_eq!(job(&encrypted, "", "").key_slots().unwrap().iter().filter(|v| matches!(v, KeySlot::Recipient(_))).count(), 2);
+
+    let decrypt = |identity: Option<&str>| {
+      let mut shincrypt = job(&encrypted, dir.subdir("decrypted"), "");
+      shincrypt.set_conflict_policy(ConflictPolicy::Overwrite);
+      if let Some(v) = identity {
+        shincrypt.set_identity(dir.join(v));
+      }
+      shincrypt.decrypt_file()
+    };
+    for identity in ["identity0.txt", "identity1.txt"] {
+      decrypt(Some(identity)).unwrap();
+      assert_eq!(std::fs::read(dir.join("decrypted/input.txt")).unwrap(), sample(100));
+    }
+    assert!(matches!(decrypt(Some("identity2.txt")), Err(ShinError::WrongIdentity)));
+    assert!(matches!(decrypt(None), Err(ShinError::KeyRequired(_))));
+
+    // A password and recipients get a slot each
+    let mut both = job(&input, dir.subdir("encrypted"), "password");
+    both.set_recipients(vec![identities[0].recipient()]);
+    both.set_conflict_policy(ConflictPolicy::Overwrite);
+    both.encrypt_file().unwrap();
+    decrypt(Some("identity0.txt")).unwrap();
+    decrypts(&encrypted, "password", &[]).unwrap();
+  }
+
+  fn used_slots(encrypted: &std::path::Path) -> usize { job(encrypted, "", "").key_slots().unwrap().iter().filter(|v| !matches!(v, KeySlot::Empty)).count() }
+
+  #[test]
+  fn key_slots_add_and_remove() {
+    let dir = TestDir::new();
+    let input = dir.write("input.txt", &sample(100));
+    job(&input, dir.subdir("encrypted"), "first").encrypt_file().unwrap();
+    let encrypted = dir.join("encrypted/input.txt.snc");
+    let keyfiles = [dir.join("key.bin")];
+    ShinCrypt::gen_keyfile(&keyfiles[0]).unwrap();
+    assert_eq!(used_slots(&encrypted), 1);
+
+    // Added keys work next to the current one
+    job(&encrypted, "", "first").add_password("second", &[]).unwrap();
+    job(&encrypted, "", "second").add_password("", &keyfiles).unwrap();
+    assert_eq!(used_slots(&encrypted), 3);
+    decrypts(&encrypted, "first", &[]).unwrap();
+    decrypts(&encrypted, "second", &[]).unwrap();
+    decrypts(&encrypted, "", &keyfiles).unwrap();
+    assert!(matches!(job(&encrypted, "", "wrong").add_password("third", &[]), Err(ShinError::WrongPassword)));
+    assert!(matches!(job(&encrypted, "", "first").add_password("", &[]), Err(ShinError::KeyRequired(_))));
+
+    // Removing takes the slot of the given key, the last key can't go
+    job(&encrypted, "", "second").remove_key().unwrap();
+    assert!(matches!(decrypts(&encrypted, "second", &[]), Err(ShinError::WrongPassword)));
+    let mut keyfile_job = job(&encrypted, "", "");
+    keyfile_job.set_keyfiles(keyfiles.to_vec());
+    keyfile_job.remove_key().unwrap();
+    assert_eq!(used_slots(&encrypted), 1);
+    assert!(job(&encrypted, "", "first").remove_key().is_err());
+
+    // Only the key header was rewritten
+    decrypts(&encrypted, "first", &[]).unwrap();
+    assert_eq!(std::fs::read(dir.join("encrypted/decrypted/input.txt")).unwrap(), sample(100));
+  }
+
+  #[test]
+  fn key_slots_run_out() {
+    let dir = TestDir::new();
+    let input = dir.write("input.txt", &sample(100));
+    job(&input, dir.subdir("encrypted"), "password").encrypt_file().unwrap();
+    let encrypted = dir.join("encrypted/input.txt.snc");
+
+    for i in 1..KEY_SLOTS {
+      job(&encrypted, "", "password").add_password(format!("password {}", i), &[]).unwrap();
+    }
+    assert_eq!(used_slots(&encrypted), KEY_SLOTS);
+    assert!(matches!(job(&encrypted, "", "password").add_password("one more", &[]), Err(ShinError::Other(_))));
+    decrypts(&encrypted, &format!("password {}", KEY_SLOTS - 1), &[]).unwrap();
+
+    // Without a free slot the change happens in place
+    job(&encrypted, "", "password 3").change_password("replaced", &[]).unwrap();
+    decrypts(&encrypted, "replaced", &[]).unwrap();
+    assert!(matches!(decrypts(&encrypted, "password 3", &[]), Err(ShinError::WrongPassword)));
+  }
+
+  #[test]
+  fn password_change_keeps_the_other_slots() {
+    let dir = TestDir::new();
+    let input = dir.write("input.txt", &sample(100));
+    job(&input, dir.subdir("encrypted"), "first").encrypt_file().unwrap();
+    let encrypted = dir.join("encrypted/input.txt.snc");
+    job(&encrypted, "", "first").add_password("second", &[]).unwrap();
+
+    // Changing replaces the slot the current key opens
+    job(&encrypted, "", "first").change_password("changed", &[]).unwrap();
+    assert_eq!(used_slots(&encrypted), 2);
+    assert!(matches!(decrypts(&encrypted, "first", &[]), Err(ShinError::WrongPassword)));
+    decrypts(&encrypted, "changed", &[]).unwrap();
+    decrypts(&encrypted, "second", &[]).unwrap();
+    assert!(matches!(job(&encrypted, "", "wrong").change_password("third", &[]), Err(ShinError::WrongPassword)));
+    assert!(job(&encrypted, "", "changed").change_password("", &[]).is_err());
+    assert_eq!(std::fs::read(dir.join("encrypted/decrypted/input.txt")).unwrap(), sample(100));
+  }
+
+  // An identity unlocks the file to add a password for it
+  #[test]
+  fn password_added_with_an_identity() {
+    let dir = TestDir::new();
+    let input = dir.write("input.txt", &sample(100));
+    let identity = Identity::generate().unwrap();
+    identity.save(dir.join("identity.txt")).unwrap();
+
+    let mut encrypt = job(&input, dir.subdir("encrypted"), "");
+    encrypt.set_recipients(vec![identity.recipient()]);
+    encrypt.encrypt_file().unwrap();
+    let encrypted = dir.join("encrypted/input.txt.snc");
+    assert!(matches!(decrypts(&encrypted, "password", &[]), Err(ShinError::KeyRequired(_))));
+
+    let mut unlock = job(&encrypted, "", "");
+    unlock.set_identity(dir.join("identity.txt"));
+    unlock.add_password("password", &[]).unwrap();
+    // A public key slot has no password to change
+    assert!(unlock.change_password("other", &[]).is_err());
+    decrypts(&encrypted, "password", &[]).unwrap();
+  }
+
+  #[test]
+  fn seekable_reader_reads_any_position() {
+    let dir = TestDir::new();
+    let data = sample(2 * CHUNK + 10);
+    let input = dir.write("input.txt", &data);
+    for method in SUITES[1..].iter().copied() {
+      let mut encrypt = job(&input, dir.subdir("encrypted"), "password");
+      encrypt.set_encryption(method);
+      encrypt.encrypt_file().unwrap();
+      let (_, mut reader) = job(dir.join("encrypted/input.txt.snc"), "", "password").open_reader().unwrap();
+      assert_eq!(reader.size(), data.len() as u64);
+
+      // Reads across a chunk boundary, then back into the first chunk and from the end
+      for (pos, len) in [(CHUNK as u64 - 5, 10), (3, 4), (2 * CHUNK as u64 + 5, 5)] {
+        let mut buf = vec![0u8; len];
+        reader.seek(std::io::SeekFrom::Start(pos)).unwrap();
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf[..], data[pos as usize..pos as usize + len], "{:?} at {}", method, pos);
+      }
+      assert_eq!(reader.seek(std::io::SeekFrom::End(-3)).unwrap(), data.len() as u64 - 3);
+      assert!(reader.seek(std::io::SeekFrom::Current(-(data.len() as i64))).is_err());
+      std::fs::remove_dir_all(dir.join("encrypted")).unwrap();
+    }
+  }
+
+  #[test]
+  fn single_entry_extracted_from_an_archive() {
+    let dir = TestDir::new();
+    let input = dir.subdir("folder");
+    std::fs::create_dir_all(input.join("nested")).unwrap();
+    std::fs::write(input.join("a.txt"), sample(100)).unwrap();
+    std::fs::write(input.join("nested/b.txt"), sample(CHUNK + 5)).unwrap();
+    job(&input, dir.subdir("encrypted"), "password").encrypt_file().unwrap();
+
+    let out_dir = dir.subdir("extracted");
+    let shincrypt = job(dir.join("encrypted/folder.snc"), &out_dir, "password");
+    let entries = shincrypt.list_entries().unwrap();
+    let b = entries.iter().find(|v| v.path.ends_with("nested/b.txt")).unwrap();
+    assert_eq!(b.size, CHUNK as u64 + 5);
+    shincrypt.extract_entry(b).unwrap();
+    assert_eq!(dir.entries("extracted"), ["b.txt"]);
+    assert_eq!(std::fs::read(out_dir.join("b.txt")).unwrap(), sample(CHUNK + 5));
+
+    let nested = entries.iter().find(|v| v.path.ends_with("nested")).unwrap();
+    assert!(shincrypt.extract_entry(nested).is_err());
+
+    // A single file has no entries
+    let file = dir.write("input.txt", &sample(10));
+    job(&file, dir.subdir("encrypted"), "password").encrypt_file().unwrap();
+    assert!(job(dir.join("encrypted/input.txt.snc"), "", "password").list_entries().is_err());
+  }
+
+  #[test]
+  fn padme_padded_len() {
+    for (len, padded) in [(0, 0), (1, 1), (2, 2), (9, 10), (100, 104), (1000, 1024), (1025, 1088), (1_000_000, 1_015_808), ((1 << 40) + 1, 1_116_691_496_960)] {
+      assert_eq!(PaddingScheme::Padme.padded_len(len), padded, "{} bytes", len);
+    }
+
+    // At most 12% on top and never smaller for more data
+    let mut previous = 0;
+    for len in (2..200_000).step_by(7) {
+      let padded = PaddingScheme::Padme.padded_len(len);
+      assert!(padded >= len && (padded - len) * 100 <= len * 12 && padded >= previous, "{} bytes", len);
+      previous = padded;
+    }
+  }
+
+  #[test]
+  fn power_of_two_and_no_padding() {
+    for (len, padded) in [(0, 1), (1, 1), (5, 8), (1024, 1024), (1025, 2048)] {
+      assert_eq!(PaddingScheme::PowerOfTwo.padded_len(len), padded, "{} bytes", len);
+      assert_eq!(PaddingScheme::None.padded_len(len), len);
+    }
+  }
+
+  #[test]
+  fn padded_file_round_trip() {
+    let dir = TestDir::new();
+    let input = dir.write("input.txt", &sample(1000));
+
+    job(&input, dir.subdir("plain"), "password").encrypt_file().unwrap();
+    let mut encrypt = job(&input, dir.subdir("padded"), "password");
+    encrypt.set_padding(PaddingScheme::Padme);
+    encrypt.encrypt_file().unwrap();
+    let (plain, padded) = (dir.join("plain/input.txt.snc"), dir.join("padded/input.txt.snc"));
+    assert_eq!(std::fs::metadata(&padded).unwrap().len(), std::fs::metadata(&plain).unwrap().len() + 24);
+
+    let out_dir = dir.subdir("decrypted");
+    let decrypt = job(&padded, &out_dir, "password");
+    let (file_h, _) = decrypt.open_reader().unwrap();
+    assert_eq!((file_h.padding, file_h.data_len), (PaddingScheme::Padme, Some(1000)));
+    decrypt.decrypt_file().unwrap();
+    assert_eq!(std::fs::read(out_dir.join("input.txt")).unwrap(), sample(1000));
+  }
+
+  // The random name hides the original one, the header brings it back even when no metadata was asked for
+  #[test]
+  fn obfuscated_name_restored_from_the_header() {
+    let dir = TestDir::new();
+    let input = dir.write("secret name.txt", &sample(100));
+    let mut encrypt = job(&input, dir.subdir("encrypted"), "password");
+    encrypt.set_metadata_policy(MetadataPolicy::None);
+    encrypt.set_obfuscate_name(true);
+    let encrypted = encrypt.encrypt_file().unwrap();
+
+    let name = encrypted.file_stem().unwrap().to_str().unwrap();
+    assert!(name.len() == 32 && name.chars().all(|v| v.is_ascii_hexdigit()), "{}", name);
+    assert_eq!(encrypted.extension().unwrap(), ENCRYPTION_EXT);
+    assert_ne!(encrypt.encrypt_file().unwrap(), encrypted);
+
+    let out_dir = dir.subdir("decrypted");
+    job(&encrypted, &out_dir, "password").decrypt_file().unwrap();
+    assert_eq!(std::fs::read(out_dir.join("secret name.txt")).unwrap(), sample(100));
+  }
+
+  // A cancelled job fails with Cancelled at the next chunk and removes what it wrote
+  #[test]
+  fn cancelled_jobs_leave_nothing_behind() {
+    let dir = TestDir::new();
+    let input = dir.write("input.txt", &sample(CHUNK + 10));
+    let folder = dir.subdir("folder");
+    std::fs::write(folder.join("a.txt"), sample(CHUNK + 10)).unwrap();
+    let cancel = CancelToken::default();
+    cancel.cancel();
+
+    for input in [&input, &folder] {
+      let mut encrypt = job(input, dir.subdir("encrypted"), "password");
+      encrypt.set_cancel_token(cancel.clone());
+      assert!(matches!(encrypt.encrypt_file(), Err(ShinError::Cancelled)));
+      assert_eq!(dir.entries("encrypted").len(), 0);
+
+      let encrypted = job(input, dir.subdir("encrypted"), "password").encrypt_file().unwrap();
+      let mut decrypt = job(&encrypted, dir.subdir("decrypted"), "password");
+      decrypt.set_cancel_token(cancel.clone());
+      assert!(matches!(decrypt.decrypt_file(), Err(ShinError::Cancelled)));
+      assert_eq!(dir.entries("decrypted").len(), 0);
+      std::fs::remove_file(encrypted).unwrap();
+    }
+
+    // A reset token lets the next job run
+    cancel.reset();
+    let mut encrypt = job(&input, dir.subdir("encrypted"), "password");
+    encrypt.set_cancel_token(cancel);
+    encrypt.encrypt_file().unwrap();
+  }
+
+  // Every phase of a job reports, and each one ends complete
+  #[test]
+  fn progress_reports_every_phase() {
+    let dir = TestDir::new();
+    let input = dir.write("input.txt", &sample(CHUNK + 10));
+    let (sender, receiver) = crossbeam::channel::unbounded();
+    let mut encrypt = ShinCrypt::new(&input, dir.subdir("encrypted"), "password", Some(sender.clone()));
+    encrypt.set_kdf_params(fast_kdf());
+    let encrypted = encrypt.encrypt_file().unwrap();
+    let mut decrypt = ShinCrypt::new(&encrypted, dir.subdir("decrypted"), "password", Some(sender));
+    decrypt.set_kdf_params(fast_kdf());
+    decrypt.decrypt_file().unwrap();
+
+    let events = receiver.try_iter().collect::<Vec<_>>();
+    for phase in [ProgressPhase::KeyDerivation, ProgressPhase::Encrypting, ProgressPhase::Decrypting] {
+      let last = events.iter().rev().find(|v| v.phase == phase).unwrap_or_else(|| panic!("no {:?} event", phase));
+      assert!(last.total.is_none_or(|v| last.done >= v), "{:?} ended at {} of {:?}", phase, last.done, last.total);
+    }
+  }
+
+  // The conflict policy decides about an output that exists, Ask and Skip leave it alone
+  #[test]
+  fn existing_output_follows_the_policy() {
+    let dir = TestDir::new();
+    let input = dir.write("input.txt", &sample(100));
+    let out_dir = dir.subdir("encrypted");
+    let existing = job(&input, &out_dir, "password").encrypt_file().unwrap();
+    let before = std::fs::read(&existing).unwrap();
+
+    let encrypt = |policy| {
+      let mut shincrypt = job(&input, &out_dir, "password");
+      shincrypt.set_conflict_policy(policy);
+      shincrypt.encrypt_file()
+    };
+    assert!(matches!(encrypt(ConflictPolicy::Ask), Err(ShinError::OutputExists(v)) if v == existing));
+    assert!(matches!(encrypt(ConflictPolicy::Skip), Err(ShinError::Skipped(v)) if v == existing));
+    assert_eq!(std::fs::read(&existing).unwrap(), before);
+
+    assert_eq!(encrypt(ConflictPolicy::Rename).unwrap(), out_dir.join("input.txt (1).snc"));
+    assert_eq!(encrypt(ConflictPolicy::Overwrite).unwrap(), existing);
+    assert_ne!(std::fs::read(&existing).unwrap(), before);
+    assert_eq!(dir.entries("encrypted").len(), 2);
+
+    // Decrypting has the same choices
+    let mut decrypt = job(&existing, dir.path(), "password");
+    assert!(matches!(decrypt.decrypt_file(), Err(ShinError::OutputExists(_))));
+    decrypt.set_conflict_policy(ConflictPolicy::Rename);
+    decrypt.decrypt_file().unwrap();
+    assert_eq!(std::fs::read(dir.join("input (1).txt")).unwrap(), sample(100));
+
+    // Only the output itself is asked about, anything else that exists is an I/O error
+    assert!(matches!(ShinError::io("Failed to create temporary file", &existing, std::io::ErrorKind::AlreadyExists.into()), ShinError::Io { .. }));
+  }
+
+  // Skipped files of an archive are reported, the source must not be removed when the output isn't a full copy of it
+  #[test]
+  fn skipped_archive_entries_are_reported() {
+    let dir = TestDir::new();
+    let folder = dir.subdir("folder");
+    std::fs::write(folder.join("a.txt"), sample(100)).unwrap();
+    std::fs::write(folder.join("b.txt"), sample(200)).unwrap();
+    let encrypted = job(&folder, dir.subdir("encrypted"), "password").encrypt_file().unwrap();
+
+    let existing = dir.subdir("decrypted/folder");
+    std::fs::write(existing.join("a.txt"), b"kept").unwrap();
+    let mut decrypt = job(&encrypted, dir.join("decrypted"), "password");
+    decrypt.set_conflict_policy(ConflictPolicy::Skip);
+    assert!(matches!(decrypt.decrypt_file(), Err(ShinError::SkippedEntries(v)) if v == [existing.join("a.txt")]));
+    assert_eq!(std::fs::read(existing.join("a.txt")).unwrap(), b"kept");
+    assert_eq!(std::fs::read(existing.join("b.txt")).unwrap(), sample(200));
+
+    decrypt.set_conflict_policy(ConflictPolicy::Overwrite);
+    decrypt.decrypt_file().unwrap();
+    assert_eq!(std::fs::read(existing.join("a.txt")).unwrap(), sample(100));
+  }
+
+  // The verification pass decrypts the new file again before it is moved into place
+  #[test]
+  fn verified_encryption() {
+    let dir = TestDir::new();
+    let input = dir.write("input.txt", &sample(CHUNK + 10));
+    for algorithm in [CompressionAlgorithm::None, CompressionAlgorithm::Zstd] {
+      assert!(round_trip(&dir, &input, |v| { v.set_verify(true); v.set_padding(PaddingScheme::Padme); v.set_compression(CompressionParams { algorithm, level: 3 }) }) == sample(CHUNK + 10), "{:?}", algorithm);
+    }
+
+    let folder = dir.subdir("folder");
+    std::fs::write(folder.join("a.txt"), sample(100)).unwrap();
+    let mut encrypt = job(&folder, dir.subdir("encrypted"), "password");
+    encrypt.set_verify(true);
+    let encrypted = encrypt.encrypt_file().unwrap();
+    job(&encrypted, dir.subdir("decrypted"), "password").decrypt_file().unwrap();
+    assert_eq!(std::fs::read(dir.join("decrypted/folder/a.txt")).unwrap(), sample(100));
+  }
+
+  // Verification opens the written file through its key slots like decrypting does, a slot that doesn't unwrap the data key fails it
+  #[test]
+  fn verification_unlocks_the_written_slots() {
+    let dir = TestDir::new();
+    let input = dir.write("input.txt", &sample(100));
+    let shincrypt = job(&input, dir.subdir("encrypted"), "password");
+    let encrypted = shincrypt.encrypt_file().unwrap();
+    let mut hashing_writer = HashingWriter::new(std::io::sink(), true);
+    hashing_writer.write_all(&sample(100)).unwrap();
+    let checksum = hashing_writer.checksum().unwrap();
+
+    let opened = job(&encrypted, "", "password");
+    let mut key_h = opened.read_key_header().unwrap();
+    let (slot, key) = opened.unlock_key(&key_h).unwrap();
+    shincrypt.verify_output(&encrypted, &key, &checksum).unwrap();
+
+    if let KeySlot::Password { wrapped, .. } = &mut key_h.slots[slot] {
+      wrapped[0] ^= 1;
+    }
+    opened.write_key_header(&key_h).unwrap();
+    assert!(matches!(shincrypt.verify_output(&encrypted, &key, &checksum), Err(ShinError::CorruptHeader(_))));
+
+    // Without the identity only the data key can be checked
+    let mut encrypt = job(&input, dir.subdir("recipient"), "");
+    encrypt.set_recipients(vec![Identity::generate().unwrap().recipient()]);
+    encrypt.set_verify(true);
+    encrypt.encrypt_file().unwrap();
+  }
+
+  // Every .snc file below the directory gets its own report, one bad file doesn't stop the others
+  #[test]
+  fn verify_batch_reports_every_file() {
+    let dir = TestDir::new();
+    let input = dir.write("input.txt", &sample(CHUNK + 10));
+    let batch = dir.subdir("batch");
+    for (name, password) in [("good", "password"), ("nested/other", "other"), ("nested/deeper/damaged", "password")] {
+      let out_dir = dir.subdir(&format!("batch/{}", name));
+      let encrypted = job(&input, &out_dir, password).encrypt_file().unwrap();
+      std::fs::rename(&encrypted, out_dir.with_extension(ENCRYPTION_EXT)).unwrap();
+      std::fs::remove_dir(out_dir).unwrap();
+    }
+    let damaged = batch.join("nested/deeper/damaged.snc");
+    let mut bytes = std::fs::read(&damaged).unwrap();
+    let len = bytes.len();
+    bytes[len - 5] ^= 0x01;
+    std::fs::write(&damaged, bytes).unwrap();
+    dir.write("batch/notes.txt", b"not encrypted");
+
+    let reports = job(&batch, "", "password").verify_batch().unwrap();
+    let found = reports.iter().map(|v| (v.path.strip_prefix(&batch).unwrap().to_str().unwrap(), v.status())).collect::<Vec<_>>();
+    assert_eq!(found, [("good.snc", VerifyStatus::Ok), ("nested/deeper/damaged.snc", VerifyStatus::Corrupt), ("nested/other.snc", VerifyStatus::WrongPassword)]);
+
+    // Verifying writes nothing, and an empty directory has nothing to verify
+    assert_eq!(dir.entries("batch"), ["good.snc", "nested", "notes.txt"]);
+    assert!(job(dir.subdir("empty"), "", "password").verify_batch().is_err());
+  }
+
+  // The KDF parameters of a slot aren't authenticated, a crafted file must not make Argon2 run for hours
+  #[test]
+  fn kdf_limits_are_corrupt_header() {
+    let dir = TestDir::new();
+    let input = dir.write("input.txt", &sample(100));
+    let encrypted = job(&input, dir.subdir("encrypted"), "password").encrypt_file().unwrap();
+    let shincrypt = job(&encrypted, dir.subdir("decrypted"), "password");
+    let original = shincrypt.read_key_header().unwrap();
+
+    let limits: [fn(&mut KdfParams); 3] = [|v| v.memory_kib = MAX_KDF_MEMORY_KIB + 1, |v| v.iterations = MAX_KDF_ITERATIONS + 1, |v| v.parallelism = MAX_KDF_PARALLELISM + 1];
+    for (i, exceed) in limits.iter().enumerate() {
+      let mut key_h = original.clone();
+      if let KeySlot::Password { kdf, .. } = &mut key_h.slots[0] {
+        exceed(kdf);
+      }
+      shincrypt.write_key_header(&key_h).unwrap();
+      assert!(matches!(shincrypt.decrypt_file(), Err(ShinError::CorruptHeader(_))), "limit {}", i);
+    }
+
+    shincrypt.write_key_header(&original).unwrap();
+    shincrypt.verify_file().unwrap();
+  }
+
+  #[test]
+  fn padded_len_near_the_largest_size() {
+    for len in [u64::MAX, u64::MAX - 1, u64::MAX - (1 << 56)] {
+      assert_eq!(PaddingScheme::Padme.padded_len(len), len);
+      assert_eq!(PaddingScheme::PowerOfTwo.padded_len(len), len);
+    }
+    assert_eq!(PaddingScheme::Padme.padded_len((1 << 63) + 1), (1 << 63) + (1 << 57));
   }
 }
@@ -0,0 +1,101 @@
+/// Errors of the encryption API. The ones a caller can react to have their own variant, everything else is Other
+#[derive(Debug)]
+pub enum ShinError {
+  /// Reading or writing a file failed, `action` says what was being done
+  Io { action: &'static str, path: std::path::PathBuf, source: std::io::Error },
+  /// No key slot opens with the given password and keyfiles
+  WrongPassword,
+  /// The identity has no key slot in this file
+  WrongIdentity,
+  /// The file needs another kind of key than the one given, the message says which
+  KeyRequired(String),
+  /// The key header or the file header is damaged or was tampered with
+  CorruptHeader(String),
+  /// The encrypted data failed authentication or ends early
+  CorruptData(String),
+  /// Made by a newer version of ShinCrypt
+  UnsupportedVersion(u16),
+  /// The key derivation or cipher settings can't be used for encryption
+  InvalidParams(String),
+  NotShinCrypt,
+  Cancelled,
+  /// The output exists and the conflict policy is Ask, nothing was written
+  OutputExists(std::path::PathBuf),
+  /// The output exists and the conflict policy is Skip, nothing was written
+  Skipped(std::path::PathBuf),
+  /// An archive was decrypted into an existing directory and the conflict policy Skip kept these files in place of its own
+  SkippedEntries(Vec<std::path::PathBuf>),
+  Other(String),
+}
+
+impl ShinError {
+  /// An I/O error on `path`
+  pub fn io(action: &'static str, path: impl Into<std::path::PathBuf>, source: std::io::Error) -> Self {
+    Self::Io { action, path: path.into(), source }
+  }
+
+  /// An I/O error while streaming decrypted data. The decrypting readers fail with InvalidData or UnexpectedEof, those are about the encrypted data and not the file
+  pub fn decrypt(action: &'static str, path: impl Into<std::path::PathBuf>, source: std::io::Error) -> Self {
+    match source.kind() {
+      std::io::ErrorKind::InvalidData | std::io::ErrorKind::UnexpectedEof => Self::CorruptData(source.to_string()),
+      _ => Self::io(action, path, source),
+    }
+  }
+
+  /// What the user can do about it, shown under the error
+  pub fn remediation(&self) -> Option<&'static str> {
+    match self {
+      Self::Io { .. } => Some("Check that the path exists, is accessible and that there is enough free space"),
+      Self::WrongPassword => Some("Check the password and keyfiles and try again"),
+      Self::WrongIdentity => Some("Use the identity the file was encrypted for, or its password"),
+      Self::KeyRequired(_) => None,
+      Self::CorruptHeader(_) | Self::CorruptData(_) => Some("The file is damaged, restore it from a backup"),
+      Self::UnsupportedVersion(_) => Some("Update ShinCrypt to open this file"),
+      Self::InvalidParams(_) => Some("Change the key derivation or cipher settings"),
+      Self::NotShinCrypt => Some("Select a file encrypted with ShinCrypt"),
+      Self::Cancelled => None,
+      Self::OutputExists(_) => Some("Move or rename the existing file, choose another output directory or change what happens to existing files in the settings"),
+      Self::Skipped(_) | Self::SkippedEntries(_) => None,
+      Self::Other(_) => None,
+    }
+  }
+}
+
+impl std::fmt::Display for ShinError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Self::Io { action, path, source } => write!(f, "{} {:?}: {}", action, path, source),
+      Self::WrongPassword => write!(f, "Wrong password or keyfile"),
+      Self::WrongIdentity => write!(f, "This file wasn't encrypted for this identity"),
+      Self::KeyRequired(v) => write!(f, "{}", v),
+      Self::CorruptHeader(v) => write!(f, "{}", v),
+      Self::CorruptData(v) => write!(f, "{}", v),
+      Self::UnsupportedVersion(v) => write!(f, "Unsupported file version {}, this version of ShinCrypt reads up to {}", v, crate::logic::encryption::ENCRYPTION_VERSION),
+      Self::InvalidParams(v) => write!(f, "{}", v),
+      Self::NotShinCrypt => write!(f, "Not a ShinCrypt file"),
+      Self::Cancelled => write!(f, "Cancelled, the partial output was removed"),
+      Self::OutputExists(v) => write!(f, "Output already exists: {:?}", v),
+      Self::Skipped(v) => write!(f, "Skipped, the output already exists: {:?}", v),
+      Self::SkippedEntries(v) => write!(f, "Decrypted, but {} existing files were kept instead of the ones in the archive: {:?}", v.len(), v),
+      Self::Other(v) => write!(f, "{}", v),
+    }
+  }
+}
+
+impl std::error::Error for ShinError {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      Self::Io { source, .. } => Some(source),
+      _ => None,
+    }
+  }
+}
+
+// The helpers below the API still report plain messages
+impl From<String> for ShinError {
+  fn from(value: String) -> Self { Self::Other(value) }
+}
+
+impl From<&str> for ShinError {
+  fn from(value: &str) -> Self { Self::Other(value.to_string()) }
+}
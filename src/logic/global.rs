@@ -1,4 +1,4 @@
-use crate::{AppState, OLDAPPNAME};
+use crate::{AppState, OLDAPPNAME, logic::{error::ShinError, output::ConflictPolicy, secret::Secret}};
 use gtk::{gdk::prelude::DisplayExt, prelude::*};
 use gtk4 as gtk;
 use parking_lot::RwLock;
@@ -50,6 +50,18 @@ impl Global {
     data_mb / duration_secs
   }
 
+  pub fn format_size(bytes: u64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+      size /= 1024.0;
+      unit += 1;
+    }
+
+    if unit == 0 { format!("{} {}", bytes, units[0]) } else { format!("{:.2} {}", size, units[unit]) }
+  }
+
   pub fn check_for_update(aps: Arc<RwLock<AppState>>) -> Result<bool, String> {
     let current_version = aps.read().consts.version.clone();
     let url = aps.read().consts.download_url.clone();
@@ -184,6 +196,15 @@ impl Global {
 
     Ok(())
   }
+
+  /// Moves a file or directory to the desktop trash, where it can still be restored
+  pub fn trash_path(path: std::path::PathBuf) -> Result<(), String> {
+    if let Err(e) = gtk::gio::File::for_path(&path).trash(gtk::gio::Cancellable::NONE) {
+      return Err(format!("Can't be moved to the trash\n{}", e));
+    }
+
+    Ok(())
+  }
 }
 
 // pub struct Tar {}
@@ -340,6 +361,54 @@ impl GTKhelper {
     alert.choose(Some(window), None::<&gtk::gio::Cancellable>, move |res| if res == Ok(1) {});
   }
 
+  /// Reports why a file dialog failed, closing it without choosing anything isn't an error
+  pub fn dialog_error(window: &gtk::ApplicationWindow, err: gtk::glib::Error) {
+    if !err.matches(gtk::DialogError::Dismissed) {
+      Self::message_box(window, "Error", err.message(), None);
+    }
+  }
+
+  /// Text of a password entry in locked memory, for everything ShinCrypt does with it afterwards.
+  /// Known limitation: GTK keeps the text in the entry's own buffer, and text() hands out a GString copy that is freed without being wiped. Neither can be wiped from here
+  pub fn password_text(entry: &gtk::PasswordEntry) -> Secret<String> { Secret::from(entry.text().as_str()) }
+
+  /// Failure of an encryption operation, with what the user can do about it
+  pub fn error_box(window: &gtk::ApplicationWindow, error: &ShinError) {
+    let title = match error {
+      ShinError::WrongPassword | ShinError::WrongIdentity => "Wrong key",
+      ShinError::CorruptHeader(_) | ShinError::CorruptData(_) => "Corrupted file",
+      ShinError::Cancelled => "Cancelled",
+      ShinError::Skipped(_) | ShinError::SkippedEntries(_) => "Skipped",
+      _ => "Failed",
+    };
+    let detail = match error.remediation() {
+      Some(v) => format!("{}\n\n{}", error, v),
+      None => error.to_string(),
+    };
+    Self::message_box(window, title, detail, None);
+  }
+
+  /// Asks what to do about an output that already exists, `retry` runs the job again with the chosen policy
+  pub fn ask_conflict(window: &gtk::ApplicationWindow, path: &std::path::Path, retry: impl Fn(ConflictPolicy) + 'static) {
+    let alert = gtk::AlertDialog::builder().modal(true).message("Output already exists").detail(format!("{}\n\nThe choice applies to every existing file of this job", path.display())).buttons(vec!["Cancel", "Skip", "Overwrite", "Keep both"]).default_button(3).cancel_button(0).build();
+    alert.choose(Some(window), None::<&gtk::gio::Cancellable>, move |res| match res {
+      Ok(1) => retry(ConflictPolicy::Skip),
+      Ok(2) => retry(ConflictPolicy::Overwrite),
+      Ok(3) => retry(ConflictPolicy::Rename),
+      _ => (),
+    });
+  }
+
+  /// Asks before shredding `path` where it may not reach the data, `remove` gets true to shred and false to move it to the trash. Cancel keeps the file
+  pub fn ask_shred(window: &gtk::ApplicationWindow, path: &std::path::Path, warning: &str, remove: impl FnOnce(bool) + 'static) {
+    let alert = gtk::AlertDialog::builder().modal(true).message("Shredding may not erase the source").detail(format!("{}\n\n{}", path.display(), warning)).buttons(vec!["Keep", "Move to trash", "Shred anyway"]).default_button(1).cancel_button(0).build();
+    alert.choose(Some(window), None::<&gtk::gio::Cancellable>, move |res| match res {
+      Ok(1) => remove(false),
+      Ok(2) => remove(true),
+      _ => (),
+    });
+  }
+
   // pub fn message_box(window: &gtk::ApplicationWindow, message: impl AsRef<str>, detail: impl AsRef<str>, buttons: Option<Vec<&str>>, callback: Option<impl Fn(String) + Copy + Clone + 'static>) {
   //   let dialog = gtk::Window::builder().transient_for(window).modal(true).title(message.as_ref()).default_width(300).build();
 
@@ -374,7 +443,8 @@ impl GTKhelper {
   //   dialog.present();
   // }
 
-  pub fn drag_n_drop(entry: &gtk::Entry) {
+  pub fn drag_n_drop(entry: &impl IsA<gtk::Editable>) {
+    let entry = entry.upcast_ref::<gtk::Editable>();
     let entry_c = entry.clone();
 
     // Create a DropTarget for files
@@ -0,0 +1,274 @@
+use blake2::digest::{KeyInit, Mac};
+use chacha20poly1305::aead::AeadInPlace;
+use crate::logic::secret::SecretKey;
+use std::io::Write;
+use zeroize::{Zeroize, Zeroizing};
+
+static PUBLIC_PREFIX: &str = "snc-pk-";
+static SECRET_PREFIX: &str = "snc-sk-";
+static WRAP_CONTEXT: &[u8] = b"ShinCrypt recipient";
+const KEY_SIZE: usize = 32;
+pub const STANZA_SIZE: usize = 32 + 32 + 16; // Ephemeral public key + wrapped data key + Poly1305 tag
+
+/// Public key files can be encrypted for, shared as "snc-pk-<hex>"
+#[derive(Clone)]
+pub struct Recipient {
+  key: x25519_dalek::PublicKey,
+}
+
+impl std::str::FromStr for Recipient {
+  type Err = String;
+
+  fn from_str(text: &str) -> Result<Self, Self::Err> {
+    let hex = text.trim().strip_prefix(PUBLIC_PREFIX).ok_or_else(|| format!("Not a public key: {}", text.trim()))?;
+    let key: [u8; KEY_SIZE] = from_hex(hex).and_then(|v| v.try_into().ok()).ok_or_else(|| format!("Invalid public key: {}", text.trim()))?;
+    Ok(Self { key: key.into() })
+  }
+}
+
+impl Recipient {
+  /// Every public key found in the text, separated by whitespace or commas. Identity files contain theirs too
+  pub fn parse_all(text: &str) -> Result<Vec<Self>, String> { text.split(|c: char| c.is_whitespace() || c == ',').filter(|v| v.starts_with(PUBLIC_PREFIX)).map(|v| v.parse()).collect() }
+
+  /// Recipients from a list of public keys and files containing them
+  pub fn from_list(items: impl IntoIterator<Item = impl AsRef<std::path::Path>>) -> Result<Vec<Self>, String> {
+    let mut recipients = Vec::new();
+    for item in items {
+      let item = item.as_ref();
+      let text = match item.to_str() {
+        Some(v) if v.trim().starts_with(PUBLIC_PREFIX) => v.to_string(),
+        _ => std::fs::read_to_string(item).map_err(|e| format!("Failed to read public key file {:?}: {}", item, e))?,
+      };
+
+      let found = Self::parse_all(&text)?;
+      if found.is_empty() {
+        return Err(format!("No public key found in {:?}", item));
+      }
+      recipients.extend(found);
+    }
+
+    Ok(recipients)
+  }
+
+  /// Writes the public key alone, the file that gets shared
+  pub fn save(&self, path: impl AsRef<std::path::Path>) -> Result<(), String> {
+    let path = path.as_ref();
+    std::fs::write(path, format!("{}\n", self)).map_err(|e| format!("Failed to write public key file at {:?}: {}", path, e))
+  }
+
+  /// Seals the data key so only the matching identity can open it, a fresh ephemeral key is used every time
+  pub fn wrap(&self, data_key: &[u8; KEY_SIZE]) -> Result<[u8; STANZA_SIZE], String> {
+    let mut secret = [0u8; KEY_SIZE];
+    getrandom::fill(&mut secret).map_err(|e| format!("Failed to generate ephemeral key: {}", e))?;
+    let ephemeral = x25519_dalek::StaticSecret::from(secret);
+    secret.zeroize();
+    let ephemeral_pk = x25519_dalek::PublicKey::from(&ephemeral);
+
+    let shared = ephemeral.diffie_hellman(&self.key);
+    if !shared.was_contributory() {
+      return Err("Invalid public key".to_string());
+    }
+
+    // Room for the tag up front, growing would leave a copy of the key in the old allocation
+    let mut wrapped = Vec::with_capacity(STANZA_SIZE - KEY_SIZE);
+    wrapped.extend_from_slice(data_key);
+    wrap_aead(shared.as_bytes(), &ephemeral_pk, &self.key).encrypt_in_place(&Default::default(), b"", &mut wrapped).map_err(|_| "Failed to wrap data key".to_string())?;
+
+    let mut stanza = [0u8; STANZA_SIZE];
+    stanza[..KEY_SIZE].copy_from_slice(ephemeral_pk.as_bytes());
+    stanza[KEY_SIZE..].copy_from_slice(&wrapped);
+    Ok(stanza)
+  }
+}
+
+impl std::fmt::Display for Recipient {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result { write!(f, "{}{}", PUBLIC_PREFIX, to_hex(self.key.as_bytes())) }
+}
+
+/// Secret key that opens files encrypted for its public key, stored as "snc-sk-<hex>"
+pub struct Identity {
+  secret: x25519_dalek::StaticSecret,
+}
+
+impl Identity {
+  pub fn generate() -> Result<Self, String> {
+    let mut secret = [0u8; KEY_SIZE];
+    getrandom::fill(&mut secret).map_err(|e| format!("Failed to generate identity: {}", e))?;
+    let identity = Self { secret: secret.into() };
+    secret.zeroize();
+    Ok(identity)
+  }
+
+  pub fn from_file(path: impl AsRef<std::path::Path>) -> Result<Self, String> {
+    let path = path.as_ref();
+    let text = Zeroizing::new(std::fs::read_to_string(path).map_err(|e| format!("Failed to read identity file {:?}: {}", path, e))?);
+    let hex = text.lines().find_map(|v| v.trim().strip_prefix(SECRET_PREFIX)).ok_or_else(|| format!("No secret key found in {:?}", path))?;
+    let bytes = Zeroizing::new(from_hex(hex).ok_or_else(|| format!("Invalid secret key in {:?}", path))?);
+    let mut secret: [u8; KEY_SIZE] = bytes.as_slice().try_into().map_err(|_| format!("Invalid secret key in {:?}", path))?;
+    let identity = Self { secret: secret.into() };
+    secret.zeroize();
+    Ok(identity)
+  }
+
+  /// Writes the identity with its public key as a comment, never overwrites an existing file
+  pub fn save(&self, path: impl AsRef<std::path::Path>) -> Result<(), String> {
+    let path = path.as_ref();
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    let mut file = options.open(path).map_err(|e| format!("Failed to create identity file at {:?}: {}", path, e))?;
+    let hex = Zeroizing::new(to_hex(self.secret.as_bytes()));
+    let text = Zeroizing::new(format!("# ShinCrypt identity, keep it secret\n# public key: {}\n{}{}\n", self.recipient(), SECRET_PREFIX, *hex));
+    file.write_all(text.as_bytes()).map_err(|e| format!("Failed to write identity file: {}", e))?;
+    file.sync_all().map_err(|e| format!("Failed to write identity file: {}", e))?;
+
+    Ok(())
+  }
+
+  pub fn recipient(&self) -> Recipient { Recipient { key: x25519_dalek::PublicKey::from(&self.secret) } }
+
+  /// The data key, if the stanza was wrapped for this identity
+  pub fn unwrap(&self, stanza: &[u8]) -> Option<SecretKey> {
+    let ephemeral_pk: [u8; KEY_SIZE] = stanza.get(..KEY_SIZE)?.try_into().ok()?;
+    let ephemeral_pk = x25519_dalek::PublicKey::from(ephemeral_pk);
+
+    let shared = self.secret.diffie_hellman(&ephemeral_pk);
+    if !shared.was_contributory() {
+      return None;
+    }
+
+    let mut data_key = Zeroizing::new(stanza.get(KEY_SIZE..)?.to_vec());
+    wrap_aead(shared.as_bytes(), &ephemeral_pk, &self.recipient().key).decrypt_in_place(&Default::default(), b"", &mut *data_key).ok()?;
+    SecretKey::from_slice(&data_key)
+  }
+}
+
+// The wrap key is unique per stanza (fresh ephemeral key), so the all zero nonce is never reused
+fn wrap_aead(shared: &[u8; KEY_SIZE], ephemeral_pk: &x25519_dalek::PublicKey, recipient_pk: &x25519_dalek::PublicKey) -> chacha20poly1305::XChaCha20Poly1305 {
+  let mut mac = <blake2::Blake2bMac<blake2::digest::consts::U32> as KeyInit>::new_from_slice(shared).expect("X25519 output is a valid BLAKE2b key");
+  mac.update(WRAP_CONTEXT);
+  mac.update(ephemeral_pk.as_bytes());
+  mac.update(recipient_pk.as_bytes());
+  let mut wrap_key: [u8; KEY_SIZE] = mac.finalize().into_bytes().into();
+  let aead = chacha20poly1305::XChaCha20Poly1305::new(&wrap_key.into());
+  wrap_key.zeroize();
+  aead
+}
+
+fn to_hex(bytes: &[u8]) -> String { bytes.iter().map(|v| format!("{:02x}", v)).collect() }
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+  let hex = hex.trim();
+  if !hex.len().is_multiple_of(2) {
+    return None;
+  }
+  (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::logic::test_util::TestDir;
+
+  #[test]
+  fn recipient_text_round_trip() {
+    let recipient = Identity::generate().unwrap().recipient();
+    let text = recipient.to_string();
+    assert!(text.starts_with(PUBLIC_PREFIX) && text.len() == PUBLIC_PREFIX.len() + KEY_SIZE * 2);
+    assert_eq!(format!(" {}\n", text).parse::<Recipient>().unwrap().to_string(), text);
+  }
+
+  #[test]
+  fn invalid_recipients_are_rejected() {
+    let valid = Identity::generate().unwrap().recipient().to_string();
+    for text in ["", "snc-pk-", "pk-00", &valid[1..], &valid[..valid.len() - 1], &valid[..valid.len() - 2], &format!("{}00", valid), &format!("{}{}", PUBLIC_PREFIX, "zz".repeat(KEY_SIZE))] {
+      assert!(text.parse::<Recipient>().is_err(), "{:?}", text);
+    }
+  }
+
+  #[test]
+  fn parse_all_finds_every_key() {
+    let keys = [Identity::generate().unwrap().recipient().to_string(), Identity::generate().unwrap().recipient().to_string()];
+    let found = Recipient::parse_all(&format!("# team keys\n{},\n  {} trailing words", keys[0], keys[1])).unwrap();
+    assert_eq!(found.iter().map(|v| v.to_string()).collect::<Vec<_>>(), keys);
+
+    assert!(Recipient::parse_all("no keys here").unwrap().is_empty());
+    assert!(Recipient::parse_all(&format!("{} snc-pk-00", keys[0])).is_err());
+  }
+
+  #[test]
+  fn wrap_opens_only_for_the_identity() {
+    let identity = Identity::generate().unwrap();
+    let other = Identity::generate().unwrap();
+    let file_key = [7u8; KEY_SIZE];
+
+    let stanza = identity.recipient().wrap(&file_key).unwrap();
+    assert_eq!(*identity.unwrap(&stanza).unwrap(), file_key);
+    assert!(other.unwrap(&stanza).is_none());
+
+    // Every wrap uses a fresh ephemeral key
+    assert_ne!(identity.recipient().wrap(&file_key).unwrap(), stanza);
+
+    let mut damaged = stanza;
+    damaged[STANZA_SIZE - 1] ^= 1;
+    assert!(identity.unwrap(&damaged).is_none());
+    assert!(identity.unwrap(&stanza[..STANZA_SIZE - 1]).is_none());
+    assert!(identity.unwrap(&[]).is_none());
+  }
+
+  #[test]
+  fn identity_file_round_trip() {
+    let dir = TestDir::new();
+    let identity = Identity::generate().unwrap();
+    let path = dir.join("identity.txt");
+    identity.save(&path).unwrap();
+    assert!(identity.save(&path).is_err(), "an existing identity file must not be overwritten");
+
+    let loaded = Identity::from_file(&path).unwrap();
+    assert_eq!(loaded.recipient().to_string(), identity.recipient().to_string());
+
+    // The public key in the comment is enough to encrypt for it
+    let recipients = Recipient::from_list([&path]).unwrap();
+    assert_eq!(recipients.len(), 1);
+    assert!(loaded.unwrap(&recipients[0].wrap(&[1u8; KEY_SIZE]).unwrap()).is_some());
+
+    #[cfg(unix)]
+    assert_eq!(std::os::unix::fs::PermissionsExt::mode(&std::fs::metadata(&path).unwrap().permissions()) & 0o777, 0o600);
+  }
+
+  #[test]
+  fn damaged_identity_files_are_rejected() {
+    let dir = TestDir::new();
+    for (name, text) in [("empty", String::new()), ("short", format!("{}00ff", SECRET_PREFIX)), ("hex", format!("{}{}", SECRET_PREFIX, "zz".repeat(KEY_SIZE)))] {
+      assert!(Identity::from_file(dir.write(name, text.as_bytes())).is_err(), "{}", name);
+    }
+    assert!(Identity::from_file(dir.join("missing")).is_err());
+  }
+
+  #[test]
+  fn recipient_list_from_keys_and_files() {
+    let dir = TestDir::new();
+    let recipient = Identity::generate().unwrap().recipient();
+    let path = dir.join("key.pub");
+    recipient.save(&path).unwrap();
+
+    let recipients = Recipient::from_list([std::path::PathBuf::from(recipient.to_string()), path.clone()]).unwrap();
+    assert_eq!(recipients.iter().map(|v| v.to_string()).collect::<Vec<_>>(), [recipient.to_string(), recipient.to_string()]);
+
+    std::fs::write(&path, "nothing").unwrap();
+    assert!(Recipient::from_list([&path]).is_err_and(|e| e.contains("No public key")));
+    assert!(Recipient::from_list([dir.join("missing")]).is_err());
+  }
+
+  #[test]
+  fn hex_helpers() {
+    assert_eq!(to_hex(&[0x00, 0x0f, 0xab, 0xff]), "000fabff");
+    assert_eq!(from_hex(" 000fABff\n"), Some(vec![0x00, 0x0f, 0xab, 0xff]));
+    assert_eq!(from_hex(""), Some(Vec::new()));
+    assert_eq!(from_hex("abc"), None);
+    assert_eq!(from_hex("zz"), None);
+    assert_eq!(from_hex("é0"), None);
+  }
+}
@@ -1,2 +1,12 @@
+pub(crate) mod cipher;
+pub(crate) mod compression;
 pub(crate) mod encryption;
+pub(crate) mod error;
 pub(crate) mod global;
+pub(crate) mod identity;
+pub(crate) mod output;
+pub(crate) mod progress;
+pub(crate) mod secret;
+pub(crate) mod shred;
+#[cfg(test)]
+pub(crate) mod test_util;
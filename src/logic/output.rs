@@ -0,0 +1,296 @@
+use serde::{Deserialize, Serialize};
+
+static TEMP_PREFIX: &str = ".shincrypt-";
+static TEMP_EXT: &str = "part";
+static LOCK_NAME: &str = ".lock"; // Held by the job writing the output, a free lock means the job is gone
+
+/// What happens when an output file already exists. Applies to the .snc file, a decrypted file and every file unpacked from an archive,
+/// directories are merged and only the files in them conflict
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum ConflictPolicy {
+  /// Fail with ShinError::OutputExists before anything is written, the caller asks and runs the job again with another policy
+  #[default]
+  Ask = 0,
+  /// Keep the existing file, a skipped job fails with ShinError::Skipped
+  Skip = 1,
+  Overwrite = 2,
+  /// Write "name (1).ext", "name (2).ext" and so on instead
+  Rename = 3,
+}
+
+impl ConflictPolicy {
+  pub fn from_u8(num: u8) -> Option<Self> {
+    match num {
+      0 => Some(ConflictPolicy::Ask),
+      1 => Some(ConflictPolicy::Skip),
+      2 => Some(ConflictPolicy::Overwrite),
+      3 => Some(ConflictPolicy::Rename),
+      _ => None,
+    }
+  }
+
+  /// Where the output for `path` goes, None when it is skipped. `taken` tells whether a path is already in use
+  pub(crate) fn resolve(&self, path: &std::path::Path, taken: impl Fn(&std::path::Path) -> bool) -> std::io::Result<Option<std::path::PathBuf>> {
+    if !taken(path) {
+      return Ok(Some(path.to_path_buf()));
+    }
+
+    match self {
+      Self::Ask => Err(std::io::Error::new(std::io::ErrorKind::AlreadyExists, format!("{:?} already exists", path))),
+      Self::Skip => Ok(None),
+      Self::Overwrite => Ok(Some(path.to_path_buf())),
+      Self::Rename => (1..=u16::MAX).map(|n| numbered(path, n)).find(|v| !taken(v)).map(Some).ok_or_else(|| std::io::Error::new(std::io::ErrorKind::AlreadyExists, format!("No free name for {:?}", path))),
+    }
+  }
+}
+
+/// Whether anything, even a broken link, is at `path`
+pub(crate) fn exists(path: &std::path::Path) -> bool { std::fs::symlink_metadata(path).is_ok() }
+
+// "report.pdf" -> "report (2).pdf"
+fn numbered(path: &std::path::Path, n: u16) -> std::path::PathBuf {
+  let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+  match path.extension() {
+    Some(v) => path.with_file_name(format!("{} ({}).{}", stem, n, v.to_string_lossy())),
+    None => path.with_file_name(format!("{} ({})", stem, n)),
+  }
+}
+
+/// An output written under a temporary name next to where it belongs, so a failed job never leaves something that looks finished.
+/// The output goes to path() inside a hidden `.shincrypt-<random>.part` directory, commit() moves it into place and anything else removes it
+pub(crate) struct TempOutput {
+  dir: std::path::PathBuf,
+  path: std::path::PathBuf,
+  target: std::path::PathBuf,
+  lock: Option<std::fs::File>,
+}
+
+impl TempOutput {
+  /// Starts an output for `target`, its directory has to exist
+  pub(crate) fn new(target: impl AsRef<std::path::Path>) -> std::io::Result<Self> {
+    let target = target.as_ref().to_path_buf();
+    let parent = target.parent().filter(|v| !v.as_os_str().is_empty()).unwrap_or(std::path::Path::new("."));
+    let name = target.file_name().ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Output path has no file name"))?;
+
+    let mut bytes = [0u8; 8];
+    getrandom::fill(&mut bytes).map_err(|e| std::io::Error::other(e.to_string()))?;
+    let dir = parent.join(format!("{}{}.{}", TEMP_PREFIX, bytes.iter().map(|v| format!("{:02x}", v)).collect::<String>(), TEMP_EXT));
+    std::fs::create_dir(&dir)?;
+
+    let mut output = Self { path: dir.join(name), dir, target, lock: None };
+    let lock = std::fs::File::create(output.dir.join(LOCK_NAME))?;
+    lock.lock()?;
+    output.lock = Some(lock);
+
+    Ok(output)
+  }
+
+  /// Where to write, a file or a directory with the name of the target
+  pub(crate) fn path(&self) -> &std::path::Path { &self.path }
+
+  /// Flushes the output to disk and renames it into place. An existing directory target gets the new entries merged in
+  pub(crate) fn commit(mut self) -> std::io::Result<()> {
+    sync_tree(&self.path)?;
+
+    if self.target.is_dir() && self.path.is_dir() {
+      merge_into(&self.path, &self.target)?;
+    } else {
+      std::fs::rename(&self.path, &self.target)?;
+    }
+    sync_dir(self.target.parent().unwrap_or(std::path::Path::new(".")));
+
+    self.remove();
+    Ok(())
+  }
+
+  fn remove(&mut self) {
+    // Windows can't remove a file that is still open
+    drop(self.lock.take());
+    let _ = std::fs::remove_dir_all(&self.dir);
+  }
+
+  /// Removes what crashed or killed jobs left in `dir`. Outputs of jobs that are still running keep their lock and are left alone
+  pub(crate) fn clean_stale(dir: impl AsRef<std::path::Path>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+      return;
+    };
+
+    for entry in entries.flatten() {
+      let path = entry.path();
+      let name = entry.file_name();
+      let name = name.to_string_lossy();
+      if !name.starts_with(TEMP_PREFIX) || path.extension().is_none_or(|v| v != TEMP_EXT) || !path.is_dir() {
+        continue;
+      }
+
+      // A missing lock means the job died before it took one
+      let stale = match std::fs::OpenOptions::new().write(true).open(path.join(LOCK_NAME)) {
+        Ok(v) => v.try_lock().is_ok(),
+        Err(e) => e.kind() == std::io::ErrorKind::NotFound,
+      };
+      if stale {
+        let _ = std::fs::remove_dir_all(&path);
+      }
+    }
+  }
+}
+
+impl Drop for TempOutput {
+  fn drop(&mut self) {
+    if self.lock.is_some() {
+      self.remove();
+    }
+  }
+}
+
+// fsync every file below `path`, so a rename never points at data that is still only in the page cache
+fn sync_tree(path: &std::path::Path) -> std::io::Result<()> {
+  let metadata = std::fs::symlink_metadata(path)?;
+  if metadata.is_dir() {
+    for entry in std::fs::read_dir(path)? {
+      sync_tree(&entry?.path())?;
+    }
+    sync_dir(path);
+  } else if metadata.is_file() {
+    // Windows only flushes handles opened for writing, read-only files from an archive can still be flushed for reading elsewhere
+    std::fs::OpenOptions::new().write(true).open(path).or_else(|_| std::fs::File::open(path))?.sync_all()?;
+  }
+
+  Ok(())
+}
+
+// Makes created and renamed entries durable. Not possible on every platform, Windows can't open a directory as a file
+fn sync_dir(path: &std::path::Path) {
+  if let Ok(v) = std::fs::File::open(path) {
+    let _ = v.sync_all();
+  }
+}
+
+// Moves the entries of `src` into the existing directory `dst`, existing files are replaced
+fn merge_into(src: &std::path::Path, dst: &std::path::Path) -> std::io::Result<()> {
+  for entry in std::fs::read_dir(src)? {
+    let entry = entry?;
+    let to = dst.join(entry.file_name());
+    if entry.file_type()?.is_dir() && to.is_dir() {
+      merge_into(&entry.path(), &to)?;
+    } else {
+      std::fs::rename(entry.path(), &to)?;
+    }
+  }
+  sync_dir(dst);
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::logic::test_util::TestDir;
+  use std::path::{Path, PathBuf};
+
+  fn taken(paths: &[&str]) -> impl Fn(&Path) -> bool { move |path| paths.iter().any(|v| Path::new(v) == path) }
+
+  #[test]
+  fn free_path_is_used_by_every_policy() {
+    for policy in [ConflictPolicy::Ask, ConflictPolicy::Skip, ConflictPolicy::Overwrite, ConflictPolicy::Rename] {
+      assert_eq!(policy.resolve(Path::new("out/a.txt"), taken(&["out/b.txt"])).unwrap(), Some(PathBuf::from("out/a.txt")), "{:?}", policy);
+    }
+  }
+
+  #[test]
+  fn ask_fails_with_already_exists() {
+    let e = ConflictPolicy::Ask.resolve(Path::new("out/a.txt"), taken(&["out/a.txt"])).unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::AlreadyExists);
+  }
+
+  #[test]
+  fn skip_resolves_to_nothing() { assert_eq!(ConflictPolicy::Skip.resolve(Path::new("out/a.txt"), taken(&["out/a.txt"])).unwrap(), None); }
+
+  #[test]
+  fn overwrite_keeps_the_path() { assert_eq!(ConflictPolicy::Overwrite.resolve(Path::new("out/a.txt"), taken(&["out/a.txt"])).unwrap(), Some(PathBuf::from("out/a.txt"))); }
+
+  #[test]
+  fn rename_takes_the_first_free_number() {
+    let resolve = |path: &str, used: &[&str]| ConflictPolicy::Rename.resolve(Path::new(path), taken(used)).unwrap().unwrap();
+    assert_eq!(resolve("out/report.pdf", &["out/report.pdf"]), PathBuf::from("out/report (1).pdf"));
+    assert_eq!(resolve("out/report.pdf", &["out/report.pdf", "out/report (1).pdf", "out/report (2).pdf"]), PathBuf::from("out/report (3).pdf"));
+    assert_eq!(resolve("out/a.txt.snc", &["out/a.txt.snc"]), PathBuf::from("out/a.txt (1).snc"));
+    assert_eq!(resolve("out/folder", &["out/folder"]), PathBuf::from("out/folder (1)"));
+
+    let e = ConflictPolicy::Rename.resolve(Path::new("a"), |_| true).unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::AlreadyExists);
+  }
+
+  #[test]
+  fn from_u8_matches_discriminants() {
+    for policy in [ConflictPolicy::Ask, ConflictPolicy::Skip, ConflictPolicy::Overwrite, ConflictPolicy::Rename] {
+      assert_eq!(ConflictPolicy::from_u8(policy as u8), Some(policy));
+    }
+    assert_eq!(ConflictPolicy::from_u8(4), None);
+  }
+
+  #[test]
+  fn temp_output_commits_a_file() {
+    let dir = TestDir::new();
+    let target = dir.write("out.txt", b"old");
+
+    let output = TempOutput::new(&target).unwrap();
+    assert!(output.path().starts_with(dir.path()) && output.path() != target);
+    std::fs::write(output.path(), b"new").unwrap();
+    assert_eq!(std::fs::read(&target).unwrap(), b"old");
+
+    output.commit().unwrap();
+    assert_eq!(std::fs::read(&target).unwrap(), b"new");
+    assert_eq!(dir.entries(""), ["out.txt"]);
+  }
+
+  #[test]
+  fn temp_output_merges_into_a_directory() {
+    let dir = TestDir::new();
+    let target = dir.subdir("folder");
+    dir.subdir("folder/sub");
+    dir.write("folder/kept.txt", b"kept");
+    dir.write("folder/sub/a.txt", b"old");
+
+    let output = TempOutput::new(&target).unwrap();
+    std::fs::create_dir_all(output.path().join("sub")).unwrap();
+    std::fs::write(output.path().join("sub/a.txt"), b"new").unwrap();
+    std::fs::write(output.path().join("b.txt"), b"b").unwrap();
+    output.commit().unwrap();
+
+    assert_eq!(std::fs::read(target.join("kept.txt")).unwrap(), b"kept");
+    assert_eq!(std::fs::read(target.join("sub/a.txt")).unwrap(), b"new");
+    assert_eq!(std::fs::read(target.join("b.txt")).unwrap(), b"b");
+    assert_eq!(dir.entries(""), ["folder"]);
+  }
+
+  #[test]
+  fn dropped_temp_output_leaves_nothing() {
+    let dir = TestDir::new();
+    let output = TempOutput::new(dir.join("out.txt")).unwrap();
+    std::fs::write(output.path(), b"half").unwrap();
+    drop(output);
+    assert!(dir.entries("").is_empty());
+  }
+
+  #[test]
+  fn clean_stale_keeps_running_jobs() {
+    let dir = TestDir::new();
+    let running = TempOutput::new(dir.join("running.txt")).unwrap();
+    std::fs::write(running.path(), b"busy").unwrap();
+
+    // One left by a killed job with its lock released, one that died before taking it and an unrelated directory
+    let killed = dir.subdir(&format!("{}killed.{}", TEMP_PREFIX, TEMP_EXT));
+    std::fs::write(killed.join(LOCK_NAME), b"").unwrap();
+    dir.subdir(&format!("{}early.{}", TEMP_PREFIX, TEMP_EXT));
+    dir.subdir(&format!("{}other", TEMP_PREFIX));
+
+    TempOutput::clean_stale(dir.path());
+    let entries = dir.entries("");
+    assert_eq!(entries.len(), 2);
+    assert!(entries.contains(&format!("{}other", TEMP_PREFIX)));
+    assert!(running.path().exists());
+    running.commit().unwrap();
+  }
+}
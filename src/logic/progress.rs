@@ -0,0 +1,151 @@
+static SEND_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100); // Chunks can come in far faster than a GUI redraws
+
+/// What a running job is doing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ProgressPhase {
+  KeyDerivation,
+  Scanning,
+  Packing,
+  Encrypting,
+  Decrypting,
+  Verifying,
+}
+
+impl ProgressPhase {
+  pub fn label(&self) -> &'static str {
+    match self {
+      Self::KeyDerivation => "Deriving key",
+      Self::Scanning => "Scanning",
+      Self::Packing => "Packing",
+      Self::Encrypting => "Encrypting",
+      Self::Decrypting => "Decrypting",
+      Self::Verifying => "Verifying",
+    }
+  }
+}
+
+/// Sent on the progress channel of a job
+#[derive(Debug, Clone)]
+pub struct ProgressEvent {
+  pub phase: ProgressPhase,
+  pub done: u64,                        // Bytes
+  pub total: Option<u64>,               // Bytes, None while it isn't known
+  pub file: Option<std::path::PathBuf>, // Entry being packed or unpacked
+  pub speed: f64,                       // Bytes per second since the phase started
+  pub eta: Option<std::time::Duration>,
+}
+
+impl ProgressEvent {
+  /// Done as a fraction (0.0 to 1.0), None while the total isn't known
+  pub fn fraction(&self) -> Option<f64> { self.total.filter(|v| *v > 0).map(|v| (self.done as f64 / v as f64).min(1.0)) }
+}
+
+// Phase, speed and remaining time of a running job, e.g. "Encrypting 45% · 120.50 MB/s · 00:00:12 left"
+impl std::fmt::Display for ProgressEvent {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.write_str(self.phase.label())?;
+    if let Some(v) = self.fraction() {
+      write!(f, " {:.0}%", v * 100.0)?;
+    }
+    if self.done > 0 {
+      write!(f, " · {}/s", crate::logic::global::Global::format_size(self.speed as u64))?;
+    }
+    if let Some(v) = self.eta {
+      let secs = v.as_secs();
+      write!(f, " · {:02}:{:02}:{:02} left", secs / 3600, secs % 3600 / 60, secs % 60)?;
+    }
+    if let Some(v) = self.file.as_ref().and_then(|v| v.file_name()) {
+      write!(f, " · {}", v.to_string_lossy())?;
+    }
+    Ok(())
+  }
+}
+
+// Counters of a job, turned into events for the channel. Clones share them, so the tar loop can name the file the stream is working on
+#[derive(Clone)]
+pub(crate) struct Progress {
+  sender: Option<crossbeam::channel::Sender<ProgressEvent>>,
+  state: std::sync::Arc<parking_lot::Mutex<ProgressState>>,
+}
+
+struct ProgressState {
+  phase: ProgressPhase,
+  done: u64,
+  total: Option<u64>,
+  file: Option<std::path::PathBuf>,
+  start: std::time::Instant,
+  last_sent: Option<std::time::Instant>,
+}
+
+impl Progress {
+  pub(crate) fn new(sender: Option<crossbeam::channel::Sender<ProgressEvent>>) -> Self {
+    let state = ProgressState { phase: ProgressPhase::Scanning, done: 0, total: None, file: None, start: std::time::Instant::now(), last_sent: None };
+    Self { sender, state: std::sync::Arc::new(parking_lot::Mutex::new(state)) }
+  }
+
+  // Starts counting from zero, a new phase is always sent
+  pub(crate) fn start(&self, phase: ProgressPhase, total: Option<u64>) {
+    {
+      let mut state = self.state.lock();
+      *state = ProgressState { phase, done: 0, total, file: None, start: std::time::Instant::now(), last_sent: None };
+    }
+    self.send();
+  }
+
+  pub(crate) fn add(&self, bytes: u64) { self.state.lock().done += bytes; }
+
+  pub(crate) fn set_file(&self, file: impl AsRef<std::path::Path>) { self.state.lock().file = Some(file.as_ref().to_path_buf()); }
+
+  pub(crate) fn send(&self) {
+    let Some(sender) = &self.sender else {
+      return;
+    };
+
+    // The event that completes the phase always goes out
+    let mut state = self.state.lock();
+    let complete = state.total.is_some_and(|v| state.done >= v);
+    if !complete && state.last_sent.is_some_and(|v| v.elapsed() < SEND_INTERVAL) {
+      return;
+    }
+    state.last_sent = Some(std::time::Instant::now());
+
+    let elapsed = state.start.elapsed().as_secs_f64();
+    let speed = if elapsed > 0.0 { state.done as f64 / elapsed } else { 0.0 };
+    let eta = state.total.filter(|_| speed > 0.0).map(|v| std::time::Duration::from_secs_f64(v.saturating_sub(state.done) as f64 / speed));
+
+    let _ = sender.send(ProgressEvent { phase: state.phase, done: state.done, total: state.total, file: state.file.clone(), speed, eta }); // Ignore errors if receiver is dropped
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn events_are_throttled_except_the_last() {
+    let (sender, receiver) = crossbeam::channel::unbounded();
+    let progress = Progress::new(Some(sender));
+    progress.start(ProgressPhase::Encrypting, Some(100));
+    for _ in 0..9 {
+      progress.add(10);
+      progress.send();
+    }
+    progress.add(10);
+    progress.send();
+
+    // The start of the phase and its completion always go out, the chunks in between came too fast
+    let events = receiver.try_iter().collect::<Vec<_>>();
+    assert_eq!(events.iter().map(|v| v.done).collect::<Vec<_>>(), [0, 100]);
+    assert!(events.iter().all(|v| v.phase == ProgressPhase::Encrypting));
+    assert_eq!(events[1].fraction(), Some(1.0));
+  }
+
+  #[test]
+  fn fraction_needs_a_total() {
+    let event = ProgressEvent { phase: ProgressPhase::Packing, done: 50, total: None, file: None, speed: 0.0, eta: None };
+    assert_eq!(event.fraction(), None);
+    assert_eq!(ProgressEvent { total: Some(0), ..event.clone() }.fraction(), None);
+    assert_eq!(ProgressEvent { total: Some(200), ..event.clone() }.fraction(), Some(0.25));
+    assert_eq!(ProgressEvent { total: Some(10), ..event }.fraction(), Some(1.0));
+  }
+}
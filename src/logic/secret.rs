@@ -0,0 +1,289 @@
+use zeroize::Zeroize;
+
+/// A password, key or other secret on the heap, wiped when it is dropped. On Linux its pages are locked in memory so it
+/// never gets swapped out, that is best effort and silently skipped when RLIMIT_MEMLOCK doesn't allow it
+pub struct Secret<T: Zeroize + AsRef<[u8]>> {
+  value: Box<T>,
+  locked: bool,
+}
+
+/// A 256 bit key
+pub type SecretKey = Secret<[u8; 32]>;
+
+impl<T: Zeroize + AsRef<[u8]>> Secret<T> {
+  pub fn new(value: T) -> Self {
+    let value = Box::new(value);
+    let locked = lock((*value).as_ref());
+    Self { value, locked }
+  }
+}
+
+impl<const N: usize> Secret<[u8; N]> {
+  /// All zeros, to be filled in place so the secret never passes through the stack
+  pub fn zeroed() -> Self { Self::new([0u8; N]) }
+
+  pub fn random() -> Result<Self, String> {
+    let mut secret = Self::zeroed();
+    getrandom::fill(&mut *secret).map_err(|e| format!("Failed to generate key: {}", e))?;
+    Ok(secret)
+  }
+
+  pub fn from_slice(bytes: &[u8]) -> Option<Self> {
+    let mut secret = Self::zeroed();
+    secret.get_mut(..bytes.len())?.copy_from_slice(bytes);
+    (bytes.len() == N).then_some(secret)
+  }
+}
+
+impl Secret<String> {
+  pub fn as_str(&self) -> &str { self.value.as_str() }
+}
+
+// Copied straight into the new allocation, a copy on the stack wouldn't get wiped
+impl<const N: usize> From<&[u8; N]> for Secret<[u8; N]> {
+  fn from(value: &[u8; N]) -> Self {
+    let mut secret = Self::zeroed();
+    secret.copy_from_slice(value);
+    secret
+  }
+}
+
+impl From<String> for Secret<String> {
+  fn from(value: String) -> Self { Self::new(value) }
+}
+
+impl From<&str> for Secret<String> {
+  fn from(value: &str) -> Self { Self::new(value.to_string()) }
+}
+
+impl<T: Zeroize + AsRef<[u8]>> std::ops::Deref for Secret<T> {
+  type Target = T;
+
+  fn deref(&self) -> &T { &self.value }
+}
+
+// Only fixed size secrets can be changed in place, a growing String would move out of the locked pages
+impl<const N: usize> std::ops::DerefMut for Secret<[u8; N]> {
+  fn deref_mut(&mut self) -> &mut [u8; N] { &mut self.value }
+}
+
+impl<const N: usize> Clone for Secret<[u8; N]> {
+  fn clone(&self) -> Self { Self::from(&*self.value) }
+}
+
+impl Clone for Secret<String> {
+  fn clone(&self) -> Self { Self::new((*self.value).clone()) }
+}
+
+impl<T: Zeroize + AsRef<[u8]>> std::fmt::Debug for Secret<T> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result { f.write_str("Secret(..)") }
+}
+
+impl<T: Zeroize + AsRef<[u8]>> Drop for Secret<T> {
+  fn drop(&mut self) {
+    // Zeroizing a String empties it, the locked range has to be taken before
+    let (ptr, len) = ((*self.value).as_ref().as_ptr() as usize, (*self.value).as_ref().len());
+    self.value.zeroize();
+    if self.locked {
+      unlock(ptr, len);
+    }
+  }
+}
+
+/// Keeps core dumps off while it lives, a dump of a running job would contain its keys. Guards can overlap,
+/// the settings from before the first one come back when the last one is dropped. Does nothing outside Linux
+pub struct NoCoreDumps(());
+
+impl NoCoreDumps {
+  pub fn new() -> Self {
+    #[cfg(target_os = "linux")]
+    {
+      let mut state = CORE_DUMPS.lock();
+      if state.0 == 0 {
+        state.1 = linux::disable_core_dumps();
+      }
+      state.0 += 1;
+    }
+    Self(())
+  }
+}
+
+impl Default for NoCoreDumps {
+  fn default() -> Self { Self::new() }
+}
+
+impl Drop for NoCoreDumps {
+  fn drop(&mut self) {
+    #[cfg(target_os = "linux")]
+    {
+      let mut state = CORE_DUMPS.lock();
+      state.0 -= 1;
+      if state.0 == 0
+        && let Some(saved) = state.1.take()
+      {
+        linux::restore_core_dumps(saved);
+      }
+    }
+  }
+}
+
+// Guards alive and the settings they replaced
+#[cfg(target_os = "linux")]
+static CORE_DUMPS: parking_lot::Mutex<(usize, Option<linux::CoreDumpSettings>)> = parking_lot::Mutex::new((0, None));
+
+#[cfg(target_os = "linux")]
+fn lock(bytes: &[u8]) -> bool { linux::lock(bytes.as_ptr() as usize, bytes.len()) }
+
+#[cfg(target_os = "linux")]
+fn unlock(ptr: usize, len: usize) { linux::unlock(ptr, len) }
+
+#[cfg(not(target_os = "linux"))]
+fn lock(_bytes: &[u8]) -> bool { false }
+
+#[cfg(not(target_os = "linux"))]
+fn unlock(_ptr: usize, _len: usize) {}
+
+#[cfg(target_os = "linux")]
+mod linux {
+  // Locks don't nest, unlocking a page shared with another secret would unlock that one too. Counts how many secrets hold every page
+  static PAGES: parking_lot::Mutex<std::collections::BTreeMap<usize, usize>> = parking_lot::Mutex::new(std::collections::BTreeMap::new());
+
+  pub struct CoreDumpSettings {
+    dumpable: libc::c_int,
+    limit: libc::rlimit,
+  }
+
+  fn page_size() -> usize {
+    static PAGE_SIZE: std::sync::OnceLock<usize> = std::sync::OnceLock::new();
+    *PAGE_SIZE.get_or_init(|| match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+      v if v > 0 => v as usize,
+      _ => 4096,
+    })
+  }
+
+  fn pages(ptr: usize, len: usize) -> impl Iterator<Item = usize> {
+    let size = page_size();
+    (ptr / size * size..ptr + len).step_by(size)
+  }
+
+  pub fn lock(ptr: usize, len: usize) -> bool {
+    if len == 0 {
+      return false;
+    }
+
+    let mut locked = PAGES.lock();
+    let new = pages(ptr, len).filter(|v| !locked.contains_key(v)).collect::<Vec<_>>();
+    for (i, page) in new.iter().enumerate() {
+      if unsafe { libc::mlock(*page as *const libc::c_void, page_size()) } != 0 {
+        for page in &new[..i] {
+          unsafe { libc::munlock(*page as *const libc::c_void, page_size()) };
+        }
+        return false;
+      }
+    }
+
+    for page in pages(ptr, len) {
+      *locked.entry(page).or_insert(0) += 1;
+    }
+    true
+  }
+
+  pub fn unlock(ptr: usize, len: usize) {
+    let mut locked = PAGES.lock();
+    for page in pages(ptr, len) {
+      if let Some(count) = locked.get_mut(&page) {
+        *count -= 1;
+        if *count == 0 {
+          locked.remove(&page);
+          unsafe { libc::munlock(page as *const libc::c_void, page_size()) };
+        }
+      }
+    }
+  }
+
+  // A process that isn't dumpable writes no core file whatever core_pattern says, the limit covers kernels that still do
+  pub fn disable_core_dumps() -> Option<CoreDumpSettings> {
+    let dumpable = unsafe { libc::prctl(libc::PR_GET_DUMPABLE) };
+    let mut limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+    if dumpable < 0 || unsafe { libc::getrlimit(libc::RLIMIT_CORE, &mut limit) } != 0 {
+      return None;
+    }
+
+    unsafe {
+      libc::prctl(libc::PR_SET_DUMPABLE, 0);
+      libc::setrlimit(libc::RLIMIT_CORE, &libc::rlimit { rlim_cur: 0, rlim_max: limit.rlim_max });
+    }
+    Some(CoreDumpSettings { dumpable, limit })
+  }
+
+  pub fn restore_core_dumps(saved: CoreDumpSettings) {
+    unsafe {
+      libc::setrlimit(libc::RLIMIT_CORE, &saved.limit);
+      libc::prctl(libc::PR_SET_DUMPABLE, saved.dumpable);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn from_slice_needs_the_exact_length() {
+    let bytes = (0..32).collect::<Vec<u8>>();
+    assert_eq!(SecretKey::from_slice(&bytes).unwrap().as_slice(), bytes.as_slice());
+    assert!(SecretKey::from_slice(&bytes[..31]).is_none());
+    assert!(SecretKey::from_slice(&[0u8; 33]).is_none());
+    assert!(SecretKey::from_slice(&[]).is_none());
+  }
+
+  #[test]
+  fn zeroed_and_random() {
+    assert_eq!(*SecretKey::zeroed(), [0u8; 32]);
+    let (a, b) = (SecretKey::random().unwrap(), SecretKey::random().unwrap());
+    assert_ne!(*a, [0u8; 32]);
+    assert_ne!(*a, *b);
+  }
+
+  #[test]
+  fn clones_are_separate_copies() {
+    let mut key = SecretKey::from(&[1u8; 32]);
+    let copy = key.clone();
+    key[0] = 2;
+    assert_eq!(copy[0], 1);
+    assert_ne!(key.value.as_ptr(), copy.value.as_ptr());
+
+    let password = Secret::from("correct horse");
+    assert_eq!(password.clone().as_str(), "correct horse");
+    assert_eq!(Secret::from(String::from("correct horse")).as_str(), password.as_str());
+  }
+
+  #[test]
+  fn debug_hides_the_value() {
+    assert_eq!(format!("{:?}", Secret::from("hunter2")), "Secret(..)");
+    assert_eq!(format!("{:?}", SecretKey::from(&[0x41; 32])), "Secret(..)");
+  }
+
+  // Secrets share pages, dropping one in any order must leave the others intact
+  #[test]
+  fn secrets_sharing_pages() {
+    let mut secrets = (0..64u8).map(|v| SecretKey::from(&[v; 32])).collect::<Vec<_>>();
+    let mut i = 0;
+    while !secrets.is_empty() {
+      i = (i + 7) % secrets.len();
+      secrets.remove(i);
+      assert!(secrets.iter().all(|v| v.iter().all(|b| *b == v[0])));
+    }
+  }
+
+  #[cfg(target_os = "linux")]
+  #[test]
+  fn no_core_dumps_while_a_guard_lives() {
+    let first = NoCoreDumps::new();
+    let second = NoCoreDumps::new();
+    assert_eq!(unsafe { libc::prctl(libc::PR_GET_DUMPABLE) }, 0);
+    drop(first);
+    assert_eq!(unsafe { libc::prctl(libc::PR_GET_DUMPABLE) }, 0);
+    drop(second);
+  }
+}
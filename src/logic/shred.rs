@@ -0,0 +1,239 @@
+use serde::{Deserialize, Serialize};
+use std::io::{Seek, Write};
+
+static BLOCK_SIZE: usize = crate::SIZE_1MB;
+static RENAMES: usize = 3; // The name is overwritten in the directory entry too
+// Filesystems that write changes to new blocks, the old contents stay where they were (statfs magic doesn't need libc this way)
+static COW_FILESYSTEMS: [&str; 5] = ["btrfs", "zfs", "bcachefs", "f2fs", "nilfs2"];
+
+/// What the contents of a shredded file get overwritten with
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum ShredPattern {
+  #[default]
+  Random = 0,
+  Zeros = 1,
+}
+
+impl ShredPattern {
+  pub fn from_u8(num: u8) -> Option<Self> {
+    match num {
+      0 => Some(ShredPattern::Random),
+      1 => Some(ShredPattern::Zeros),
+      _ => None,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ShredParams {
+  pub passes: u32,
+  pub pattern: ShredPattern,
+}
+
+impl Default for ShredParams {
+  // One random pass is as good as many on anything built this century
+  fn default() -> Self { Self { passes: 1, pattern: ShredPattern::Random } }
+}
+
+/// Overwrites a file, or every file in a directory, flushes it to disk, renames it a few times and removes it. Links are removed, never followed
+pub fn shred_path(path: impl AsRef<std::path::Path>, params: ShredParams) -> std::io::Result<()> {
+  let path = path.as_ref();
+  let metadata = std::fs::symlink_metadata(path)?;
+  if metadata.is_dir() {
+    for entry in std::fs::read_dir(path)? {
+      shred_path(entry?.path(), params)?;
+    }
+    let path = scramble_name(path)?;
+    std::fs::remove_dir(path)
+  } else if metadata.is_file() {
+    overwrite(path, metadata.len(), params)?;
+    let path = scramble_name(path)?;
+    std::fs::remove_file(path)
+  } else {
+    std::fs::remove_file(path)
+  }
+}
+
+fn overwrite(path: &std::path::Path, len: u64, params: ShredParams) -> std::io::Result<()> {
+  let mut file = std::fs::OpenOptions::new().write(true).open(path)?;
+  let mut block = vec![0u8; BLOCK_SIZE];
+
+  for _ in 0..params.passes.max(1) {
+    file.seek(std::io::SeekFrom::Start(0))?;
+    let mut left = len;
+    while left > 0 {
+      let n = left.min(BLOCK_SIZE as u64) as usize;
+      if params.pattern == ShredPattern::Random {
+        getrandom::fill(&mut block[..n]).map_err(|e| std::io::Error::other(e.to_string()))?;
+      }
+      file.write_all(&block[..n])?;
+      left -= n as u64;
+    }
+    // Every pass has to reach the disk, otherwise the page cache only keeps the last one
+    file.sync_all()?;
+  }
+
+  // The size is gone from the inode too
+  file.set_len(0)?;
+  file.sync_all()
+}
+
+// Renames to random names of the same length, returns where it ended up
+fn scramble_name(path: &std::path::Path) -> std::io::Result<std::path::PathBuf> {
+  let len = path.file_name().map(|v| v.len()).unwrap_or(1).clamp(1, 64);
+  let mut path = path.to_path_buf();
+  for _ in 0..RENAMES {
+    let mut bytes = vec![0u8; len];
+    getrandom::fill(&mut bytes).map_err(|e| std::io::Error::other(e.to_string()))?;
+    let name: String = bytes.iter().map(|v| (b'a' + v % 26) as char).collect();
+
+    let new_path = path.with_file_name(name);
+    if std::fs::symlink_metadata(&new_path).is_ok() {
+      continue;
+    }
+    std::fs::rename(&path, &new_path)?;
+    path = new_path;
+  }
+
+  Ok(path)
+}
+
+/// Why overwriting `path` may not reach the data on the disk, None when nothing speaks against it.
+/// Only Linux can be checked, elsewhere the answer is always that it can't be guaranteed
+pub fn shred_warning(path: impl AsRef<std::path::Path>) -> Option<String> {
+  #[cfg(target_os = "linux")]
+  {
+    let path = std::fs::canonicalize(path).ok()?;
+    if let Some(fs) = filesystem(&path)
+      && COW_FILESYSTEMS.contains(&fs.as_str())
+    {
+      return Some(format!("{} is a copy-on-write filesystem, overwriting writes new blocks and the old contents may still be recoverable", fs));
+    }
+    if on_ssd(&path) {
+      return Some("The file is on an SSD, wear levelling keeps old copies of the data that overwriting can't reach. Full disk encryption is the only reliable protection".to_string());
+    }
+    None
+  }
+
+  #[cfg(not(target_os = "linux"))]
+  {
+    let _ = path;
+    Some("ShinCrypt can't check whether this drive is an SSD or uses a copy-on-write filesystem, on those the old contents may still be recoverable".to_string())
+  }
+}
+
+// Type of the filesystem holding `path`, from the longest mount point it is below
+#[cfg(target_os = "linux")]
+fn filesystem(path: &std::path::Path) -> Option<String> {
+  let mounts = std::fs::read_to_string("/proc/self/mounts").ok()?;
+  mounts
+    .lines()
+    .filter_map(|line| {
+      let mut fields = line.split_whitespace();
+      let (_, mount, fs) = (fields.next()?, fields.next()?, fields.next()?);
+      // Spaces in mount points are escaped as \040
+      let mount = std::path::PathBuf::from(mount.replace("\\040", " "));
+      path.starts_with(&mount).then(|| (mount.as_os_str().len(), fs.to_string()))
+    })
+    .max_by_key(|v| v.0)
+    .map(|v| v.1)
+}
+
+// Whether the block device holding `path` doesn't rotate. Partitions have the queue of their disk one level up
+#[cfg(target_os = "linux")]
+fn on_ssd(path: &std::path::Path) -> bool {
+  use std::os::unix::fs::MetadataExt;
+  let Ok(metadata) = std::fs::metadata(path) else {
+    return false;
+  };
+  let dev = metadata.dev();
+  let (major, minor) = (((dev >> 32) & 0xffff_f000) | ((dev >> 8) & 0xfff), ((dev >> 12) & 0xffff_ff00) | (dev & 0xff));
+
+  let device = std::path::PathBuf::from(format!("/sys/dev/block/{}:{}", major, minor));
+  [device.join("queue/rotational"), device.join("../queue/rotational")].iter().find_map(|v| std::fs::read_to_string(v).ok()).is_some_and(|v| v.trim() == "0")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::logic::test_util::TestDir;
+
+  #[test]
+  fn pattern_from_u8() {
+    for pattern in [ShredPattern::Random, ShredPattern::Zeros] {
+      assert_eq!(ShredPattern::from_u8(pattern as u8), Some(pattern));
+    }
+    assert_eq!(ShredPattern::from_u8(2), None);
+  }
+
+  #[test]
+  fn shreds_a_file() {
+    for params in [ShredParams::default(), ShredParams { passes: 3, pattern: ShredPattern::Zeros }, ShredParams { passes: 0, pattern: ShredPattern::Random }] {
+      let dir = TestDir::new();
+      let path = dir.write("secret.txt", &vec![b'x'; BLOCK_SIZE + 10]);
+
+      // A second link to the same inode shows what happened to the contents
+      let link = dir.join("link");
+      std::fs::hard_link(&path, &link).unwrap();
+
+      shred_path(&path, params).unwrap();
+      assert!(!path.exists(), "{:?}", params);
+      assert_eq!(std::fs::metadata(&link).unwrap().len(), 0, "{:?}", params);
+      assert_eq!(dir.entries("").len(), 1, "{:?}", params);
+    }
+  }
+
+  #[test]
+  fn shreds_a_directory_tree() {
+    let dir = TestDir::new();
+    let root = dir.subdir("folder");
+    dir.subdir("folder/a/b");
+    dir.subdir("folder/empty");
+    dir.write("folder/top.txt", b"top");
+    dir.write("folder/a/b/deep.txt", b"deep");
+    dir.write("folder/a/empty.txt", b"");
+
+    shred_path(&root, ShredParams::default()).unwrap();
+    assert_eq!(dir.entries("").len(), 0);
+    assert!(shred_path(&root, ShredParams::default()).is_err());
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn links_are_removed_not_followed() {
+    let dir = TestDir::new();
+    let target = dir.write("target.txt", b"keep me");
+    dir.subdir("target_dir");
+    dir.write("target_dir/inner.txt", b"keep me too");
+
+    let folder = dir.subdir("folder");
+    std::os::unix::fs::symlink(&target, folder.join("file_link")).unwrap();
+    std::os::unix::fs::symlink(dir.join("target_dir"), folder.join("dir_link")).unwrap();
+    std::os::unix::fs::symlink(&target, dir.join("link")).unwrap();
+
+    shred_path(dir.join("link"), ShredParams::default()).unwrap();
+    shred_path(&folder, ShredParams::default()).unwrap();
+    assert_eq!(std::fs::read(&target).unwrap(), b"keep me");
+    assert_eq!(std::fs::read(dir.join("target_dir/inner.txt")).unwrap(), b"keep me too");
+    assert_eq!(dir.entries("").len(), 2);
+  }
+
+  #[test]
+  fn scrambled_names_keep_length_and_directory() {
+    let dir = TestDir::new();
+    let path = dir.write("report.pdf", b"data");
+
+    let scrambled = scramble_name(&path).unwrap();
+    assert_eq!(scrambled.parent(), Some(dir.path()));
+    assert_eq!(scrambled.file_name().unwrap().len(), "report.pdf".len());
+    assert_ne!(scrambled, path);
+    assert_eq!(std::fs::read(&scrambled).unwrap(), b"data");
+  }
+
+  #[cfg(target_os = "linux")]
+  #[test]
+  fn no_warning_for_missing_paths() {
+    assert_eq!(shred_warning("/no/such/path/shincrypt"), None);
+  }
+}
@@ -0,0 +1,44 @@
+// Shared by the tests of the logic modules
+
+/// A directory of its own for a test, removed with everything in it when the test ends
+pub(crate) struct TestDir(std::path::PathBuf);
+
+impl TestDir {
+  pub(crate) fn new() -> Self {
+    let mut bytes = [0u8; 8];
+    getrandom::fill(&mut bytes).unwrap();
+    let dir = std::env::temp_dir().join(format!("shincrypt-test-{}", bytes.iter().map(|v| format!("{:02x}", v)).collect::<String>()));
+    std::fs::create_dir(&dir).unwrap();
+    Self(dir)
+  }
+
+  pub(crate) fn path(&self) -> &std::path::Path { &self.0 }
+
+  pub(crate) fn join(&self, path: impl AsRef<std::path::Path>) -> std::path::PathBuf { self.0.join(path) }
+
+  pub(crate) fn subdir(&self, name: &str) -> std::path::PathBuf {
+    let dir = self.join(name);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+  }
+
+  pub(crate) fn write(&self, name: &str, data: &[u8]) -> std::path::PathBuf {
+    let path = self.join(name);
+    std::fs::write(&path, data).unwrap();
+    path
+  }
+
+  /// Names in `dir` below the test directory, sorted
+  pub(crate) fn entries(&self, dir: impl AsRef<std::path::Path>) -> Vec<String> {
+    let mut entries = std::fs::read_dir(self.join(dir)).unwrap().map(|v| v.unwrap().file_name().to_string_lossy().into_owned()).collect::<Vec<_>>();
+    entries.sort();
+    entries
+  }
+}
+
+impl Drop for TestDir {
+  fn drop(&mut self) { let _ = std::fs::remove_dir_all(&self.0); }
+}
+
+/// Text that compresses, line after line of it
+pub(crate) fn sample(len: usize) -> Vec<u8> { b"ShinCrypt test data, line after line of it\n".iter().copied().cycle().take(len).collect() }
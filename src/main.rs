@@ -1,6 +1,7 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 use crate::{gtk::settings_win::AppSettings, logic::global::Global};
+mod cli;
 mod gtk;
 mod logic;
 
@@ -57,12 +58,18 @@ impl Default for AppConsts {
 
 // const ICON_BYTES: &[u8] = if cfg!(target_os = "windows") { include_bytes!("../resources/icon.ico") } else { include_bytes!("../resources/icon.png") };
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+fn main() -> std::process::ExitCode {
   let old_app = std::env::current_exe().unwrap().parent().unwrap().join(OLDAPPNAME);
   if old_app.exists() {
     Global::del_path(old_app).unwrap()
   }
 
+  // Any argument means the command line, without arguments the GUI starts
+  let args = std::env::args_os().skip(1).collect::<Vec<_>>();
+  if !args.is_empty() {
+    return cli::cli_ui::cli(args);
+  }
+
   gtk::gtk_ui::gtk_ui();
-  Ok(())
+  std::process::ExitCode::SUCCESS
 }